@@ -0,0 +1,132 @@
+//! Sending and receiving over loopback one datagram per system call against batches with
+//! `sendmmsg` and `recvmmsg`.
+//!
+//! ```text
+//! cargo bench --features linux-batch --bench batch
+//! ```
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dsu_protocol::{
+    crc::Crc32,
+    transport::{RecvBatch, Transport},
+    types::*,
+    ControllerData,
+};
+
+/// Four slots to each of this many subscribers, as one server tick.
+const SUBSCRIBERS: [usize; 3] = [1, 4, 16];
+
+fn packet(slot: u8) -> ControllerData {
+    ControllerData::new(
+        1,
+        slot,
+        State::Connected,
+        Model::FullGyro,
+        ConnectionType::Usb,
+        [0, 0, 0, 0, 0, slot],
+        BatteryStatus::Full,
+        true,
+        Crc32::new(),
+    )
+}
+
+fn bind() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_nonblocking(true).unwrap();
+    socket
+}
+
+fn send(c: &mut Criterion) {
+    let packets: Vec<_> = (0..4).map(packet).collect();
+    let mut group = c.benchmark_group("send tick");
+    for subscribers in SUBSCRIBERS {
+        // Nobody reads from these, and loopback drops what doesn't fit in their buffers.
+        let receivers: Vec<_> = (0..subscribers).map(|_| bind()).collect();
+        let addrs: Vec<SocketAddr> = receivers.iter().map(|r| r.local_addr().unwrap()).collect();
+        let datagrams: Vec<(&[u8], &SocketAddr)> = packets
+            .iter()
+            .flat_map(|packet| addrs.iter().map(move |addr| (&packet.bytes[..], addr)))
+            .collect();
+        let mut socket = bind();
+        group.throughput(Throughput::Elements(datagrams.len() as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("send_to", subscribers),
+            &datagrams,
+            |b, datagrams| {
+                b.iter(|| {
+                    for (bytes, to) in datagrams {
+                        socket.send_to(bytes, to).unwrap();
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("sendmmsg", subscribers),
+            &datagrams,
+            |b, datagrams| {
+                b.iter(|| {
+                    let mut rest = &datagrams[..];
+                    while !rest.is_empty() {
+                        rest = &rest[socket.send_batch(rest).unwrap()..];
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Times receiving `count` datagrams that are already waiting.
+fn recv_waiting(
+    count: usize,
+    iters: u64,
+    mut recv: impl FnMut(&mut UdpSocket) -> io::Result<usize>,
+) -> Duration {
+    let sender = bind();
+    let mut receiver = bind();
+    let to = receiver.local_addr().unwrap();
+    let packet = packet(0);
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        for _ in 0..count {
+            sender.send_to(&packet.bytes, to).unwrap();
+        }
+        let start = Instant::now();
+        let mut received = 0;
+        while received < count {
+            received += recv(&mut receiver).unwrap();
+        }
+        total += start.elapsed();
+    }
+    total
+}
+
+fn recv(c: &mut Criterion) {
+    let mut group = c.benchmark_group("recv tick");
+    for count in SUBSCRIBERS.map(|subscribers| subscribers * 4) {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(BenchmarkId::new("recv_from", count), |b| {
+            let mut buf = [0; 128];
+            b.iter_custom(|iters| {
+                recv_waiting(count, iters, |socket| socket.recv_from(&mut buf).map(|_| 1))
+            })
+        });
+        group.bench_function(BenchmarkId::new("recvmmsg", count), |b| {
+            let mut batch = RecvBatch::new(count);
+            b.iter_custom(|iters| {
+                recv_waiting(count, iters, |socket| socket.recv_batch(&mut batch))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, send, recv);
+criterion_main!(benches);
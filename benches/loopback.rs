@@ -0,0 +1,100 @@
+//! A server pushing controller data to clients over loopback: how long one packet takes to
+//! reach a client, and how many a tick delivers as subscribers are added.
+//!
+//! ```text
+//! cargo bench --bench loopback
+//! ```
+
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dsu_protocol::{
+    client::Client,
+    crc::Crc32,
+    server::{Limits, Server, SLOTS},
+    types::*,
+    ControllerData, MessageRef, MAX_MESSAGE_SIZE,
+};
+
+const SUBSCRIBERS: [usize; 4] = [1, 4, 16, 64];
+
+fn packet(slot: u8) -> ControllerData {
+    ControllerData::new(
+        0,
+        slot,
+        State::Connected,
+        Model::FullGyro,
+        ConnectionType::Usb,
+        [0, 0, 0, 0, 0, slot],
+        BatteryStatus::Full,
+        true,
+        Crc32::new(),
+    )
+}
+
+/// A server on loopback with `count` clients subscribed to every slot.
+fn serve(count: usize) -> (Server, Vec<Client>) {
+    let mut server = Server::bind("127.0.0.1:0").unwrap();
+    server.set_limits(Limits::NONE);
+    let addr = server.local_addr().unwrap();
+    let clients: Vec<_> = (0..count)
+        .map(|_| {
+            let mut client = Client::connect(addr).unwrap();
+            client
+                .subscribe(Registration::AllControllers, 0, [0; 6])
+                .unwrap();
+            client
+        })
+        .collect();
+    while server.subscriber_count() < count {
+        server.poll().unwrap();
+    }
+    (server, clients)
+}
+
+/// Waits for controller data, skipping anything else.
+fn recv(client: &mut Client, buf: &mut [u8; MAX_MESSAGE_SIZE]) {
+    while !matches!(client.recv(buf).unwrap(), MessageRef::ControllerData(_)) {}
+}
+
+/// Times pushing `packets` and receiving all of them on every client. Subscriptions are kept
+/// alive between iterations, outside the timed part.
+fn deliver(count: usize, iters: u64, packets: &[ControllerData]) -> Duration {
+    let (mut server, mut clients) = serve(count);
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        server.poll().unwrap();
+        let start = Instant::now();
+        server.push_many(packets).unwrap();
+        for client in &mut clients {
+            for _ in packets {
+                recv(client, &mut buf);
+            }
+        }
+        total += start.elapsed();
+    }
+    total
+}
+
+fn latency(c: &mut Criterion) {
+    let packets = [packet(0)];
+    c.bench_function("push to recv", |b| {
+        b.iter_custom(|iters| deliver(1, iters, &packets))
+    });
+}
+
+fn fan_out(c: &mut Criterion) {
+    let packets: Vec<_> = (0..SLOTS as u8).map(packet).collect();
+    let mut group = c.benchmark_group("fan-out tick");
+    for count in SUBSCRIBERS {
+        group.throughput(Throughput::Elements((count * packets.len()) as u64));
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter_custom(|iters| deliver(count, iters, &packets))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, latency, fan_out);
+criterion_main!(benches);
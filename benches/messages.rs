@@ -0,0 +1,184 @@
+//! Building, parsing and checksumming each message type.
+//!
+//! ```text
+//! cargo bench --bench messages
+//! ```
+
+use core::hash::Hasher;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use dsu_protocol::{crc::Crc32, template::DataTemplate, types::*, *};
+
+/// `crc32fast`'s hasher, which picks a SIMD implementation at runtime, for comparison.
+#[derive(Default)]
+struct Crc32Fast(crc32fast::Hasher);
+
+impl Hasher for Crc32Fast {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.clone().finalize() as u64
+    }
+}
+
+fn controller_data(hasher: impl Hasher) -> ControllerData {
+    ControllerData::new(
+        1,
+        0,
+        State::Connected,
+        Model::FullGyro,
+        ConnectionType::Usb,
+        [1, 2, 3, 4, 5, 6],
+        BatteryStatus::Full,
+        true,
+        hasher,
+    )
+}
+
+/// One of each message as sent on the wire.
+fn messages() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        (
+            "RequestProtocolVersionInfo",
+            RequestProtocolVersionInfo::new(1, Crc32::new())
+                .bytes
+                .to_vec(),
+        ),
+        (
+            "ProtocolVersionInfo",
+            ProtocolVersionInfo::new(1, Protocol::Version1001, Crc32::new())
+                .bytes
+                .to_vec(),
+        ),
+        (
+            "RequestControllerInfo",
+            RequestControllerInfo::new(1, &[0, 1, 2, 3], Crc32::new())
+                .unwrap()
+                .bytes
+                .to_vec(),
+        ),
+        (
+            "ControllerInfo",
+            ControllerInfo::new(
+                1,
+                0,
+                State::Connected,
+                Model::FullGyro,
+                ConnectionType::Usb,
+                [1, 2, 3, 4, 5, 6],
+                BatteryStatus::Full,
+                Crc32::new(),
+            )
+            .bytes
+            .to_vec(),
+        ),
+        (
+            "RequestControllerData",
+            RequestControllerData::new(1, Registration::AllControllers, 0, [0; 6], Crc32::new())
+                .bytes
+                .to_vec(),
+        ),
+        (
+            "ControllerData",
+            controller_data(Crc32::new()).bytes.to_vec(),
+        ),
+    ]
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("new");
+    group.bench_function("RequestProtocolVersionInfo", |b| {
+        b.iter(|| RequestProtocolVersionInfo::new(black_box(1), Crc32::new()))
+    });
+    group.bench_function("ProtocolVersionInfo", |b| {
+        b.iter(|| ProtocolVersionInfo::new(black_box(1), Protocol::Version1001, Crc32::new()))
+    });
+    group.bench_function("RequestControllerInfo", |b| {
+        b.iter(|| RequestControllerInfo::new(black_box(1), &[0, 1, 2, 3], Crc32::new()))
+    });
+    group.bench_function("ControllerInfo", |b| {
+        b.iter(|| {
+            ControllerInfo::new(
+                black_box(1),
+                0,
+                State::Connected,
+                Model::FullGyro,
+                ConnectionType::Usb,
+                [1, 2, 3, 4, 5, 6],
+                BatteryStatus::Full,
+                Crc32::new(),
+            )
+        })
+    });
+    group.bench_function("RequestControllerData", |b| {
+        b.iter(|| {
+            RequestControllerData::new(
+                black_box(1),
+                Registration::AllControllers,
+                0,
+                [0; 6],
+                Crc32::new(),
+            )
+        })
+    });
+    group.bench_function("ControllerData", |b| {
+        b.iter(|| controller_data(black_box(Crc32::new())))
+    });
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, bytes) in messages() {
+        group.bench_function(name, |b| {
+            b.iter(|| MessageRef::parse(black_box(&bytes), Crc32::new()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn update_crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update_crc");
+    let mut data = controller_data(Crc32::new());
+    group.bench_function("Crc32", |b| {
+        b.iter(|| black_box(&mut data).update_crc(Crc32::new()))
+    });
+    group.bench_function("crc32fast", |b| {
+        b.iter(|| black_box(&mut data).update_crc(Crc32Fast::default()))
+    });
+
+    // Stamping a tick's packet, where only the motion data and packet number changed, by
+    // copying and rehashing all of it against reusing the hashed prefix of the last one sent,
+    // like the server does.
+    let mut number = 0u32;
+    group.bench_function("stamp", |b| {
+        b.iter(|| {
+            number = number.wrapping_add(1);
+            data.set_motion_timestamp(number as u64);
+            data.set_gyro_pitch(number as f32);
+            let mut packet = black_box(&data).clone();
+            packet.header_mut().set_sender_id(1);
+            packet.set_packet_number(number);
+            packet.update_crc(Crc32::new());
+            packet.header().crc32()
+        })
+    });
+    let mut template = DataTemplate::new();
+    group.bench_function("stamp with DataTemplate", |b| {
+        b.iter(|| {
+            number = number.wrapping_add(1);
+            data.set_motion_timestamp(number as u64);
+            data.set_gyro_pitch(number as f32);
+            template
+                .update(black_box(&data), 1, number)
+                .header()
+                .crc32()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode, parse, update_crc);
+criterion_main!(benches);
@@ -0,0 +1,200 @@
+//! `sendmmsg` and `recvmmsg` for [`UdpSocket`], which send or receive many datagrams with one
+//! system call.
+//!
+//! Used by `dsu_protocol`'s `linux-batch` feature, and kept in its own crate so that
+//! `dsu_protocol` has no unsafe code. Empty except on Linux.
+
+#![cfg(target_os = "linux")]
+
+use std::{
+    io, mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+    os::unix::io::AsRawFd,
+    ptr,
+};
+
+/// Most datagrams passed to the kernel at once, so the headers fit on the stack.
+pub const MAX_BATCH: usize = 64;
+
+/// Sends up to [`MAX_BATCH`] datagrams and returns how many were sent. Fails only if the
+/// first one couldn't be sent.
+pub fn send(socket: &UdpSocket, datagrams: &[(&[u8], &SocketAddr)]) -> io::Result<usize> {
+    let datagrams = &datagrams[..datagrams.len().min(MAX_BATCH)];
+    if datagrams.is_empty() {
+        return Ok(0);
+    }
+    // SAFETY: all of these are plain C structs for which zero is a valid value.
+    let mut addrs: [libc::sockaddr_storage; MAX_BATCH] = unsafe { mem::zeroed() };
+    let mut iovecs: [libc::iovec; MAX_BATCH] = unsafe { mem::zeroed() };
+    let mut headers: [libc::mmsghdr; MAX_BATCH] = unsafe { mem::zeroed() };
+    for (i, (bytes, to)) in datagrams.iter().enumerate() {
+        let addr_len = write_addr(to, &mut addrs[i]);
+        // The kernel only reads from `iov_base` when sending.
+        iovecs[i].iov_base = bytes.as_ptr() as *mut libc::c_void;
+        iovecs[i].iov_len = bytes.len();
+        let header = &mut headers[i].msg_hdr;
+        header.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        header.msg_namelen = addr_len;
+        header.msg_iov = &mut iovecs[i];
+        header.msg_iovlen = 1;
+    }
+    // SAFETY: the first `datagrams.len()` headers point at addresses and buffers that outlive
+    // the call.
+    let sent = unsafe {
+        libc::sendmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            datagrams.len() as libc::c_uint,
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(sent as usize)
+}
+
+/// Receives up to [`MAX_BATCH`] datagrams into `bufs`, waiting like `recv_from` for the first
+/// but not for the rest, and appends each one's length and sender to `received`. Returns how
+/// many were appended; the datagrams are in the same number of buffers at the start of `bufs`.
+///
+/// Datagrams from senders without an IP address, which a UDP socket shouldn't receive, are
+/// skipped.
+pub fn recv<B: AsMut<[u8]>>(
+    socket: &UdpSocket,
+    bufs: &mut [B],
+    received: &mut Vec<(usize, SocketAddr)>,
+) -> io::Result<usize> {
+    let count = bufs.len().min(MAX_BATCH);
+    if count == 0 {
+        return Ok(0);
+    }
+    // SAFETY: all of these are plain C structs for which zero is a valid value.
+    let mut addrs: [libc::sockaddr_storage; MAX_BATCH] = unsafe { mem::zeroed() };
+    let mut iovecs: [libc::iovec; MAX_BATCH] = unsafe { mem::zeroed() };
+    let mut headers: [libc::mmsghdr; MAX_BATCH] = unsafe { mem::zeroed() };
+    for (i, buf) in bufs[..count].iter_mut().enumerate() {
+        let buf = buf.as_mut();
+        iovecs[i].iov_base = buf.as_mut_ptr() as *mut libc::c_void;
+        iovecs[i].iov_len = buf.len();
+        let header = &mut headers[i].msg_hdr;
+        header.msg_name = &mut addrs[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        header.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        header.msg_iov = &mut iovecs[i];
+        header.msg_iovlen = 1;
+    }
+    // SAFETY: the first `count` headers point at addresses and buffers that outlive the call,
+    // and `bufs` isn't touched until it returns.
+    let received_count = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            headers.as_mut_ptr(),
+            count as libc::c_uint,
+            libc::MSG_WAITFORONE,
+            ptr::null_mut(),
+        )
+    };
+    if received_count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut kept = 0;
+    for (i, (header, addr)) in headers[..received_count as usize]
+        .iter()
+        .zip(&addrs)
+        .enumerate()
+    {
+        if let Some(from) = read_addr(addr) {
+            bufs.swap(kept, i);
+            received.push((header.msg_len as usize, from));
+            kept += 1;
+        }
+    }
+    Ok(kept)
+}
+
+fn write_addr(addr: &SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    let storage = storage as *mut libc::sockaddr_storage;
+    match addr {
+        SocketAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            // SAFETY: `sockaddr_storage` is large and aligned enough for any address.
+            unsafe { ptr::write(storage as *mut libc::sockaddr_in, sin) };
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            // SAFETY: as above.
+            unsafe { ptr::write(storage as *mut libc::sockaddr_in6, sin6) };
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+        }
+    }
+}
+
+fn read_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    let storage = storage as *const libc::sockaddr_storage;
+    // SAFETY: the family says which address the kernel wrote, and `sockaddr_storage` is large
+    // and aligned enough for any of them.
+    match unsafe { (*storage).ss_family } as libc::c_int {
+        libc::AF_INET => {
+            let sin = unsafe { &*(storage as *const libc::sockaddr_in) };
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes()),
+                u16::from_be(sin.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(storage as *const libc::sockaddr_in6) };
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                u16::from_be(sin6.sin6_port),
+                sin6.sin6_flowinfo,
+                sin6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_and_recv() {
+        for local in ["127.0.0.1:0", "[::1]:0"] {
+            let sender = UdpSocket::bind(local).unwrap();
+            let receiver = UdpSocket::bind(local).unwrap();
+            let to = receiver.local_addr().unwrap();
+            let packets: Vec<Vec<u8>> = (1..=3u8).map(|n| vec![n; n as usize * 10]).collect();
+            let datagrams: Vec<_> = packets.iter().map(|p| (&p[..], &to)).collect();
+            assert_eq!(send(&sender, &datagrams).unwrap(), 3);
+
+            let mut bufs = [[0; 100]; 4];
+            let mut received = Vec::new();
+            let mut count = 0;
+            while count < 3 {
+                count += recv(&receiver, &mut bufs[count..], &mut received).unwrap();
+            }
+            let from = sender.local_addr().unwrap();
+            assert_eq!(received, [(10, from), (20, from), (30, from)]);
+            for (i, packet) in packets.iter().enumerate() {
+                assert_eq!(&bufs[i][..packet.len()], &packet[..]);
+            }
+        }
+    }
+}
@@ -0,0 +1,535 @@
+//! Procedural macros used by `dsu_protocol` to declare message layouts.
+//!
+//! These macros expand to paths inside `dsu_protocol` (`crate::BufType`,
+//! `crate::layout`, `crate::error::Invalid`) and are not meant to be used
+//! from other crates.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    braced, bracketed,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Attribute, Error, Expr, Ident, LitInt, LitStr, Path, Result, Token, Type,
+};
+
+mod kw {
+    syn::custom_keyword!(message);
+}
+
+/// Declares a buffer type and the fields it contains.
+///
+/// ```ignore
+/// layout! {
+///     message ControllerInfo[32] {
+///         header: Header = 0,
+///         controller_header: ControllerHeader = 20,
+///     }
+/// }
+/// ```
+///
+/// Each field is `name: Type = offset`. The accessors generated depend on the type:
+///
+/// * integers and floats get `name()` and `set_name()`
+/// * `bool` gets `is_name()` and `set_name()`
+/// * `[u8; N]` gets `name()` and `name_mut()`
+/// * `int as Enum = offset { raw => Enum::Variant, ... }` gets `name()` returning
+///   `Result<Enum, Invalid<int>>` and `set_name()`
+/// * any other type is treated as a nested buffer type and gets `name()` and `name_mut()`
+///
+/// Fields marked `#[manual]` only reserve their bytes in the layout; their accessors are
+/// written by hand. `#[manual(Type)]` names the type taken and returned by those accessors
+/// so the field can be serialized through them, otherwise the raw bytes are used. Doc
+/// comments on fields are copied to the getter and the layout table.
+///
+/// The macro also emits compile-time checks that no two fields overlap and that every field
+/// fits in the buffer, a `LAYOUT` constant describing the fields, and, when the `serde`
+/// feature of `dsu_protocol` is enabled, field-by-field `Serialize` and `Deserialize` impls.
+#[proc_macro]
+pub fn layout(input: TokenStream) -> TokenStream {
+    let layout = parse_macro_input!(input as LayoutInput);
+    match layout.expand() {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct LayoutInput {
+    message: bool,
+    name: Ident,
+    size: LitInt,
+    fields: Punctuated<FieldInput, Token![,]>,
+}
+
+impl Parse for LayoutInput {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let message = if input.peek(kw::message) {
+            input.parse::<kw::message>()?;
+            true
+        } else {
+            false
+        };
+        let name = input.parse()?;
+        let size_content;
+        bracketed!(size_content in input);
+        let size = size_content.parse()?;
+        let content;
+        braced!(content in input);
+        let fields = content.parse_terminated(FieldInput::parse, Token![,])?;
+        Ok(LayoutInput {
+            message,
+            name,
+            size,
+            fields,
+        })
+    }
+}
+
+struct FieldInput {
+    attrs: Vec<Attribute>,
+    name: Ident,
+    ty: Type,
+    enum_ty: Option<Path>,
+    offset: LitInt,
+    variants: Vec<(Expr, Path)>,
+}
+
+impl Parse for FieldInput {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let name = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+        let enum_ty = if input.peek(Token![as]) {
+            input.parse::<Token![as]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        input.parse::<Token![=]>()?;
+        let offset = input.parse()?;
+
+        let mut variants = Vec::new();
+        if enum_ty.is_some() {
+            let content;
+            braced!(content in input);
+            while !content.is_empty() {
+                let raw = content.parse()?;
+                content.parse::<Token![=>]>()?;
+                let path = content.parse()?;
+                variants.push((raw, path));
+                if content.is_empty() {
+                    break;
+                }
+                content.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(FieldInput {
+            attrs,
+            name,
+            ty,
+            enum_ty,
+            offset,
+            variants,
+        })
+    }
+}
+
+enum Kind {
+    Int(usize),
+    Bool,
+    Bytes(usize),
+    Enum(usize),
+    Struct,
+}
+
+const INTS: &[(&str, usize)] = &[
+    ("u8", 1),
+    ("i8", 1),
+    ("u16", 2),
+    ("i16", 2),
+    ("u32", 4),
+    ("i32", 4),
+    ("f32", 4),
+    ("u64", 8),
+    ("i64", 8),
+    ("f64", 8),
+];
+
+fn int_size(ty: &Type) -> Option<usize> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let ident = path.get_ident()?;
+    INTS.iter()
+        .find(|(name, _)| ident == name)
+        .map(|&(_, size)| size)
+}
+
+fn type_name(ty: &Type) -> String {
+    quote!(#ty).to_string().replace(' ', "")
+}
+
+impl FieldInput {
+    fn kind(&self) -> Result<Kind> {
+        if self.enum_ty.is_some() {
+            return int_size(&self.ty)
+                .map(Kind::Enum)
+                .ok_or_else(|| Error::new_spanned(&self.ty, "enum fields must be stored as an integer"));
+        }
+        if let Some(size) = int_size(&self.ty) {
+            return Ok(Kind::Int(size));
+        }
+        match &self.ty {
+            Type::Path(path) if path.path.is_ident("bool") => Ok(Kind::Bool),
+            Type::Array(array) => {
+                let elem_is_u8 = matches!(&*array.elem, Type::Path(p) if p.path.is_ident("u8"));
+                let len = match &array.len {
+                    Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(len), .. }) => Some(len.base10_parse()?),
+                    _ => None,
+                };
+                match (elem_is_u8, len) {
+                    (true, Some(len)) => Ok(Kind::Bytes(len)),
+                    _ => Err(Error::new_spanned(array, "array fields must be `[u8; N]` with a literal length")),
+                }
+            }
+            _ => Ok(Kind::Struct),
+        }
+    }
+
+    fn is_manual(&self) -> bool {
+        self.attrs.iter().any(|attr| attr.path().is_ident("manual"))
+    }
+
+    /// The type of the hand-written accessors of a `#[manual(Type)]` field.
+    fn manual_type(&self) -> Result<Option<Type>> {
+        for attr in &self.attrs {
+            if attr.path().is_ident("manual") {
+                if let syn::Meta::List(_) = &attr.meta {
+                    return attr.parse_args().map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn docs(&self) -> Vec<&Attribute> {
+        self.attrs.iter().filter(|attr| attr.path().is_ident("doc")).collect()
+    }
+
+    fn doc_string(&self) -> String {
+        let mut lines = Vec::new();
+        for attr in self.docs() {
+            if let syn::Meta::NameValue(nv) = &attr.meta {
+                if let Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(s), .. }) = &nv.value {
+                    lines.push(s.value().trim().to_string());
+                }
+            }
+        }
+        lines.join(" ")
+    }
+
+    fn size_expr(&self, kind: &Kind) -> TokenStream2 {
+        match kind {
+            Kind::Int(size) | Kind::Enum(size) | Kind::Bytes(size) => quote!(#size),
+            Kind::Bool => quote!(1usize),
+            Kind::Struct => {
+                let ty = &self.ty;
+                quote!(<#ty as crate::BufType>::SIZE)
+            }
+        }
+    }
+
+    fn accessors(&self, kind: &Kind) -> TokenStream2 {
+        let docs = self.docs();
+        let name = &self.name;
+        let name_str = name.to_string();
+        let set = format_ident!("set_{}", name);
+        let get_mut = format_ident!("{}_mut", name);
+        let ty = &self.ty;
+        let start = self.offset.base10_parse::<usize>().unwrap_or(0);
+        let size = self.size_expr(kind);
+        let range = quote!(#start..(#start + #size));
+
+        match kind {
+            Kind::Int(_) => quote! {
+                #(#docs)*
+                pub fn #name(&self) -> #ty {
+                    <#ty>::from_le_bytes(self.bytes[#range].try_into().unwrap())
+                }
+
+                pub fn #set(&mut self, val: #ty) {
+                    self.bytes[#range].copy_from_slice(&val.to_le_bytes());
+                }
+            },
+            Kind::Bool => {
+                let is = format_ident!("is_{}", name);
+                quote! {
+                    #(#docs)*
+                    pub fn #is(&self) -> bool {
+                        self.bytes[#start] != 0
+                    }
+
+                    pub fn #set(&mut self, val: bool) {
+                        self.bytes[#start] = if val { 1 } else { 0 };
+                    }
+                }
+            }
+            Kind::Bytes(_) => quote! {
+                #(#docs)*
+                pub fn #name(&self) -> &#ty {
+                    self.bytes[#range].try_into().unwrap()
+                }
+
+                pub fn #get_mut(&mut self) -> &mut #ty {
+                    (&mut self.bytes[#range]).try_into().unwrap()
+                }
+            },
+            Kind::Enum(_) => {
+                let enum_ty = self.enum_ty.as_ref().unwrap();
+                let raws = self.variants.iter().map(|(raw, _)| raw);
+                let paths = self.variants.iter().map(|(_, path)| path);
+                let raws2 = raws.clone();
+                let paths2 = paths.clone();
+                quote! {
+                    #(#docs)*
+                    pub fn #name(&self) -> Result<#enum_ty, crate::error::Invalid<#ty>> {
+                        match <#ty>::from_le_bytes(self.bytes[#range].try_into().unwrap()) {
+                            #(val if val == #raws => Ok(#paths),)*
+                            invalid => Err(crate::error::Invalid(invalid, #name_str)),
+                        }
+                    }
+
+                    pub fn #set(&mut self, val: #enum_ty) {
+                        let intval: #ty = match val {
+                            #(#paths2 => #raws2,)*
+                        };
+                        self.bytes[#range].copy_from_slice(&intval.to_le_bytes());
+                    }
+                }
+            }
+            Kind::Struct => quote! {
+                #(#docs)*
+                pub fn #name(&self) -> &#ty {
+                    <&#ty>::try_from(&self.bytes[#range]).unwrap()
+                }
+
+                pub fn #get_mut(&mut self) -> &mut #ty {
+                    <&mut #ty>::try_from(&mut self.bytes[#range]).unwrap()
+                }
+            },
+        }
+    }
+
+    fn spec(&self, kind: &Kind) -> TokenStream2 {
+        let name = self.name.to_string();
+        let offset = &self.offset;
+        let size = self.size_expr(kind);
+        let doc = self.doc_string();
+        let (ty, kind_tokens) = match kind {
+            Kind::Int(_) => (type_name(&self.ty), quote!(Int)),
+            Kind::Bool => (type_name(&self.ty), quote!(Bool)),
+            Kind::Bytes(_) => (type_name(&self.ty), quote!(Bytes)),
+            Kind::Enum(_) => {
+                let enum_ty = self.enum_ty.as_ref().unwrap();
+                (quote!(#enum_ty).to_string().replace(' ', ""), quote!(Enum))
+            }
+            Kind::Struct => (type_name(&self.ty), quote!(Struct)),
+        };
+        let kind_tokens = if self.is_manual() { quote!(Manual) } else { kind_tokens };
+        let values = self.variants.iter().map(|(raw, path)| {
+            let variant = path.segments.last().unwrap().ident.to_string();
+            quote!((#raw as u64, #variant))
+        });
+        quote! {
+            crate::layout::Field {
+                name: #name,
+                offset: #offset,
+                size: #size,
+                ty: #ty,
+                kind: crate::layout::FieldKind::#kind_tokens,
+                values: &[#(#values,)*],
+                doc: #doc,
+            }
+        }
+    }
+}
+
+impl FieldInput {
+    /// Returns the type of this field in the serde representation, an expression reading it
+    /// from `self`, and a statement writing it from `repr` into `this`.
+    fn serde_parts(&self, kind: &Kind) -> Result<(TokenStream2, TokenStream2, TokenStream2)> {
+        let name = &self.name;
+        let set = format_ident!("set_{}", name);
+        let get_mut = format_ident!("{}_mut", name);
+        let ty = &self.ty;
+        let start = self.offset.base10_parse::<usize>()?;
+        let size = self.size_expr(kind);
+        let range = quote!(#start..(#start + #size));
+
+        if self.is_manual() {
+            if let Some(manual_ty) = self.manual_type()? {
+                return Ok((
+                    quote!(#manual_ty),
+                    quote!(self.#name()),
+                    quote!(this.#set(repr.#name);),
+                ));
+            }
+            return match kind {
+                Kind::Int(_) => Ok((
+                    quote!(#ty),
+                    quote!(<#ty>::from_le_bytes(self.bytes[#range].try_into().unwrap())),
+                    quote!(this.bytes[#range].copy_from_slice(&repr.#name.to_le_bytes());),
+                )),
+                Kind::Bytes(_) => Ok((
+                    quote!(#ty),
+                    quote!(self.bytes[#range].try_into().unwrap()),
+                    quote!(this.bytes[#range].copy_from_slice(&repr.#name);),
+                )),
+                _ => Err(Error::new_spanned(
+                    &self.name,
+                    "manual fields must be integers or byte arrays, or name their accessor type with `#[manual(Type)]`",
+                )),
+            };
+        }
+
+        Ok(match kind {
+            Kind::Int(_) => (quote!(#ty), quote!(self.#name()), quote!(this.#set(repr.#name);)),
+            Kind::Bool => {
+                let is = format_ident!("is_{}", name);
+                (quote!(bool), quote!(self.#is()), quote!(this.#set(repr.#name);))
+            }
+            Kind::Bytes(_) => (quote!(#ty), quote!(*self.#name()), quote!(*this.#get_mut() = repr.#name;)),
+            Kind::Enum(_) => {
+                let enum_ty = self.enum_ty.as_ref().unwrap();
+                (
+                    quote!(#enum_ty),
+                    quote!(self.#name().map_err(<__S::Error as serde::ser::Error>::custom)?),
+                    quote!(this.#set(repr.#name);),
+                )
+            }
+            Kind::Struct => (quote!(#ty), quote!(self.#name().clone()), quote!(*this.#get_mut() = repr.#name;)),
+        })
+    }
+}
+
+impl LayoutInput {
+    fn serde_impls(&self, fields: &[(usize, &FieldInput, Kind)]) -> Result<TokenStream2> {
+        let name = &self.name;
+        let name_str = name.to_string();
+        let size = &self.size;
+
+        let mut names = Vec::new();
+        let mut tys = Vec::new();
+        let mut reads = Vec::new();
+        let mut writes = Vec::new();
+        for (_, field, kind) in fields {
+            let (ty, read, write) = field.serde_parts(kind)?;
+            names.push(&field.name);
+            tys.push(ty);
+            reads.push(read);
+            writes.push(write);
+        }
+
+        Ok(quote! {
+            #[cfg(feature = "serde")]
+            const _: () = {
+                #[derive(serde::Serialize, serde::Deserialize)]
+                #[serde(rename = #name_str)]
+                struct Repr {
+                    #(#names: #tys,)*
+                }
+
+                impl serde::Serialize for #name {
+                    fn serialize<__S: serde::Serializer>(&self, serializer: __S) -> Result<__S::Ok, __S::Error> {
+                        let repr = Repr {
+                            #(#names: #reads,)*
+                        };
+                        serde::Serialize::serialize(&repr, serializer)
+                    }
+                }
+
+                impl<'de> serde::Deserialize<'de> for #name {
+                    fn deserialize<__D: serde::Deserializer<'de>>(deserializer: __D) -> Result<Self, __D::Error> {
+                        let repr: Repr = serde::Deserialize::deserialize(deserializer)?;
+                        let mut this = #name { bytes: [0; #size] };
+                        #(#writes)*
+                        Ok(this)
+                    }
+                }
+            };
+        })
+    }
+
+    fn expand(&self) -> Result<TokenStream2> {
+        let name = &self.name;
+        let name_str = name.to_string();
+        let size = &self.size;
+        let buf_type = if self.message {
+            quote!(buf_type!(message #name, #size);)
+        } else {
+            quote!(buf_type!(#name, #size);)
+        };
+
+        let mut fields = Vec::new();
+        for field in &self.fields {
+            let kind = field.kind()?;
+            let offset = field.offset.base10_parse::<usize>()?;
+            fields.push((offset, field, kind));
+        }
+        fields.sort_by_key(|(offset, _, _)| *offset);
+
+        let mut checks = Vec::new();
+        for pair in fields.windows(2) {
+            let (offset, field, kind) = &pair[0];
+            let (next_offset, next, _) = &pair[1];
+            let size = field.size_expr(kind);
+            let msg = LitStr::new(
+                &format!("{}: field `{}` overlaps field `{}`", name_str, field.name, next.name),
+                Span::call_site(),
+            );
+            checks.push(quote!(assert!(#offset + #size <= #next_offset, #msg);));
+        }
+        if let Some((offset, field, kind)) = fields.last() {
+            let buf_size = &self.size;
+            let size = field.size_expr(kind);
+            let msg = LitStr::new(
+                &format!("{}: field `{}` extends past the end of the buffer", name_str, field.name),
+                Span::call_site(),
+            );
+            checks.push(quote!(assert!(#offset + #size <= #buf_size, #msg);));
+        }
+
+        let accessors = fields
+            .iter()
+            .filter(|(_, field, _)| !field.is_manual())
+            .map(|(_, field, kind)| field.accessors(kind));
+        let specs = fields.iter().map(|(_, field, kind)| field.spec(kind));
+        let serde_impls = self.serde_impls(&fields)?;
+
+        Ok(quote! {
+            #buf_type
+
+            impl #name {
+                pub const LAYOUT: crate::layout::Layout = crate::layout::Layout {
+                    name: #name_str,
+                    size: #size,
+                    fields: &[#(#specs,)*],
+                };
+
+                #(#accessors)*
+            }
+
+            #[allow(clippy::int_plus_one)]
+            const _: () = {
+                #(#checks)*
+            };
+
+            #serde_impls
+        })
+    }
+}
@@ -0,0 +1,62 @@
+//! Checks that a DSU server behaves the way clients expect.
+
+use std::{
+    error::Error,
+    net::{SocketAddr, ToSocketAddrs},
+    process,
+    time::Duration,
+};
+
+use clap::Parser;
+use dsu_protocol::{
+    conformance::{self, Config},
+    transport::bind_udp,
+};
+
+#[derive(Parser)]
+#[command(
+    name = "dsu-conformance",
+    about = "Check a DSU server against the protocol"
+)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    server: String,
+    /// How long to wait for responses to requests.
+    #[arg(long, default_value_t = 1000)]
+    response_timeout_ms: u64,
+    /// How long to collect controller data for each kind of subscription.
+    #[arg(long, default_value_t = 2000)]
+    data_ms: u64,
+    /// Don't check that subscriptions time out, which takes about ten seconds.
+    #[arg(long)]
+    skip_timeout: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let server = args
+        .server
+        .to_socket_addrs()?
+        .next()
+        .ok_or("no server address given")?;
+    let local: SocketAddr = if server.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = bind_udp(local)?;
+    socket.set_nonblocking(true)?;
+
+    let config = Config {
+        response_timeout: Duration::from_millis(args.response_timeout_ms),
+        data_duration: Duration::from_millis(args.data_ms),
+        check_timeout: !args.skip_timeout,
+        ..Config::default()
+    };
+    let report = conformance::run(socket, server, &config)?;
+    println!("{}", report);
+    if !report.passed() {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Decodes and prints DSU traffic, either as a subscribed client or passively on a port.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    time::Instant,
+};
+
+use clap::Parser;
+use dsu_protocol::{
+    auth::AuthTransport,
+    client::Client,
+    crc::Crc32,
+    error::RecvError,
+    transport::{bind_udp, Transport},
+    types::*,
+    *,
+};
+use dsu_tools::{
+    allow_refused,
+    print::{self, Format, Record},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum TypeFilter {
+    Version,
+    Info,
+    Data,
+}
+
+#[derive(Parser)]
+#[command(name = "dsu-dump", about = "Decode and print DSU traffic")]
+struct Args {
+    /// Server to subscribe to as a client.
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    server: String,
+    /// Listen passively on this address instead of subscribing to a server.
+    #[arg(long, conflicts_with = "server")]
+    listen: Option<SocketAddr>,
+    #[arg(long, value_enum, default_value = "human")]
+    format: Format,
+    /// Only print messages about this slot. May be repeated.
+    #[arg(long)]
+    slot: Vec<u8>,
+    /// Only print messages of this type, requests included. May be repeated.
+    #[arg(long = "type", value_enum)]
+    message_type: Vec<TypeFilter>,
+    /// Sign requests with the key in this file and only accept signed responses, see
+    /// `dsu_protocol::auth`.
+    #[arg(long, conflicts_with = "listen")]
+    key_file: Option<PathBuf>,
+}
+
+impl Args {
+    fn wants(&self, message: &MessageRef) -> bool {
+        let type_ok = self.message_type.is_empty()
+            || match message.header().message_type() {
+                Ok(MessageType::ProtocolVersionInfo) => self.message_type.contains(&TypeFilter::Version),
+                Ok(MessageType::ControllerInfo) => self.message_type.contains(&TypeFilter::Info),
+                Ok(MessageType::ControllerData) => self.message_type.contains(&TypeFilter::Data),
+                Err(_) => false,
+            };
+        let slot_ok = self.slot.is_empty()
+            || self.slot.iter().any(|&slot| print::matches_slot(message, slot));
+        type_ok && slot_ok
+    }
+
+    fn print(&self, out: &mut impl Write, record: &Record) -> io::Result<()> {
+        if let Ok(message) = &record.message {
+            if !self.wants(message) {
+                return Ok(());
+            }
+        }
+        print::write_record(out, self.format, record)?;
+        out.flush()
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let start = Instant::now();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+
+    if let Some(listen) = args.listen {
+        let socket = bind_udp(listen)?;
+        loop {
+            let (len, from) = socket.recv_from(&mut buf)?;
+            let record = Record {
+                elapsed: start.elapsed(),
+                from,
+                message: MessageRef::parse(&buf[..len], Crc32::new()),
+            };
+            args.print(&mut out, &record)?;
+        }
+    }
+
+    let client = Client::connect(args.server.as_str())?;
+    match &args.key_file {
+        Some(path) => {
+            let key = dsu_tools::read_key(path)?;
+            let socket = client.socket().try_clone()?;
+            let client = Client::with_transport(AuthTransport::new(socket, key), *client.server());
+            dump(&args, start, client)
+        }
+        None => dump(&args, start, client),
+    }
+}
+
+fn dump<T: Transport<Addr = SocketAddr>>(
+    args: &Args,
+    start: Instant,
+    mut client: Client<T>,
+) -> Result<(), Box<dyn Error>> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let from = *client.server();
+    // The server may not be up yet, in which case the subscription is retried while waiting.
+    allow_refused(client.request_protocol_version())?;
+    allow_refused(client.request_controller_info(&[0, 1, 2, 3]))?;
+    allow_refused(client.subscribe(Registration::AllControllers, 0, [0; 6]))?;
+    loop {
+        let message = match client.recv(&mut buf) {
+            Ok(message) => Ok(message),
+            Err(RecvError::Parse(err)) => Err(err),
+            Err(RecvError::Io(err)) if err.kind() == io::ErrorKind::ConnectionRefused => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let record = Record {
+            elapsed: start.elapsed(),
+            from,
+            message,
+        };
+        args.print(&mut out, &record)?;
+    }
+}
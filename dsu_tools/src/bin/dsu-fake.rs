@@ -0,0 +1,100 @@
+//! Serves scripted synthetic controller input as a DSU server, no hardware needed.
+
+use std::{
+    error::Error,
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use dsu_protocol::{
+    auth::AuthTransport,
+    metrics::MetricsEndpoint,
+    server::{Server, SLOTS},
+    transport::{bind_udp, Transport},
+};
+use dsu_tools::{fake::Script, limits::LimitArgs};
+
+const DEMO: &str = include_str!("../../scripts/demo.toml");
+
+#[derive(Parser)]
+#[command(name = "dsu-fake", about = "Serve scripted synthetic controller input")]
+struct Args {
+    /// Script to play, see `dsu_tools::fake`. Plays a demo by default.
+    script: Option<PathBuf>,
+    /// Address to serve on. `[::]:26760` accepts IPv6 and IPv4 clients from the network.
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    bind: String,
+    #[arg(long, default_value_t = 0)]
+    slot: u8,
+    /// Stop after this many seconds instead of running forever.
+    #[arg(long, value_parser = dsu_tools::parse_secs)]
+    duration: Option<Duration>,
+    /// Only talk to clients that sign their packets with the key in this file, see
+    /// `dsu_protocol::auth`.
+    #[arg(long)]
+    key_file: Option<PathBuf>,
+    /// With `--key-file`, also serve plain DSU clients that don't sign their packets.
+    #[arg(long, requires = "key_file")]
+    allow_unsigned: bool,
+    /// Serve metrics for Prometheus over HTTP on this address, e.g. `127.0.0.1:9477`.
+    #[arg(long)]
+    metrics: Option<SocketAddr>,
+    #[command(flatten)]
+    limits: LimitArgs,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    dsu_tools::init_logging();
+    if args.slot as usize >= SLOTS {
+        return Err(format!("slot must be below {}", SLOTS).into());
+    }
+    let source = match &args.script {
+        Some(path) => fs::read_to_string(path)?,
+        None => DEMO.to_string(),
+    };
+    let script: Script = source.parse()?;
+
+    let socket = bind_udp(args.bind.as_str())?;
+    socket.set_nonblocking(true)?;
+    eprintln!("serving slot {} on {}", args.slot, socket.local_addr()?);
+    match &args.key_file {
+        Some(path) => {
+            let key = dsu_tools::read_key(path)?;
+            let mut transport = AuthTransport::new(socket, key);
+            transport.set_allow_unsigned(args.allow_unsigned);
+            serve(&args, &script, Server::with_transport(transport))
+        }
+        None => serve(&args, &script, Server::with_transport(socket)),
+    }
+}
+
+fn serve<T: Transport>(args: &Args, script: &Script, mut server: Server<T>) -> Result<(), Box<dyn Error>> {
+    let stop = args.duration;
+    server.set_limits(args.limits.limits());
+    server.set_access(args.limits.access());
+    server.set_controller(script.frame(args.slot, Duration::ZERO).controller_header().clone());
+    let mut endpoint = args.metrics.map(MetricsEndpoint::bind).transpose()?;
+    if let Some(endpoint) = &endpoint {
+        eprintln!("serving metrics on http://{}/metrics", endpoint.local_addr()?);
+    }
+
+    let start = Instant::now();
+    let interval = script.interval();
+    let mut frame = 0u32;
+    loop {
+        let time = interval * frame;
+        if stop.is_some_and(|stop| time > stop) {
+            return Ok(());
+        }
+        server.poll_until(start + time)?;
+        if let Some(endpoint) = &mut endpoint {
+            endpoint.poll(|| server.metrics());
+        }
+        server.push(&script.frame(args.slot, time))?;
+        frame += 1;
+    }
+}
@@ -0,0 +1,174 @@
+//! Relays DSU traffic between clients and a server through a simulated bad network.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    io,
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use dsu_protocol::{
+    impair::{Delay, ImpairedTransport, Impairment, Stats},
+    transport::{bind_udp, Transport},
+    MAX_MESSAGE_SIZE,
+};
+
+#[derive(Parser)]
+#[command(
+    name = "dsu-impair",
+    about = "Relay DSU traffic through a simulated bad network"
+)]
+struct Args {
+    /// Address clients connect to instead of the server.
+    #[arg(long, default_value = "127.0.0.1:26770")]
+    listen: SocketAddr,
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    server: SocketAddr,
+    /// Probability of dropping a packet.
+    #[arg(long, default_value_t = 0.0)]
+    drop: f64,
+    /// Probability of sending a packet twice.
+    #[arg(long, default_value_t = 0.0)]
+    duplicate: f64,
+    /// Probability of flipping a bit in a packet.
+    #[arg(long, default_value_t = 0.0)]
+    corrupt: f64,
+    /// Probability of holding a packet back so later ones overtake it.
+    #[arg(long, default_value_t = 0.0)]
+    reorder: f64,
+    #[arg(long, default_value_t = 10.0)]
+    reorder_delay_ms: f64,
+    /// Mean delay.
+    #[arg(long, default_value_t = 0.0)]
+    delay_ms: f64,
+    /// Standard deviation of the delay.
+    #[arg(long, default_value_t = 0.0)]
+    jitter_ms: f64,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+impl Args {
+    fn impairment(&self) -> Result<Impairment, String> {
+        for (name, rate) in [
+            ("drop", self.drop),
+            ("duplicate", self.duplicate),
+            ("corrupt", self.corrupt),
+            ("reorder", self.reorder),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{} must be between 0 and 1", name));
+            }
+        }
+        let millis = |name: &str, ms: f64| {
+            if ms.is_finite() && ms >= 0.0 {
+                Ok(Duration::from_secs_f64(ms / 1000.0))
+            } else {
+                Err(format!("{} must not be negative", name))
+            }
+        };
+        let mean = millis("delay", self.delay_ms)?;
+        let std_dev = millis("jitter", self.jitter_ms)?;
+        Ok(Impairment {
+            drop: self.drop,
+            duplicate: self.duplicate,
+            corrupt: self.corrupt,
+            reorder: self.reorder,
+            reorder_delay: millis("reorder delay", self.reorder_delay_ms)?,
+            delay: if std_dev > Duration::ZERO {
+                Delay::Normal { mean, std_dev }
+            } else if mean > Duration::ZERO {
+                Delay::Fixed(mean)
+            } else {
+                Delay::None
+            },
+        })
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let impairment = args.impairment()?;
+
+    let socket = bind_udp(args.listen)?;
+    socket.set_nonblocking(true)?;
+    // Server to client traffic.
+    let mut downstream = ImpairedTransport::new(socket, impairment, args.seed);
+    // Client to server traffic, one socket per client so the server can tell them apart.
+    let mut upstreams: HashMap<SocketAddr, ImpairedTransport> = HashMap::new();
+    eprintln!("relaying {} to {}", args.listen, args.server);
+
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let mut last_report = Instant::now();
+    loop {
+        while let Some((len, client)) = recv(downstream.recv_from(&mut buf))? {
+            let upstream = match upstreams.get_mut(&client) {
+                Some(upstream) => upstream,
+                None => {
+                    let local: SocketAddr = if args.server.is_ipv4() {
+                        ([0, 0, 0, 0], 0).into()
+                    } else {
+                        ([0u16; 8], 0).into()
+                    };
+                    let socket = bind_udp(local)?;
+                    socket.set_nonblocking(true)?;
+                    let seed = args.seed.wrapping_add(upstreams.len() as u64 + 1);
+                    upstreams
+                        .entry(client)
+                        .or_insert(ImpairedTransport::new(socket, impairment, seed))
+                }
+            };
+            upstream.send_to(&buf[..len], &args.server)?;
+        }
+        for (client, upstream) in &mut upstreams {
+            while let Some((len, _)) = recv(upstream.recv_from(&mut buf))? {
+                downstream.send_to(&buf[..len], client)?;
+            }
+            upstream.flush()?;
+        }
+        downstream.flush()?;
+
+        if last_report.elapsed() >= Duration::from_secs(5) {
+            last_report = Instant::now();
+            let to_server = upstreams
+                .values()
+                .map(|upstream| upstream.impairer().stats())
+                .fold(Stats::default(), add);
+            eprintln!("to server: {:?}", to_server);
+            eprintln!("to clients: {:?}", downstream.impairer().stats());
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// Turns "nothing to receive" into `None`.
+fn recv(result: io::Result<(usize, SocketAddr)>) -> io::Result<Option<(usize, SocketAddr)>> {
+    match result {
+        Ok(received) => Ok(Some(received)),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn add(a: Stats, b: Stats) -> Stats {
+    Stats {
+        received: a.received + b.received,
+        dropped: a.dropped + b.dropped,
+        duplicated: a.duplicated + b.duplicated,
+        corrupted: a.corrupted + b.corrupted,
+        reordered: a.reordered + b.reordered,
+        sent: a.sent + b.sent,
+    }
+}
@@ -0,0 +1,90 @@
+//! Subscribes to a slot and reports the latency of its controller data once a second.
+
+use std::{
+    error::Error,
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use dsu_protocol::{client::Client, error::RecvError, latency::LatencyProbe, types::*, *};
+use dsu_tools::allow_refused;
+
+#[derive(Parser)]
+#[command(name = "dsu-latency", about = "Measure the latency of DSU controller data")]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    server: String,
+    #[arg(long, default_value_t = 0)]
+    slot: u8,
+    /// Samples to report over, the latest this many packets.
+    #[arg(long, default_value_t = latency::DEFAULT_WINDOW)]
+    window: usize,
+    /// Report the delay on top of the fastest packet, instead of estimating the latency of
+    /// the fastest as half the round trip of a request.
+    #[arg(long)]
+    relative: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let mut client = Client::connect(args.server.as_str())?;
+    let mut probe = LatencyProbe::new();
+    probe.set_window(args.window);
+    if !args.relative {
+        let round_trip = round_trip(&mut client)?;
+        eprintln!("round trip {:.3} ms", ms(round_trip));
+        probe.set_base_latency(round_trip / 2);
+    }
+
+    allow_refused(client.subscribe(Registration::SlotBased, args.slot, [0; 6]))?;
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    let mut next_report = Instant::now() + Duration::from_secs(1);
+    loop {
+        match client.try_recv(&mut buf) {
+            Ok(Some(MessageRef::ControllerData(data))) => probe.record(data, Instant::now()),
+            Ok(_) => {}
+            Err(RecvError::Io(err)) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+            Err(err) => return Err(err.into()),
+        }
+        if Instant::now() < next_report {
+            continue;
+        }
+        next_report += Duration::from_secs(1);
+        match probe.report() {
+            Some(report) => println!(
+                "p50 {:.3} ms  p95 {:.3} ms  p99 {:.3} ms  max {:.3} ms  interval {:.3} ± {:.3} ms  lost {}  reordered {}",
+                ms(report.p50),
+                ms(report.p95),
+                ms(report.p99),
+                ms(report.max),
+                ms(report.interval),
+                ms(report.interval_jitter),
+                report.lost,
+                report.reordered,
+            ),
+            None => eprintln!("no controller data with motion timestamps yet"),
+        }
+    }
+}
+
+/// Times a protocol version request, retrying until the server answers.
+fn round_trip(client: &mut Client) -> Result<Duration, Box<dyn Error>> {
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let sent = Instant::now();
+        allow_refused(client.request_protocol_version())?;
+        match client.try_recv(&mut buf) {
+            Ok(Some(MessageRef::ProtocolVersionInfo(_))) => return Ok(sent.elapsed()),
+            Ok(_) => {}
+            Err(RecvError::Io(err)) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,167 @@
+//! Prints the DSU traffic in a pcap or pcapng capture, or exports a recording to pcapng.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Parser, Subcommand};
+use dsu_protocol::{
+    crc::Crc32,
+    pcap::{self, Datagram, PcapngWriter},
+    record::{Entry, Reader},
+    types::*,
+    *,
+};
+use dsu_tools::print::{self, Format, Record};
+
+#[derive(Parser)]
+#[command(
+    name = "dsu-pcap",
+    about = "Read and write DSU traffic in packet captures"
+)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the DSU messages in a capture, grouped by flow.
+    Show {
+        /// pcap or pcapng capture.
+        input: PathBuf,
+        /// Server port of the DSU traffic.
+        #[arg(long, default_value_t = client::DEFAULT_PORT)]
+        port: u16,
+        #[arg(long, value_enum, default_value = "human")]
+        format: Format,
+    },
+    /// Write a recording made with dsu-record as a pcapng capture of a client session.
+    Export {
+        /// Recording made with dsu-record.
+        input: PathBuf,
+        /// pcapng capture to write.
+        output: PathBuf,
+        #[arg(long, default_value = "127.0.0.1:26760")]
+        server: SocketAddr,
+        #[arg(long, default_value = "127.0.0.1:50000")]
+        client: SocketAddr,
+    },
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    match Args::parse().command {
+        Command::Show {
+            input,
+            port,
+            format,
+        } => show(input, port, format),
+        Command::Export {
+            input,
+            output,
+            server,
+            client,
+        } => export(input, output, server, client),
+    }
+}
+
+fn show(input: PathBuf, port: u16, format: Format) -> Result<(), Box<dyn Error>> {
+    let datagrams = pcap::read_datagrams(BufReader::new(File::open(input)?), port)?;
+    let start = datagrams.iter().map(|d| d.time).min().unwrap_or_default();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for flow in pcap::flows(datagrams, port) {
+        if format != Format::Ndjson {
+            writeln!(
+                out,
+                "flow {} <-> {}: {} datagrams",
+                flow.client,
+                flow.server,
+                flow.datagrams.len(),
+            )?;
+        }
+        for datagram in &flow.datagrams {
+            let record = Record {
+                elapsed: datagram.time.saturating_sub(start),
+                from: datagram.src,
+                message: datagram.message(),
+            };
+            print::write_record(&mut out, format, &record)?;
+        }
+    }
+    Ok(())
+}
+
+/// Synthesizes the session a client subscribed to all controllers would have seen: the
+/// handshake, a subscription renewed every second, and the recorded messages.
+fn export(
+    input: PathBuf,
+    output: PathBuf,
+    server: SocketAddr,
+    client: SocketAddr,
+) -> Result<(), Box<dyn Error>> {
+    let reader = Reader::new(BufReader::new(File::open(input)?))?;
+    let mut writer = PcapngWriter::new(BufWriter::new(File::create(output)?))?;
+    let start = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let client_id = 0;
+    let server_id = 0;
+    let mut write = |time: Duration, from_client: bool, payload: &[u8]| {
+        let (src, dst) = if from_client {
+            (client, server)
+        } else {
+            (server, client)
+        };
+        writer.write_datagram(&Datagram {
+            time: start + time,
+            src,
+            dst,
+            payload: payload.to_vec(),
+        })
+    };
+
+    let subscribe = RequestControllerData::new(
+        client_id,
+        Registration::AllControllers,
+        0,
+        [0; 6],
+        Crc32::new(),
+    );
+    write(
+        Duration::ZERO,
+        true,
+        &RequestProtocolVersionInfo::new(client_id, Crc32::new()).bytes,
+    )?;
+    write(
+        Duration::ZERO,
+        false,
+        &ProtocolVersionInfo::new(server_id, Protocol::Version1001, Crc32::new()).bytes,
+    )?;
+    write(
+        Duration::ZERO,
+        true,
+        &RequestControllerInfo::new(client_id, &[0, 1, 2, 3], Crc32::new())?.bytes,
+    )?;
+    write(Duration::ZERO, true, &subscribe.bytes)?;
+    let mut next_subscribe = client::RESUBSCRIBE_INTERVAL;
+    let mut count = 0;
+    for entry in reader {
+        let entry = entry?;
+        while entry.time() >= next_subscribe {
+            write(next_subscribe, true, &subscribe.bytes)?;
+            next_subscribe += client::RESUBSCRIBE_INTERVAL;
+        }
+        match &entry {
+            Entry::Info { time, info } => write(*time, false, &info.bytes)?,
+            Entry::Data { time, data } => write(*time, false, &data.bytes)?,
+        }
+        count += 1;
+    }
+    writer.flush()?;
+    eprintln!("exported {} entries", count);
+    Ok(())
+}
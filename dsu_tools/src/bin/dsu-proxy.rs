@@ -0,0 +1,134 @@
+//! Merges the controllers of several DSU servers into one.
+
+use std::{error::Error, str::FromStr};
+
+use clap::Parser;
+use dsu_protocol::{
+    proxy::{MergeRoute, Proxy, Route},
+    server::{Server, SLOTS},
+};
+use dsu_tools::limits::LimitArgs;
+
+#[derive(Parser)]
+#[command(name = "dsu-proxy", about = "Merge several DSU servers into one")]
+struct Args {
+    /// Upstream server to forward controllers from. May be repeated.
+    #[arg(long, required = true)]
+    upstream: Vec<String>,
+    /// Address to serve on. `[::]:26760` accepts IPv6 and IPv4 clients from the network.
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    bind: String,
+    /// Route as `UPSTREAM:SLOT=SLOT`, optionally followed by `/MAC`, e.g. `1:0=2` or
+    /// `1:0=2/00:00:00:00:01:02`. Upstreams are numbered from 0 in the order given.
+    /// May be repeated.
+    #[arg(long)]
+    route: Vec<RouteArg>,
+    /// Merge the buttons of one upstream controller with the motion of another as
+    /// `UPSTREAM:SLOT+UPSTREAM:SLOT=SLOT`, optionally followed by `/MAC`.
+    #[arg(long)]
+    merge: Vec<MergeArg>,
+    /// Only forward controllers with an explicit route.
+    #[arg(long)]
+    no_auto_route: bool,
+    #[command(flatten)]
+    limits: LimitArgs,
+}
+
+#[derive(Clone)]
+struct RouteArg(Route);
+
+impl FromStr for RouteArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid route `{}`", s);
+        let (from, downstream, mac) = split_route(s).ok_or_else(invalid)?;
+        let (upstream, slot) = parse_source(from).ok_or_else(invalid)?;
+        Ok(RouteArg(Route {
+            upstream,
+            slot,
+            downstream,
+            mac,
+        }))
+    }
+}
+
+#[derive(Clone)]
+struct MergeArg(MergeRoute);
+
+impl FromStr for MergeArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid merge `{}`", s);
+        let (from, downstream, mac) = split_route(s).ok_or_else(invalid)?;
+        let (input, motion) = from.split_once('+').ok_or_else(invalid)?;
+        Ok(MergeArg(MergeRoute {
+            input: parse_source(input).ok_or_else(invalid)?,
+            motion: parse_source(motion).ok_or_else(invalid)?,
+            downstream,
+            mac,
+        }))
+    }
+}
+
+/// Splits `FROM=SLOT[/MAC]`.
+fn split_route(s: &str) -> Option<(&str, u8, Option<[u8; 6]>)> {
+    let (route, mac) = match s.split_once('/') {
+        Some((route, mac)) => (route, Some(parse_mac(mac)?)),
+        None => (s, None),
+    };
+    let (from, downstream) = route.split_once('=')?;
+    let downstream = downstream.parse().ok().filter(|&slot: &u8| (slot as usize) < SLOTS)?;
+    Some((from, downstream, mac))
+}
+
+/// Parses `UPSTREAM:SLOT`.
+fn parse_source(s: &str) -> Option<(usize, u8)> {
+    let (upstream, slot) = s.split_once(':')?;
+    Some((upstream.parse().ok()?, slot.parse().ok()?))
+}
+
+fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = s.split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(mac),
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    dsu_tools::init_logging();
+    let mut server = Server::bind(args.bind.as_str())?;
+    server.set_limits(args.limits.limits());
+    server.set_access(args.limits.access());
+    let mut proxy = Proxy::new(server);
+    proxy.auto_route(!args.no_auto_route);
+    for upstream in &args.upstream {
+        proxy.add_upstream(upstream.as_str())?;
+    }
+    for RouteArg(route) in &args.route {
+        if route.upstream >= args.upstream.len() {
+            return Err(format!("route from unknown upstream {}", route.upstream).into());
+        }
+        proxy.route(*route);
+    }
+    for MergeArg(merge) in &args.merge {
+        if merge.input.0 >= args.upstream.len() || merge.motion.0 >= args.upstream.len() {
+            return Err("merge from unknown upstream".into());
+        }
+        proxy.merge(*merge);
+    }
+    eprintln!(
+        "forwarding {} upstream servers on {}",
+        args.upstream.len(),
+        proxy.server().local_addr()?,
+    );
+    proxy.run()?;
+    Ok(())
+}
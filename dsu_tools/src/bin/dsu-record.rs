@@ -0,0 +1,86 @@
+//! Records the controller data sent by a DSU server to a file.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fs::File,
+    io::{self, BufWriter},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use dsu_protocol::{
+    client::{Client, RESUBSCRIBE_INTERVAL},
+    error::RecvError,
+    record::Recorder,
+    types::*,
+    *,
+};
+use dsu_tools::allow_refused;
+
+#[derive(Parser)]
+#[command(name = "dsu-record", about = "Record controller data from a DSU server")]
+struct Args {
+    /// File to write the recording to.
+    output: PathBuf,
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    server: String,
+    /// Stop after this many seconds, even if the server is silent. Otherwise records until
+    /// interrupted.
+    #[arg(long, value_parser = dsu_tools::parse_secs)]
+    duration: Option<Duration>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let mut recorder = Recorder::new(BufWriter::new(File::create(&args.output)?))?;
+    let mut client = Client::connect(args.server.as_str())?;
+    allow_refused(client.request_controller_info(&[0, 1, 2, 3]))?;
+    allow_refused(client.subscribe(Registration::AllControllers, 0, [0; 6]))?;
+
+    let start = Instant::now();
+    let end = args.duration.map(|duration| start + duration);
+    let mut last_flush = start;
+    let mut known_slots = HashSet::new();
+    let mut frames = 0u64;
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+
+    while end.is_none_or(|end| Instant::now() < end) {
+        // Wait no longer than what's left, so a silent server doesn't keep us past the end.
+        if let Some(end) = end {
+            let left = end.saturating_duration_since(Instant::now());
+            if left < RESUBSCRIBE_INTERVAL {
+                client
+                    .socket()
+                    .set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+            }
+        }
+        match client.try_recv(&mut buf) {
+            Ok(Some(MessageRef::ControllerInfo(info))) => {
+                known_slots.insert(info.controller_header().slot());
+                recorder.record_info(info)?;
+            }
+            Ok(Some(MessageRef::ControllerData(data))) => {
+                let slot = data.controller_header().slot();
+                if !known_slots.contains(&slot) {
+                    allow_refused(client.request_controller_info(&[slot]))?;
+                }
+                recorder.record_data(data)?;
+                frames += 1;
+            }
+            Ok(_) | Err(RecvError::Parse(_)) => {}
+            Err(RecvError::Io(err)) if err.kind() == io::ErrorKind::ConnectionRefused => {}
+            Err(err) => return Err(err.into()),
+        }
+        // Keep what has been recorded so far on disk in case we're interrupted.
+        if last_flush.elapsed() >= Duration::from_secs(1) {
+            recorder.flush()?;
+            last_flush = Instant::now();
+        }
+    }
+
+    recorder.flush()?;
+    eprintln!("recorded {} frames in {:.1}s", frames, start.elapsed().as_secs_f64());
+    Ok(())
+}
@@ -0,0 +1,44 @@
+//! Serves a recording made with `dsu-record` as a DSU server.
+
+use std::{error::Error, fs::File, io::BufReader, path::PathBuf};
+
+use clap::Parser;
+use dsu_protocol::{record::Player, server::Server};
+use dsu_tools::limits::LimitArgs;
+
+#[derive(Parser)]
+#[command(name = "dsu-replay", about = "Replay a DSU recording through a DSU server")]
+struct Args {
+    /// Recording made with dsu-record.
+    input: PathBuf,
+    /// Address to serve on. `[::]:26760` accepts IPv6 and IPv4 clients from the network.
+    #[arg(long, default_value = "127.0.0.1:26760")]
+    bind: String,
+    /// Playback speed, where 2.0 plays twice as fast.
+    #[arg(long, default_value_t = 1.0, value_parser = dsu_tools::parse_positive)]
+    speed: f64,
+    /// Start over at the end of the recording.
+    #[arg(long = "loop")]
+    looping: bool,
+    #[command(flatten)]
+    limits: LimitArgs,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    dsu_tools::init_logging();
+    let mut player = Player::read(BufReader::new(File::open(&args.input)?))?
+        .speed(args.speed)
+        .looping(args.looping);
+    let mut server = Server::bind(args.bind.as_str())?;
+    server.set_limits(args.limits.limits());
+    server.set_access(args.limits.access());
+    eprintln!(
+        "replaying {} entries ({:.1}s) on {}",
+        player.entries().len(),
+        player.duration().as_secs_f64(),
+        server.local_addr()?,
+    );
+    player.play(&mut server)?;
+    Ok(())
+}
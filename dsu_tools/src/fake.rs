@@ -0,0 +1,516 @@
+//! Scripted synthetic controller input.
+//!
+//! A script is a TOML document. Every section is optional; a missing one leaves that part of
+//! the controller idle:
+//!
+//! ```toml
+//! rate = 100.0                 # frames per second
+//! length = 4.0                 # seconds before presses and swipes repeat, 0 to play them once
+//! model = "FullGyro"
+//! mac = "00:00:00:00:00:01"
+//!
+//! [motion]
+//! gyro = [0.0, 90.0, 0.0]      # pitch, yaw, roll in deg/s
+//! gravity = [0.0, -1.0, 0.0]   # accel in g while the orientation is unchanged
+//!
+//! [[stick]]
+//! side = "left"
+//! radius = 1.0                 # 0 to 1
+//! period = 2.0                 # seconds per circle
+//!
+//! [[press]]
+//! button = "A"
+//! at = 0.5
+//! duration = 0.25
+//!
+//! [[swipe]]
+//! from = [100, 400]
+//! to = [1800, 400]
+//! at = 1.0
+//! duration = 0.5
+//! ```
+//!
+//! The accelerometer reports gravity rotated by the orientation the gyro implies, so the two
+//! stay consistent.
+
+use std::{f64::consts::TAU, str::FromStr, time::Duration};
+
+use dsu_protocol::{crc::Crc32, types::*, ControllerData, Touch};
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    #[serde(default = "default_rate")]
+    pub rate: f64,
+    #[serde(default)]
+    pub length: f64,
+    #[serde(default = "default_model")]
+    pub model: Model,
+    #[serde(default, with = "mac")]
+    pub mac: [u8; 6],
+    #[serde(default)]
+    pub motion: Motion,
+    #[serde(default)]
+    pub stick: Vec<Stick>,
+    #[serde(default)]
+    pub press: Vec<Press>,
+    #[serde(default)]
+    pub swipe: Vec<Swipe>,
+}
+
+fn default_rate() -> f64 {
+    100.0
+}
+
+fn default_model() -> Model {
+    Model::FullGyro
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Motion {
+    #[serde(default)]
+    pub gyro: [f64; 3],
+    #[serde(default = "default_gravity")]
+    pub gravity: [f64; 3],
+}
+
+fn default_gravity() -> [f64; 3] {
+    [0.0, -1.0, 0.0]
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Motion {
+            gyro: [0.0; 3],
+            gravity: default_gravity(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Moves a stick around a circle, counterclockwise.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stick {
+    pub side: Side,
+    #[serde(default = "one")]
+    pub radius: f64,
+    #[serde(default = "one")]
+    pub period: f64,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+/// Holds a button from `at` for `duration` seconds.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Press {
+    pub button: Button,
+    pub at: f64,
+    #[serde(default = "default_press")]
+    pub duration: f64,
+}
+
+fn default_press() -> f64 {
+    0.1
+}
+
+/// Moves a finger in a straight line across the touch pad from `at` for `duration` seconds.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Swipe {
+    pub from: [u16; 2],
+    pub to: [u16; 2],
+    pub at: f64,
+    #[serde(default = "default_swipe")]
+    pub duration: f64,
+}
+
+fn default_swipe() -> f64 {
+    0.5
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let script: Script = toml::from_str(s).map_err(|err| err.to_string())?;
+        if !positive(script.rate) {
+            return Err("rate must be positive".to_string());
+        }
+        if !(script.length >= 0.0 && script.length.is_finite()) {
+            return Err("length must not be negative".to_string());
+        }
+        if script.stick.iter().any(|stick| !positive(stick.period)) {
+            return Err("stick period must be positive".to_string());
+        }
+        Ok(script)
+    }
+}
+
+fn positive(value: f64) -> bool {
+    value > 0.0 && value.is_finite()
+}
+
+impl Script {
+    /// Time between frames.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate)
+    }
+
+    /// The frame for `slot` at `time` since the start of the script. The sender id, packet
+    /// number and CRC are left for the server to fill in.
+    pub fn frame(&self, slot: u8, time: Duration) -> ControllerData {
+        let t = time.as_secs_f64();
+        let looped = if self.length > 0.0 {
+            t % self.length
+        } else {
+            t
+        };
+
+        let mut data = ControllerData::new(
+            0,
+            slot,
+            State::Connected,
+            self.model,
+            ConnectionType::Usb,
+            self.mac,
+            BatteryStatus::Charged,
+            true,
+            Crc32::new(),
+        );
+        data.set_left_stick_x(axis(0.0));
+        data.set_left_stick_y(axis(0.0));
+        data.set_right_stick_x(axis(0.0));
+        data.set_right_stick_y(axis(0.0));
+
+        for stick in &self.stick {
+            let angle = TAU * t / stick.period;
+            let x = axis(stick.radius * angle.cos());
+            let y = axis(stick.radius * angle.sin());
+            match stick.side {
+                Side::Left => {
+                    data.set_left_stick_x(x);
+                    data.set_left_stick_y(y);
+                }
+                Side::Right => {
+                    data.set_right_stick_x(x);
+                    data.set_right_stick_y(y);
+                }
+            }
+        }
+
+        let mut buttons = Buttons::new();
+        for press in &self.press {
+            if (press.at..press.at + press.duration).contains(&looped) {
+                buttons = buttons | press.button;
+                set_analog(&mut data, press.button);
+            }
+        }
+        data.set_buttons(buttons);
+
+        let mut touches = self
+            .swipe
+            .iter()
+            .enumerate()
+            .filter(|(_, swipe)| (swipe.at..swipe.at + swipe.duration).contains(&looped));
+        if let Some((id, swipe)) = touches.next() {
+            set_touch(data.touch1_mut(), id, swipe, looped);
+        }
+        if let Some((id, swipe)) = touches.next() {
+            set_touch(data.touch2_mut(), id, swipe, looped);
+        }
+
+        data.set_motion_timestamp(time.as_micros() as u64);
+        let gyro = self.motion.gyro;
+        data.set_gyro_pitch(gyro[0] as f32);
+        data.set_gyro_yaw(gyro[1] as f32);
+        data.set_gyro_roll(gyro[2] as f32);
+        let accel = gravity(self.motion.gravity, gyro, t);
+        data.set_accel_x(accel[0] as f32);
+        data.set_accel_y(accel[1] as f32);
+        data.set_accel_z(accel[2] as f32);
+        data
+    }
+}
+
+/// Maps `-1..=1` to a stick axis centered on 128.
+fn axis(value: f64) -> u8 {
+    (128.0 + 127.0 * value.clamp(-1.0, 1.0)).round() as u8
+}
+
+fn set_analog(data: &mut ControllerData, button: Button) {
+    match button {
+        Button::Left => data.set_analog_dpad_left(255),
+        Button::Down => data.set_analog_dpad_down(255),
+        Button::Right => data.set_analog_dpad_right(255),
+        Button::Up => data.set_analog_dpad_up(255),
+        Button::Y => data.set_analog_y(255),
+        Button::B => data.set_analog_b(255),
+        Button::A => data.set_analog_a(255),
+        Button::X => data.set_analog_x(255),
+        Button::R1 => data.set_analog_r1(255),
+        Button::L1 => data.set_analog_l1(255),
+        Button::R2 => data.set_analog_r2(255),
+        Button::L2 => data.set_analog_l2(255),
+        Button::Start | Button::RStick | Button::LStick | Button::Select => {}
+    }
+}
+
+fn set_touch(touch: &mut Touch, id: usize, swipe: &Swipe, t: f64) {
+    let progress = ((t - swipe.at) / swipe.duration).clamp(0.0, 1.0);
+    let lerp =
+        |from: u16, to: u16| (from as f64 + (to as f64 - from as f64) * progress).round() as u16;
+    touch.set_active(true);
+    touch.set_touch_id(id as u8);
+    touch.set_touch_x(lerp(swipe.from[0], swipe.to[0]));
+    touch.set_touch_y(lerp(swipe.from[1], swipe.to[1]));
+}
+
+/// Gravity in the controller's frame after rotating at `gyro` deg/s for `t` seconds.
+fn gravity(gravity: [f64; 3], gyro: [f64; 3], t: f64) -> [f64; 3] {
+    let rate = (gyro[0] * gyro[0] + gyro[1] * gyro[1] + gyro[2] * gyro[2]).sqrt();
+    if rate == 0.0 {
+        return gravity;
+    }
+    let axis = [gyro[0] / rate, gyro[1] / rate, gyro[2] / rate];
+    // The world appears to rotate the opposite way to the controller.
+    let angle = -(rate * t).to_radians();
+    let (sin, cos) = angle.sin_cos();
+    let dot = axis[0] * gravity[0] + axis[1] * gravity[1] + axis[2] * gravity[2];
+    let cross = [
+        axis[1] * gravity[2] - axis[2] * gravity[1],
+        axis[2] * gravity[0] - axis[0] * gravity[2],
+        axis[0] * gravity[1] - axis[1] * gravity[0],
+    ];
+    let mut rotated = [0.0; 3];
+    for i in 0..3 {
+        rotated[i] = gravity[i] * cos + cross[i] * sin + axis[i] * dot * (1.0 - cos);
+    }
+    rotated
+}
+
+mod mac {
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 6], D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let mut mac = [0u8; 6];
+        let mut parts = s.split(':');
+        for byte in &mut mac {
+            let part = parts
+                .next()
+                .ok_or_else(|| D::Error::custom("MAC address needs six bytes"))?;
+            *byte = u8::from_str_radix(part, 16).map_err(D::Error::custom)?;
+        }
+        if parts.next().is_some() {
+            return Err(D::Error::custom("MAC address needs six bytes"));
+        }
+        Ok(mac)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(s: &str) -> Script {
+        s.parse().unwrap()
+    }
+
+    fn at(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn validation() {
+        let empty = script("");
+        assert_eq!(empty.rate, 100.0);
+        assert_eq!(empty.interval(), Duration::from_millis(10));
+        assert_eq!(empty.model, Model::FullGyro);
+        assert_eq!(empty.motion.gravity, [0.0, -1.0, 0.0]);
+
+        for invalid in [
+            "rate = 0.0",
+            "rate = -1.0",
+            "rate = inf",
+            "length = -1.0",
+            "length = nan",
+            "[[stick]]\nside = \"left\"\nperiod = 0.0",
+            "[[stick]]\nside = \"middle\"",
+            "[[press]]\nbutton = \"Z\"\nat = 0.0",
+            "[[press]]\nat = 0.0",
+            "model = \"Wiimote\"",
+        ] {
+            assert!(invalid.parse::<Script>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn unknown_fields() {
+        for unknown in [
+            "speed = 1.0",
+            "[motion]\naccel = [0.0, 0.0, 0.0]",
+            "[[stick]]\nside = \"left\"\nspeed = 1.0",
+            "[[press]]\nbutton = \"A\"\nat = 0.0\nhold = true",
+            "[[swipe]]\nfrom = [0, 0]\nto = [1, 1]\nat = 0.0\nfinger = 1",
+        ] {
+            let err = unknown.parse::<Script>().unwrap_err();
+            assert!(err.contains("unknown field"), "{}: {}", unknown, err);
+        }
+    }
+
+    #[test]
+    fn mac() {
+        assert_eq!(
+            script("mac = \"00:1a:2B:ff:00:01\"").mac,
+            [0, 0x1A, 0x2B, 0xFF, 0, 1]
+        );
+        assert_eq!(script("").mac, [0; 6]);
+        for invalid in [
+            "00:00:00:00:00",
+            "00:00:00:00:00:00:00",
+            "00:00:00:00:00:zz",
+            "",
+        ] {
+            let source = format!("mac = \"{}\"", invalid);
+            assert!(source.parse::<Script>().is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn sticks() {
+        let script = script(
+            "[[stick]]\nside = \"left\"\nperiod = 2.0\n\
+             [[stick]]\nside = \"right\"\nradius = 0.5\nperiod = 4.0",
+        );
+        let sticks = |t| {
+            let data = script.frame(0, at(t));
+            [
+                data.left_stick_x(),
+                data.left_stick_y(),
+                data.right_stick_x(),
+                data.right_stick_y(),
+            ]
+        };
+        assert_eq!(sticks(0.0), [255, 128, 192, 128]);
+        assert_eq!(sticks(0.5), [128, 255, 173, 173]);
+        assert_eq!(sticks(1.0), [1, 128, 128, 192]);
+        assert_eq!(sticks(1.5), [128, 1, 83, 173]);
+        // A stick without a script stays centered.
+        let data = self::script("").frame(0, at(0.3));
+        assert_eq!([data.left_stick_x(), data.right_stick_y()], [128, 128]);
+    }
+
+    #[test]
+    fn presses() {
+        let source = "[[press]]\nbutton = \"A\"\nat = 0.5\nduration = 0.25\n\
+                      [[press]]\nbutton = \"Start\"\nat = 0.6";
+        let looped = script(&format!("length = 4.0\n{}", source));
+        let once = script(source);
+        let pressed =
+            |script: &Script, t, button| script.frame(0, at(t)).buttons().contains(button);
+
+        assert!(!pressed(&looped, 0.4, Button::A));
+        assert!(pressed(&looped, 0.5, Button::A));
+        assert!(pressed(&looped, 0.65, Button::A));
+        assert!(pressed(&looped, 0.65, Button::Start));
+        assert!(!pressed(&looped, 0.75, Button::A));
+        assert!(!pressed(&looped, 0.75, Button::Start));
+        assert!(pressed(&looped, 4.6, Button::A));
+        assert!(pressed(&looped, 8.6, Button::A));
+        assert!(!pressed(&once, 4.6, Button::A));
+        assert!(pressed(&once, 0.6, Button::A));
+
+        assert_eq!(looped.frame(0, at(0.6)).analog_a(), 255);
+        assert_eq!(looped.frame(0, at(0.8)).analog_a(), 0);
+    }
+
+    #[test]
+    fn swipes() {
+        let script = script(
+            "length = 4.0\n\
+             [[swipe]]\nfrom = [100, 400]\nto = [1900, 200]\nat = 1.0\nduration = 1.0\n\
+             [[swipe]]\nfrom = [0, 0]\nto = [0, 1000]\nat = 1.5",
+        );
+        let touches = |t| {
+            let data = script.frame(0, at(t));
+            [data.touch1().clone(), data.touch2().clone()].map(|touch| {
+                (
+                    touch.is_active(),
+                    touch.touch_id(),
+                    touch.touch_x(),
+                    touch.touch_y(),
+                )
+            })
+        };
+        assert_eq!(touches(0.5), [(false, 0, 0, 0); 2]);
+        assert_eq!(touches(1.0), [(true, 0, 100, 400), (false, 0, 0, 0)]);
+        assert_eq!(touches(1.5), [(true, 0, 1000, 300), (true, 1, 0, 0)]);
+        assert_eq!(touches(5.75), [(true, 0, 1450, 250), (true, 1, 0, 500)]);
+        assert_eq!(touches(2.5), [(false, 0, 0, 0); 2]);
+    }
+
+    #[test]
+    fn gravity_follows_the_gyro() {
+        let length = |v: [f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let down = [0.0, -1.0, 0.0];
+
+        // Turning around the axis gravity points along changes nothing.
+        assert_eq!(gravity(down, [0.0, 90.0, 0.0], 1.3), down);
+        assert_eq!(gravity(down, [0.0; 3], 1.3), down);
+
+        // Pitching up 90 degrees leaves gravity pointing out of the back of the controller.
+        let pitched = gravity(down, [90.0, 0.0, 0.0], 1.0);
+        for (axis, expected) in pitched.iter().zip([0.0, 0.0, 1.0]) {
+            assert!((axis - expected).abs() < 1e-9, "{:?}", pitched);
+        }
+
+        // Around any axis perpendicular to gravity, gravity turns by the angle the gyro
+        // implies, and never changes length.
+        let gyro = [30.0, 0.0, -40.0];
+        for step in 0..100 {
+            let t = step as f64 * 0.05;
+            let rotated = gravity(down, gyro, t);
+            assert!((length(rotated) - 1.0).abs() < 1e-9);
+            let turned = (50.0 * t) % 360.0;
+            let expected = turned.min(360.0 - turned);
+            let angle = dot(rotated, down).clamp(-1.0, 1.0).acos().to_degrees();
+            assert!(
+                (angle - expected).abs() < 1e-6,
+                "{} s: {} vs {}",
+                t,
+                angle,
+                expected
+            );
+        }
+
+        let script = script("[motion]\ngyro = [90.0, 0.0, 0.0]");
+        let data = script.frame(0, at(1.0));
+        assert_eq!(data.gyro_pitch(), 90.0);
+        assert_eq!(data.motion_timestamp(), 1_000_000);
+        assert!((data.accel_z() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn demo() {
+        let demo = script(include_str!("../scripts/demo.toml"));
+        assert_eq!(demo.rate, 100.0);
+        assert_eq!(demo.length, 4.0);
+        assert_eq!(demo.stick.len(), 2);
+        assert_eq!(demo.press.len(), 3);
+        assert_eq!(demo.swipe.len(), 1);
+        assert!(demo.frame(0, at(0.6)).buttons().contains(Button::A));
+    }
+}
@@ -0,0 +1,74 @@
+//! Command line tools for inspecting and simulating DSU traffic.
+
+pub mod fake;
+pub mod limits;
+pub mod print;
+
+use std::{error::Error, fs, io, path::Path, time::Duration};
+
+use dsu_protocol::auth::Key;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Treats `ConnectionRefused` as success.
+///
+/// Sending to a server that isn't up yet can report the rejection of an earlier datagram, which
+/// isn't a reason to give up since requests are retried.
+pub fn allow_refused(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+        result => result,
+    }
+}
+
+/// Parses a number of seconds given on the command line.
+pub fn parse_secs(arg: &str) -> Result<Duration, String> {
+    let secs: f64 = arg.parse().map_err(|err| format!("{}", err))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| "must be a finite number, at least 0".into())
+}
+
+/// Parses a number given on the command line that must be above zero.
+pub fn parse_positive(arg: &str) -> Result<f64, String> {
+    let value: f64 = arg.parse().map_err(|err| format!("{}", err))?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err("must be a finite number above 0".into())
+    }
+}
+
+/// Reads a key for [`dsu_protocol::auth`] from a file, ignoring surrounding whitespace.
+pub fn read_key(path: &Path) -> Result<Key, Box<dyn Error>> {
+    let secret = fs::read(path)?;
+    let secret = secret.trim_ascii();
+    if secret.len() < 16 {
+        return Err(format!("key in {} is shorter than 16 bytes", path.display()).into());
+    }
+    Ok(Key::new(secret))
+}
+
+/// Prints the library's log messages at `Info` and above to stderr.
+pub fn init_logging() {
+    if log::set_logger(&StderrLogger).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}: {}",
+                record.level().as_str().to_lowercase(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
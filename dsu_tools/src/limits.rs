@@ -0,0 +1,67 @@
+//! Command line options for a server's [`Limits`] and [`AccessList`].
+
+use dsu_protocol::{
+    access::{AccessList, Cidr},
+    server::{Limits, RateLimit},
+};
+
+#[derive(Clone, Debug, clap::Args)]
+pub struct LimitArgs {
+    /// Most clients subscribed at once, 0 for no limit.
+    #[arg(long, default_value_t = 64)]
+    pub max_subscribers: usize,
+    /// Most clients subscribed at once from one IP address, 0 for no limit.
+    #[arg(long, default_value_t = 8)]
+    pub max_subscribers_per_ip: usize,
+    /// Requests handled per second from all clients, 0 for no limit. Bursts of twice as many
+    /// are allowed.
+    #[arg(long, default_value_t = 2000)]
+    pub rate: u32,
+    /// Requests handled per second from one IP address, 0 for no limit.
+    #[arg(long, default_value_t = 100)]
+    pub rate_per_ip: u32,
+    /// Ignore subscriptions from clients that haven't requested the protocol version or
+    /// controller info first.
+    #[arg(long)]
+    pub require_handshake: bool,
+    /// Turn off all limits.
+    #[arg(long)]
+    pub no_limits: bool,
+    /// Only answer clients in this CIDR block, e.g. `192.168.1.0/24` or `fd00::/8`. May be
+    /// repeated.
+    #[arg(long)]
+    pub allow: Vec<Cidr>,
+    /// Never answer clients in this CIDR block, even if allowed. May be repeated.
+    #[arg(long)]
+    pub deny: Vec<Cidr>,
+}
+
+impl LimitArgs {
+    pub fn access(&self) -> AccessList {
+        AccessList {
+            allow: self.allow.clone(),
+            deny: self.deny.clone(),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        if self.no_limits {
+            return Limits::NONE;
+        }
+        let max = |max| Some(max).filter(|&max| max > 0);
+        let rate = |per_second| {
+            Some(RateLimit {
+                per_second,
+                burst: per_second.saturating_mul(2),
+            })
+            .filter(|_| per_second > 0)
+        };
+        Limits {
+            max_subscribers: max(self.max_subscribers),
+            max_subscribers_per_host: max(self.max_subscribers_per_ip),
+            rate: rate(self.rate),
+            rate_per_host: rate(self.rate_per_ip),
+            require_handshake: self.require_handshake,
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fmt::{Debug, Display},
+    io::{self, Write},
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dsu_protocol::{error::*, types::*, *};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Multi-line, every field labelled.
+    Human,
+    /// One line per message.
+    Compact,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+/// A received datagram, decoded or not.
+pub struct Record<'a> {
+    /// Time since the dump started.
+    pub elapsed: Duration,
+    pub from: SocketAddr,
+    pub message: Result<MessageRef<'a>, MessageParseError>,
+}
+
+pub fn write_record<W: Write>(out: &mut W, format: Format, record: &Record) -> io::Result<()> {
+    match format {
+        Format::Human => write_human(out, record),
+        Format::Compact => write_compact(out, record),
+        Format::Ndjson => write_ndjson(out, record),
+    }
+}
+
+pub fn message_name(message: &MessageRef) -> &'static str {
+    match message {
+        MessageRef::RequestProtocolVersionInfo(_) => "RequestProtocolVersionInfo",
+        MessageRef::ProtocolVersionInfo(_) => "ProtocolVersionInfo",
+        MessageRef::RequestControllerInfo(_) => "RequestControllerInfo",
+        MessageRef::ControllerInfo(_) => "ControllerInfo",
+        MessageRef::RequestControllerData(_) => "RequestControllerData",
+        MessageRef::ControllerData(_) => "ControllerData",
+    }
+}
+
+/// Whether the message concerns `slot`. Messages that aren't about a slot always match.
+pub fn matches_slot(message: &MessageRef, slot: u8) -> bool {
+    match message {
+        MessageRef::RequestControllerInfo(m) => m.slots().map_or(true, |slots| slots.contains(&slot)),
+        MessageRef::ControllerInfo(m) => m.controller_header().slot() == slot,
+        MessageRef::RequestControllerData(m) => match m.registration() {
+            Ok(Registration::SlotBased) => m.slot() == slot,
+            _ => true,
+        },
+        MessageRef::ControllerData(m) => m.controller_header().slot() == slot,
+        MessageRef::RequestProtocolVersionInfo(_) | MessageRef::ProtocolVersionInfo(_) => true,
+    }
+}
+
+fn show<T: Debug, R: Display>(value: Result<T, Invalid<R>>) -> String {
+    match value {
+        Ok(value) => format!("{:?}", value),
+        Err(Invalid(raw, _)) => format!("invalid({})", raw),
+    }
+}
+
+fn mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn buttons(buttons: Buttons) -> String {
+    let names = buttons.iter().map(|b| format!("{:?}", b)).collect::<Vec<_>>();
+    if names.is_empty() {
+        "-".to_string()
+    } else {
+        names.join("+")
+    }
+}
+
+fn controller_header(header: &ControllerHeader) -> String {
+    format!(
+        "slot {} {} {} {} mac {} battery {}",
+        header.slot(),
+        show(header.state()),
+        show(header.model()),
+        show(header.connection_type()),
+        mac(header.mac()),
+        show(header.battery_status()),
+    )
+}
+
+fn touch(touch: &Touch) -> String {
+    format!(
+        "{} id {} ({}, {})",
+        if touch.is_active() { "active" } else { "inactive" },
+        touch.touch_id(),
+        touch.touch_x(),
+        touch.touch_y(),
+    )
+}
+
+fn write_human<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    let seconds = record.elapsed.as_secs_f64();
+    let message = match &record.message {
+        Ok(message) => message,
+        Err(err) => return writeln!(out, "{:>10.3} {} {}", seconds, record.from, err),
+    };
+    let header = message.header();
+    writeln!(
+        out,
+        "{:>10.3} {} {} sender {:#010x} protocol {} length {} crc {:#010x}",
+        seconds,
+        record.from,
+        message_name(message),
+        header.sender_id(),
+        show(header.protocol()),
+        header.packet_length(),
+        header.crc32(),
+    )?;
+    match message {
+        MessageRef::RequestProtocolVersionInfo(_) => {}
+        MessageRef::ProtocolVersionInfo(m) => {
+            writeln!(out, "    protocol {}", show(m.protocol()))?;
+        }
+        MessageRef::RequestControllerInfo(m) => match m.slots() {
+            Ok(slots) => writeln!(out, "    slots {:?}", slots)?,
+            Err(err) => writeln!(out, "    {}", err)?,
+        },
+        MessageRef::ControllerInfo(m) => {
+            writeln!(out, "    {}", controller_header(m.controller_header()))?;
+        }
+        MessageRef::RequestControllerData(m) => {
+            writeln!(
+                out,
+                "    registration {} slot {} mac {}",
+                show(m.registration()),
+                m.slot(),
+                mac(m.mac()),
+            )?;
+        }
+        MessageRef::ControllerData(m) => {
+            writeln!(out, "    {}", controller_header(m.controller_header()))?;
+            writeln!(
+                out,
+                "    packet {} {}",
+                m.packet_number(),
+                if m.is_connected() { "connected" } else { "disconnected" },
+            )?;
+            writeln!(
+                out,
+                "    buttons {} ps {} touch {}",
+                buttons(m.buttons()),
+                m.ps_button(),
+                m.touch_button(),
+            )?;
+            writeln!(
+                out,
+                "    sticks left ({}, {}) right ({}, {})",
+                m.left_stick_x(),
+                m.left_stick_y(),
+                m.right_stick_x(),
+                m.right_stick_y(),
+            )?;
+            writeln!(
+                out,
+                "    analog dpad {} {} {} {} face {} {} {} {} shoulders r1 {} l1 {} r2 {} l2 {}",
+                m.analog_dpad_left(),
+                m.analog_dpad_down(),
+                m.analog_dpad_right(),
+                m.analog_dpad_up(),
+                m.analog_y(),
+                m.analog_b(),
+                m.analog_a(),
+                m.analog_x(),
+                m.analog_r1(),
+                m.analog_l1(),
+                m.analog_r2(),
+                m.analog_l2(),
+            )?;
+            writeln!(out, "    touch1 {}", touch(m.touch1()))?;
+            writeln!(out, "    touch2 {}", touch(m.touch2()))?;
+            writeln!(
+                out,
+                "    motion {} us accel ({:.3}, {:.3}, {:.3}) g gyro ({:.3}, {:.3}, {:.3}) deg/s",
+                m.motion_timestamp(),
+                m.accel_x(),
+                m.accel_y(),
+                m.accel_z(),
+                m.gyro_pitch(),
+                m.gyro_yaw(),
+                m.gyro_roll(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn write_compact<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    let seconds = record.elapsed.as_secs_f64();
+    let message = match &record.message {
+        Ok(message) => message,
+        Err(err) => return writeln!(out, "{:.3} {} error {}", seconds, record.from, err.code()),
+    };
+    write!(out, "{:.3} {} ", seconds, record.from)?;
+    match message {
+        MessageRef::RequestProtocolVersionInfo(_) => writeln!(out, "req-version"),
+        MessageRef::ProtocolVersionInfo(m) => writeln!(out, "version {}", show(m.protocol())),
+        MessageRef::RequestControllerInfo(m) => writeln!(out, "req-info slots={:?}", m.slots().unwrap_or(&[])),
+        MessageRef::ControllerInfo(m) => {
+            let h = m.controller_header();
+            writeln!(out, "info slot={} {} {}", h.slot(), show(h.state()), show(h.model()))
+        }
+        MessageRef::RequestControllerData(m) => writeln!(
+            out,
+            "req-data {} slot={} mac={}",
+            show(m.registration()),
+            m.slot(),
+            mac(m.mac()),
+        ),
+        MessageRef::ControllerData(m) => writeln!(
+            out,
+            "data slot={} #{} btn={} L={},{} R={},{} ts={} accel={:.2},{:.2},{:.2} gyro={:.1},{:.1},{:.1}",
+            m.controller_header().slot(),
+            m.packet_number(),
+            buttons(m.buttons()),
+            m.left_stick_x(),
+            m.left_stick_y(),
+            m.right_stick_x(),
+            m.right_stick_y(),
+            m.motion_timestamp(),
+            m.accel_x(),
+            m.accel_y(),
+            m.accel_z(),
+            m.gyro_pitch(),
+            m.gyro_yaw(),
+            m.gyro_roll(),
+        ),
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    code: &'static str,
+    offset: usize,
+    message: String,
+    header: Option<&'a Header>,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: f64,
+    elapsed: f64,
+    from: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a MessageRef<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<JsonError<'a>>,
+}
+
+fn write_ndjson<W: Write>(out: &mut W, record: &Record) -> io::Result<()> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let (message, error) = match &record.message {
+        Ok(message) => (Some(message), None),
+        Err(err) => (
+            None,
+            Some(JsonError {
+                code: err.code(),
+                offset: err.offset(),
+                message: err.to_string(),
+                header: err.header(),
+            }),
+        ),
+    };
+    let json = JsonRecord {
+        time,
+        elapsed: record.elapsed.as_secs_f64(),
+        from: record.from,
+        message,
+        error,
+    };
+    let line = serde_json::to_string(&json).unwrap_or_else(|err| {
+        // Enum fields with unknown values can't be represented structurally.
+        serde_json::json!({
+            "time": time,
+            "elapsed": json.elapsed,
+            "from": record.from,
+            "error": { "code": "unrepresentable", "message": err.to_string() },
+        })
+        .to_string()
+    });
+    writeln!(out, "{}", line)
+}
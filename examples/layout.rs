@@ -0,0 +1,9 @@
+//! Prints the byte layout of every message as protocol documentation.
+
+use dsu_protocol::layout::LAYOUTS;
+
+fn main() {
+    for layout in LAYOUTS {
+        println!("{}", layout);
+    }
+}
@@ -0,0 +1,27 @@
+#![no_main]
+
+//! Reads every field of every buffer type, without the checks `parse` does first.
+
+use dsu_protocol::*;
+use dsu_protocol_fuzz::*;
+use libfuzzer_sys::fuzz_target;
+
+/// Copies `data` into a buffer of `N` bytes, padding with zeros.
+fn buf<const N: usize>(data: &[u8]) -> [u8; N] {
+    let mut buf = [0; N];
+    let len = data.len().min(N);
+    buf[..len].copy_from_slice(&data[..len]);
+    buf
+}
+
+fuzz_target!(|data: &[u8]| {
+    read_header(Header::from_ref(&buf(data)));
+    read_controller_header(ControllerHeader::from_ref(&buf(data)));
+    read_touch(Touch::from_ref(&buf(data)));
+    read_header(RequestProtocolVersionInfo::from_ref(&buf(data)).header());
+    read_protocol_version_info(ProtocolVersionInfo::from_ref(&buf(data)));
+    read_request_controller_info(RequestControllerInfo::from_ref(&buf(data)));
+    read_controller_info(ControllerInfo::from_ref(&buf(data)));
+    read_request_controller_data(RequestControllerData::from_ref(&buf(data)));
+    read_controller_data(ControllerData::from_ref(&buf(data)));
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use dsu_protocol::{crc::Crc32, MessageRef};
+use dsu_protocol_fuzz::read_message;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = MessageRef::parse(data, Crc32::new()) {
+        read_message(&message);
+    }
+});
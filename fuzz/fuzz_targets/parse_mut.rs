@@ -0,0 +1,54 @@
+#![no_main]
+
+use dsu_protocol::{crc::Crc32, MessageMut, MessageRef};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buf = data.to_vec();
+    let parsed = MessageMut::parse_mut(&mut buf, Crc32::new()).is_ok();
+    assert_eq!(parsed, MessageRef::parse(data, Crc32::new()).is_ok());
+    if !parsed {
+        return;
+    }
+
+    // Writing back what was read and recomputing the CRC changes nothing.
+    match MessageMut::parse_mut(&mut buf, Crc32::new()).unwrap() {
+        MessageMut::RequestProtocolVersionInfo(request) => request.update_crc(Crc32::new()),
+        MessageMut::ProtocolVersionInfo(info) => {
+            if let Ok(protocol) = info.protocol() {
+                info.set_protocol(protocol);
+            }
+            info.update_crc(Crc32::new());
+        }
+        MessageMut::RequestControllerInfo(request) => {
+            if let Ok(slots) = request.slots() {
+                let slots = slots.to_vec();
+                if !slots.is_empty() {
+                    request.set_slots(&slots).unwrap();
+                }
+            }
+            request.update_crc(Crc32::new());
+        }
+        MessageMut::ControllerInfo(info) => {
+            let header = info.controller_header().clone();
+            *info.controller_header_mut() = header;
+            info.update_crc(Crc32::new());
+        }
+        MessageMut::RequestControllerData(request) => {
+            if let Ok(registration) = request.registration() {
+                request.set_registration(registration);
+            }
+            let mac = *request.mac();
+            *request.mac_mut() = mac;
+            request.update_crc(Crc32::new());
+        }
+        MessageMut::ControllerData(data) => {
+            let buttons = data.buttons();
+            data.set_buttons(buttons);
+            data.set_accel_x(data.accel_x());
+            data.set_gyro_roll(data.gyro_roll());
+            data.update_crc(Crc32::new());
+        }
+    }
+    assert_eq!(buf, data);
+});
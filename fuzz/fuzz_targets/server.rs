@@ -0,0 +1,51 @@
+#![no_main]
+
+//! Feeds datagrams to a server and checks everything it sends back is well formed.
+
+use std::net::SocketAddr;
+
+use dsu_protocol::{
+    crc::Crc32,
+    server::Server,
+    transport::{MemoryNetwork, Transport},
+    types::*,
+    ControllerData, MessageRef, MAX_MESSAGE_SIZE,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let network = MemoryNetwork::new();
+    let mut server = Server::with_transport(network.bind(([127, 0, 0, 1], 26760)).unwrap());
+    let mut client = network.bind(([127, 0, 0, 1], 0)).unwrap();
+    let from: SocketAddr = client.local_addr();
+
+    server.push(&controller_data(0)).unwrap();
+    // Several datagrams, each preceded by its length.
+    let mut rest = data;
+    while let Some((&len, tail)) = rest.split_first() {
+        let (datagram, tail) = tail.split_at((len as usize).min(tail.len()));
+        server.handle_request(datagram, from).unwrap();
+        rest = tail;
+    }
+    server.push(&controller_data(1)).unwrap();
+    server.poll().unwrap();
+
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+    while let Ok((len, _)) = client.recv_from(&mut buf) {
+        assert!(MessageRef::parse(&buf[..len], Crc32::new()).is_ok());
+    }
+});
+
+fn controller_data(slot: u8) -> ControllerData {
+    ControllerData::new(
+        0,
+        slot,
+        State::Connected,
+        Model::FullGyro,
+        ConnectionType::Usb,
+        [0, 0, 0, 0, 0, slot + 1],
+        BatteryStatus::Full,
+        true,
+        Crc32::new(),
+    )
+}
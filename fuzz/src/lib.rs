@@ -0,0 +1,106 @@
+//! Reads every field of a message, so fuzz targets reach every accessor.
+
+use std::hint::black_box;
+
+use dsu_protocol::*;
+
+pub fn read_header(header: &Header) {
+    black_box((
+        header.magic().ok(),
+        header.protocol().ok(),
+        header.packet_length(),
+        header.crc32(),
+        header.sender_id(),
+        header.message_type().ok(),
+    ));
+}
+
+pub fn read_controller_header(header: &ControllerHeader) {
+    black_box((
+        header.slot(),
+        header.state().ok(),
+        header.model().ok(),
+        header.connection_type().ok(),
+        header.mac(),
+        header.battery_status().ok(),
+    ));
+}
+
+pub fn read_touch(touch: &Touch) {
+    black_box((
+        touch.is_active(),
+        touch.touch_id(),
+        touch.touch_x(),
+        touch.touch_y(),
+    ));
+}
+
+pub fn read_protocol_version_info(info: &ProtocolVersionInfo) {
+    read_header(info.header());
+    black_box(info.protocol().ok());
+}
+
+pub fn read_request_controller_info(request: &RequestControllerInfo) {
+    read_header(request.header());
+    black_box((request.num_slots().ok(), request.slots().ok()));
+}
+
+pub fn read_controller_info(info: &ControllerInfo) {
+    read_header(info.header());
+    read_controller_header(info.controller_header());
+}
+
+pub fn read_request_controller_data(request: &RequestControllerData) {
+    read_header(request.header());
+    black_box((request.registration().ok(), request.slot(), request.mac()));
+}
+
+pub fn read_controller_data(data: &ControllerData) {
+    read_header(data.header());
+    read_controller_header(data.controller_header());
+    black_box((
+        data.is_connected(),
+        data.packet_number(),
+        data.buttons().iter().count(),
+        data.ps_button(),
+        data.touch_button(),
+        [
+            data.left_stick_x(),
+            data.left_stick_y(),
+            data.right_stick_x(),
+            data.right_stick_y(),
+        ],
+        [
+            data.analog_dpad_left(),
+            data.analog_dpad_down(),
+            data.analog_dpad_right(),
+            data.analog_dpad_up(),
+            data.analog_y(),
+            data.analog_b(),
+            data.analog_a(),
+            data.analog_x(),
+            data.analog_r1(),
+            data.analog_l1(),
+            data.analog_r2(),
+            data.analog_l2(),
+        ],
+    ));
+    read_touch(data.touch1());
+    read_touch(data.touch2());
+    black_box((
+        data.motion_timestamp(),
+        [data.accel_x(), data.accel_y(), data.accel_z()],
+        [data.gyro_pitch(), data.gyro_yaw(), data.gyro_roll()],
+    ));
+}
+
+pub fn read_message(message: &MessageRef) {
+    match message {
+        MessageRef::RequestProtocolVersionInfo(request) => read_header(request.header()),
+        MessageRef::ProtocolVersionInfo(info) => read_protocol_version_info(info),
+        MessageRef::RequestControllerInfo(request) => read_request_controller_info(request),
+        MessageRef::ControllerInfo(info) => read_controller_info(info),
+        MessageRef::RequestControllerData(request) => read_request_controller_data(request),
+        MessageRef::ControllerData(data) => read_controller_data(data),
+    }
+}
@@ -0,0 +1,160 @@
+//! Which client addresses a server answers, as lists of CIDR blocks.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::error::CidrParseError;
+
+/// A block of IPv4 or IPv6 addresses like `192.168.1.0/24` or `fd00::/8`. A bare address is a
+/// block of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Returns `None` if `prefix` is longer than the address. Bits after the prefix are
+    /// cleared.
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let addr = match addr {
+            IpAddr::V4(addr) if prefix <= 32 => IpAddr::V4(mask_v4(addr, prefix)),
+            IpAddr::V6(addr) if prefix <= 128 => IpAddr::V6(mask_v6(addr, prefix)),
+            _ => return None,
+        };
+        Some(Cidr { addr, prefix })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` is in the block. IPv4-mapped IPv6 addresses are treated as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(block), IpAddr::V4(ip)) => mask_v4(ip, self.prefix) == block,
+            (IpAddr::V6(block), IpAddr::V6(ip)) => mask_v6(ip, self.prefix) == block,
+            _ => false,
+        }
+    }
+}
+
+fn mask_v4(addr: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    Ipv4Addr::from(u32::from(addr) & mask)
+}
+
+fn mask_v6(addr: Ipv6Addr, prefix: u8) -> Ipv6Addr {
+    let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+    Ipv6Addr::from(u128::from(addr) & mask)
+}
+
+impl FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CidrParseError(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Allow and deny lists of CIDR blocks.
+///
+/// An address is allowed if it isn't in any denied block and either the allow list is empty or
+/// it is in an allowed block. The default allows everyone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessList {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|block| block.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|block| block.contains(ip)))
+    }
+
+    /// Whether everyone is allowed.
+    pub fn is_open(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let block: Cidr = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(block.to_string(), "192.168.1.0/24");
+        assert_eq!("10.0.0.1".parse::<Cidr>().unwrap().prefix(), 32);
+        assert_eq!(
+            "fd00::1:2/16".parse::<Cidr>().unwrap().to_string(),
+            "fd00::/16"
+        );
+        assert_eq!("::1".parse::<Cidr>().unwrap().prefix(), 128);
+        assert_eq!("0.0.0.0/0".parse::<Cidr>().unwrap().prefix(), 0);
+        for invalid in ["10.0.0.0/33", "::/129", "10.0.0/8", "10.0.0.0/", "host/8"] {
+            assert!(invalid.parse::<Cidr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn contains() {
+        let block: Cidr = "192.168.1.0/24".parse().unwrap();
+        assert!(block.contains(ip("192.168.1.200")));
+        assert!(!block.contains(ip("192.168.2.1")));
+        assert!(block.contains(ip("::ffff:192.168.1.5")));
+        assert!(!block.contains(ip("fd00::1")));
+
+        let block: Cidr = "fe80::/10".parse().unwrap();
+        assert!(block.contains(ip("fe80::1234")));
+        assert!(block.contains(ip("febf::1")));
+        assert!(!block.contains(ip("fec0::1")));
+
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!(!"0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("::2")));
+    }
+
+    #[test]
+    fn access_list() {
+        let mut access = AccessList::default();
+        assert!(access.allows(ip("203.0.113.9")));
+
+        access.allow = vec!["192.168.1.0/24".parse().unwrap(), "::1".parse().unwrap()];
+        access.deny = vec!["192.168.1.13".parse().unwrap()];
+        assert!(access.allows(ip("192.168.1.12")));
+        assert!(!access.allows(ip("192.168.1.13")));
+        assert!(!access.allows(ip("192.168.2.12")));
+        assert!(access.allows(ip("::1")));
+        assert!(!access.allows(ip("::2")));
+    }
+}
@@ -0,0 +1,373 @@
+//! [`proptest`] strategies for the protocol's types, behind the `proptest` feature.
+//!
+//! Generated messages are always valid: enums hold known values, slots are below 4 and CRCs
+//! are correct, so they parse back to themselves.
+
+use proptest::{num::f32 as float, prelude::*};
+
+use crate::{crc::Crc32, types::*, *};
+
+/// Every slot a server can report.
+const SLOTS: core::ops::Range<u8> = 0..4;
+
+macro_rules! arbitrary {
+    ($name:ty, $strategy:expr) => {
+        impl Arbitrary for $name {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                $strategy.boxed()
+            }
+        }
+    };
+}
+
+macro_rules! arbitrary_enum {
+    ($name:ident { $($variant:ident),* $(,)? }) => {
+        arbitrary!($name, prop_oneof![$(Just($name::$variant)),*]);
+    };
+}
+
+arbitrary_enum!(Magic { Server, Client });
+arbitrary_enum!(Protocol { Version1001 });
+arbitrary_enum!(MessageType {
+    ProtocolVersionInfo,
+    ControllerInfo,
+    ControllerData,
+});
+arbitrary_enum!(State {
+    Disconnected,
+    Reserved,
+    Connected,
+});
+arbitrary_enum!(Model {
+    NotApplicable,
+    PartialGyro,
+    FullGyro,
+    Unused,
+});
+arbitrary_enum!(ConnectionType {
+    NotApplicable,
+    Usb,
+    Bluetooth,
+});
+arbitrary_enum!(BatteryStatus {
+    NotApplicable,
+    Dying,
+    Low,
+    Medium,
+    High,
+    Full,
+    Charging,
+    Charged,
+});
+arbitrary_enum!(Registration {
+    AllControllers,
+    SlotBased,
+    MacBased,
+});
+
+arbitrary!(Button, proptest::sample::select(&Button::ALL[..]));
+arbitrary!(Buttons, any::<[u8; 2]>().prop_map(Buttons));
+
+arbitrary!(
+    Touch,
+    (any::<bool>(), any::<u8>(), any::<u16>(), any::<u16>()).prop_map(|(active, id, x, y)| {
+        let mut touch = Touch { bytes: [0; 6] };
+        touch.set_active(active);
+        touch.set_touch_id(id);
+        touch.set_touch_x(x);
+        touch.set_touch_y(y);
+        touch
+    })
+);
+
+arbitrary!(
+    ControllerHeader,
+    (
+        SLOTS,
+        any::<State>(),
+        any::<Model>(),
+        any::<ConnectionType>(),
+        any::<[u8; 6]>(),
+        any::<BatteryStatus>(),
+    )
+        .prop_map(
+            |(slot, state, model, connection_type, mac, battery_status)| {
+                let mut header = ControllerHeader { bytes: [0; 11] };
+                header.initialize(slot, state, model, connection_type, mac, battery_status);
+                header
+            }
+        )
+);
+
+arbitrary!(
+    RequestProtocolVersionInfo,
+    any::<u32>().prop_map(|sender_id| RequestProtocolVersionInfo::new(sender_id, Crc32::new()))
+);
+
+arbitrary!(
+    ProtocolVersionInfo,
+    (any::<u32>(), any::<Protocol>()).prop_map(|(sender_id, protocol)| ProtocolVersionInfo::new(
+        sender_id,
+        protocol,
+        Crc32::new()
+    ))
+);
+
+arbitrary!(
+    RequestControllerInfo,
+    (any::<u32>(), proptest::collection::vec(SLOTS, 1..=4)).prop_map(|(sender_id, slots)| {
+        RequestControllerInfo::new(sender_id, &slots, Crc32::new()).unwrap()
+    })
+);
+
+arbitrary!(
+    ControllerInfo,
+    (any::<u32>(), any::<ControllerHeader>()).prop_map(|(sender_id, header)| {
+        let mut info = ControllerInfo {
+            bytes: [0; ControllerInfo::SIZE],
+        };
+        info.header_mut().initialize(
+            Magic::Server,
+            Protocol::Version1001,
+            (ControllerInfo::SIZE - 16) as u16,
+            0,
+            sender_id,
+            MessageType::ControllerInfo,
+        );
+        *info.controller_header_mut() = header;
+        info.update_crc(Crc32::new());
+        info
+    })
+);
+
+arbitrary!(
+    RequestControllerData,
+    (any::<u32>(), any::<Registration>(), SLOTS, any::<[u8; 6]>()).prop_map(
+        |(sender_id, registration, slot, mac)| {
+            RequestControllerData::new(sender_id, registration, slot, mac, Crc32::new())
+        }
+    )
+);
+
+arbitrary!(
+    ControllerData,
+    (
+        any::<u32>(),
+        any::<ControllerHeader>(),
+        any::<bool>(),
+        any::<u32>(),
+        any::<Buttons>(),
+        any::<[u8; 18]>(),
+        [any::<Touch>(), any::<Touch>()],
+        any::<u64>(),
+        [motion(), motion(), motion(), motion(), motion(), motion()],
+    )
+        .prop_map(
+            |(
+                sender_id,
+                header,
+                connected,
+                packet_number,
+                buttons,
+                inputs,
+                touches,
+                timestamp,
+                motion,
+            )| {
+                let mut data = ControllerData {
+                    bytes: [0; ControllerData::SIZE],
+                };
+                data.header_mut().initialize(
+                    Magic::Server,
+                    Protocol::Version1001,
+                    (ControllerData::SIZE - 16) as u16,
+                    0,
+                    sender_id,
+                    MessageType::ControllerData,
+                );
+                *data.controller_header_mut() = header;
+                data.set_connected(connected);
+                data.set_packet_number(packet_number);
+                data.set_buttons(buttons);
+                // The PS and touch buttons, the sticks and the analog buttons.
+                data.bytes[38..56].copy_from_slice(&inputs);
+                let [touch1, touch2] = touches;
+                *data.touch1_mut() = touch1;
+                *data.touch2_mut() = touch2;
+                data.set_motion_timestamp(timestamp);
+                let [accel_x, accel_y, accel_z, pitch, yaw, roll] = motion;
+                data.set_accel_x(accel_x);
+                data.set_accel_y(accel_y);
+                data.set_accel_z(accel_z);
+                data.set_gyro_pitch(pitch);
+                data.set_gyro_yaw(yaw);
+                data.set_gyro_roll(roll);
+                data.update_crc(Crc32::new());
+                data
+            }
+        )
+);
+
+/// Finite motion values.
+fn motion() -> impl Strategy<Value = f32> {
+    float::NORMAL | float::SUBNORMAL | float::ZERO
+}
+
+/// The bytes of any valid message.
+pub fn message() -> BoxedStrategy<Vec<u8>> {
+    prop_oneof![
+        any::<RequestProtocolVersionInfo>().prop_map(|m| m.bytes.to_vec()),
+        any::<ProtocolVersionInfo>().prop_map(|m| m.bytes.to_vec()),
+        any::<RequestControllerInfo>().prop_map(|m| m.bytes.to_vec()),
+        any::<ControllerInfo>().prop_map(|m| m.bytes.to_vec()),
+        any::<RequestControllerData>().prop_map(|m| m.bytes.to_vec()),
+        any::<ControllerData>().prop_map(|m| m.bytes.to_vec()),
+    ]
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes<'a>(message: &'a MessageRef) -> &'a [u8] {
+        match message {
+            MessageRef::RequestProtocolVersionInfo(m) => &m.bytes,
+            MessageRef::ProtocolVersionInfo(m) => &m.bytes,
+            MessageRef::RequestControllerInfo(m) => &m.bytes,
+            MessageRef::ControllerInfo(m) => &m.bytes,
+            MessageRef::RequestControllerData(m) => &m.bytes,
+            MessageRef::ControllerData(m) => &m.bytes,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn messages_parse(message in message()) {
+            let parsed = MessageRef::parse(&message, Crc32::new()).unwrap();
+            prop_assert_eq!(bytes(&parsed), &message[..]);
+            let mut copy = message.clone();
+            prop_assert!(MessageMut::parse_mut(&mut copy, Crc32::new()).is_ok());
+        }
+
+        #[test]
+        fn controller_info_round_trip(
+            sender_id in any::<u32>(),
+            slot in SLOTS,
+            state in any::<State>(),
+            model in any::<Model>(),
+            connection_type in any::<ConnectionType>(),
+            mac in any::<[u8; 6]>(),
+            battery_status in any::<BatteryStatus>(),
+        ) {
+            let built = ControllerInfo::new(
+                sender_id, slot, state, model, connection_type, mac, battery_status, Crc32::new(),
+            );
+            let info = match MessageRef::parse(&built.bytes, Crc32::new()).unwrap() {
+                MessageRef::ControllerInfo(info) => info,
+                _ => panic!("wrong message type"),
+            };
+            prop_assert_eq!(info.header().sender_id(), sender_id);
+            let header = info.controller_header();
+            prop_assert_eq!(header.slot(), slot);
+            prop_assert_eq!(header.state().ok(), Some(state));
+            prop_assert_eq!(header.model().ok(), Some(model));
+            prop_assert_eq!(header.connection_type().ok(), Some(connection_type));
+            prop_assert_eq!(header.mac(), &mac);
+            prop_assert_eq!(header.battery_status().ok(), Some(battery_status));
+        }
+
+        #[test]
+        fn request_round_trip(
+            sender_id in any::<u32>(),
+            slots in proptest::collection::vec(any::<u8>(), 1..=4),
+            registration in any::<Registration>(),
+            slot in any::<u8>(),
+            mac in any::<[u8; 6]>(),
+        ) {
+            let built = RequestControllerInfo::new(sender_id, &slots, Crc32::new()).unwrap();
+            match MessageRef::parse(&built.bytes, Crc32::new()).unwrap() {
+                MessageRef::RequestControllerInfo(request) => {
+                    prop_assert_eq!(request.header().sender_id(), sender_id);
+                    prop_assert_eq!(request.slots().unwrap(), &slots[..]);
+                }
+                _ => panic!("wrong message type"),
+            }
+
+            let built = RequestControllerData::new(sender_id, registration, slot, mac, Crc32::new());
+            match MessageRef::parse(&built.bytes, Crc32::new()).unwrap() {
+                MessageRef::RequestControllerData(request) => {
+                    prop_assert_eq!(request.header().sender_id(), sender_id);
+                    prop_assert_eq!(request.registration().ok(), Some(registration));
+                    prop_assert_eq!(request.slot(), slot);
+                    prop_assert_eq!(request.mac(), &mac);
+                }
+                _ => panic!("wrong message type"),
+            }
+        }
+
+        #[test]
+        fn controller_data_round_trip(
+            header in any::<ControllerHeader>(),
+            connected in any::<bool>(),
+            packet_number in any::<u32>(),
+            buttons in proptest::collection::vec(any::<Button>(), 0..16),
+            sticks in any::<[u8; 4]>(),
+            touch in any::<Touch>(),
+            timestamp in any::<u64>(),
+            // Any bits, NaNs included.
+            accel in any::<[u32; 3]>(),
+        ) {
+            let mut built = ControllerData::new(
+                1,
+                header.slot(),
+                header.state().unwrap(),
+                header.model().unwrap(),
+                header.connection_type().unwrap(),
+                *header.mac(),
+                header.battery_status().unwrap(),
+                connected,
+                Crc32::new(),
+            );
+            built.set_packet_number(packet_number);
+            built.set_buttons(buttons.iter().fold(Buttons::new(), |all, &button| all | button));
+            built.set_left_stick_x(sticks[0]);
+            built.set_left_stick_y(sticks[1]);
+            built.set_right_stick_x(sticks[2]);
+            built.set_right_stick_y(sticks[3]);
+            *built.touch2_mut() = touch.clone();
+            built.set_motion_timestamp(timestamp);
+            built.set_accel_x(f32::from_bits(accel[0]));
+            built.set_accel_y(f32::from_bits(accel[1]));
+            built.set_accel_z(f32::from_bits(accel[2]));
+            built.update_crc(Crc32::new());
+
+            let data = match MessageRef::parse(&built.bytes, Crc32::new()).unwrap() {
+                MessageRef::ControllerData(data) => data,
+                _ => panic!("wrong message type"),
+            };
+            prop_assert_eq!(&data.controller_header().bytes, &header.bytes);
+            prop_assert_eq!(data.is_connected(), connected);
+            prop_assert_eq!(data.packet_number(), packet_number);
+            for button in Button::ALL {
+                prop_assert_eq!(data.buttons().contains(button), buttons.contains(&button));
+            }
+            prop_assert_eq!(
+                [data.left_stick_x(), data.left_stick_y(), data.right_stick_x(), data.right_stick_y()],
+                sticks
+            );
+            prop_assert_eq!(data.touch2().is_active(), touch.is_active());
+            prop_assert_eq!(data.touch2().touch_id(), touch.touch_id());
+            prop_assert_eq!(data.touch2().touch_x(), touch.touch_x());
+            prop_assert_eq!(data.touch2().touch_y(), touch.touch_y());
+            prop_assert_eq!(data.motion_timestamp(), timestamp);
+            prop_assert_eq!(
+                [data.accel_x().to_bits(), data.accel_y().to_bits(), data.accel_z().to_bits()],
+                accel
+            );
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! An opt-in extension that authenticates DSU packets with a shared key.
+//!
+//! Each datagram is an ordinary DSU packet, CRC included, followed by a trailer:
+//!
+//! | Offset | Size | Field                                                      |
+//! |--------|------|------------------------------------------------------------|
+//! | 0      | 4    | [`AUTH_MAGIC`]                                             |
+//! | 4      | 8    | counter, higher than any the sender used before            |
+//! | 12     | 16   | HMAC-SHA256 of the packet, magic and counter, truncated    |
+//!
+//! Receivers drop datagrams without a valid tag, and datagrams whose counter isn't above the
+//! last one accepted with the same sender id, so captured packets can't be replayed from any
+//! address. The sender id is in the signed header, so it can't be changed to start a fresh
+//! count. Peers sharing a key need distinct sender ids, which the crate's clients pick at
+//! random. Counters start at the current time in microseconds so they keep rising across
+//! restarts.
+//!
+//! Both sides wrap their transport in an [`AuthTransport`] with the same [`Key`]. A server
+//! that should also serve plain DSU clients can let unsigned packets through with
+//! [`AuthTransport::set_allow_unsigned`], answering them unsigned as well. Anyone can then
+//! talk to it as a plain client, so only signed peers are protected from injected packets.
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fmt, io,
+    net::UdpSocket,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{
+    error::AuthError, server::SUBSCRIPTION_TIMEOUT, transport::Transport, BufType, Header,
+    MAX_MESSAGE_SIZE,
+};
+
+/// "DSUA" in little-endian, marking an authenticated packet's trailer.
+pub const AUTH_MAGIC: u32 = 0x41555344;
+
+pub const TAG_SIZE: usize = 16;
+
+pub const TRAILER_SIZE: usize = 12 + TAG_SIZE;
+
+/// Size of the largest authenticated datagram.
+pub const MAX_DATAGRAM_SIZE: usize = MAX_MESSAGE_SIZE + TRAILER_SIZE;
+
+/// Plain peers remembered by an [`AuthTransport`] letting unsigned packets through. Once
+/// there are more, ones not heard from in [`SUBSCRIPTION_TIMEOUT`] are forgotten.
+pub const MAX_PLAIN_PEERS: usize = 1024;
+
+/// A key shared by a server and its clients.
+#[derive(Clone)]
+pub struct Key {
+    mac: Hmac<Sha256>,
+}
+
+impl Key {
+    /// Keys of any length work, but should be at least 16 random bytes.
+    pub fn new(secret: &[u8]) -> Self {
+        Key {
+            mac: Hmac::new_from_slice(secret).expect("HMAC accepts keys of any length"),
+        }
+    }
+
+    fn mac(&self, packet: &[u8], head: &[u8]) -> Hmac<Sha256> {
+        let mut mac = self.mac.clone();
+        mac.update(packet);
+        mac.update(head);
+        mac
+    }
+
+    /// Returns the trailer to send after `packet`, which should already have its CRC.
+    pub fn sign(&self, packet: &[u8], counter: u64) -> [u8; TRAILER_SIZE] {
+        let mut trailer = [0u8; TRAILER_SIZE];
+        trailer[0..4].copy_from_slice(&AUTH_MAGIC.to_le_bytes());
+        trailer[4..12].copy_from_slice(&counter.to_le_bytes());
+        let tag = self.mac(packet, &trailer[0..12]).finalize().into_bytes();
+        trailer[12..].copy_from_slice(&tag[..TAG_SIZE]);
+        trailer
+    }
+
+    /// Checks the trailer of `datagram` and returns the packet before it and its counter.
+    ///
+    /// The packet itself isn't parsed, so its CRC and length are still to be checked.
+    pub fn verify<'a>(&self, datagram: &'a [u8]) -> Result<(&'a [u8], u64), AuthError> {
+        let len = datagram.len();
+        if len < Header::SIZE + TRAILER_SIZE {
+            return Err(AuthError::TooShort(len));
+        }
+        let (packet, trailer) = datagram.split_at(len - TRAILER_SIZE);
+        let magic = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+        if magic != AUTH_MAGIC {
+            return Err(AuthError::InvalidMagic(magic));
+        }
+        self.mac(packet, &trailer[0..12])
+            .verify_truncated_left(&trailer[12..])
+            .map_err(|_| AuthError::InvalidTag)?;
+        let counter = u64::from_le_bytes(trailer[4..12].try_into().unwrap());
+        Ok((packet, counter))
+    }
+}
+
+/// Never shows the key.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// A transport that signs every packet it sends and only receives packets signed with the
+/// same key.
+///
+/// Rejected datagrams are skipped and counted. The last counter is remembered for every
+/// sender id a valid packet came with.
+pub struct AuthTransport<T: Transport = UdpSocket> {
+    inner: T,
+    key: Key,
+    counter: u64,
+    last_counters: HashMap<u32, u64>,
+    allow_unsigned: bool,
+    /// Addresses whose last packet was unsigned, and when it arrived.
+    plain_peers: HashMap<T::Addr, Instant>,
+    rejected: u64,
+}
+
+impl<T: Transport> AuthTransport<T> {
+    pub fn new(inner: T, key: Key) -> Self {
+        AuthTransport {
+            inner,
+            key,
+            counter: 0,
+            last_counters: HashMap::new(),
+            allow_unsigned: false,
+            plain_peers: HashMap::new(),
+            rejected: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Lets packets without a trailer through, for serving plain DSU clients alongside signed
+    /// ones. Packets sent to an address whose last packet was unsigned aren't signed either.
+    ///
+    /// Off by default. Meant for servers: a client letting unsigned packets through accepts
+    /// controller data from anyone who can spoof the server's address.
+    pub fn set_allow_unsigned(&mut self, allow: bool) {
+        self.allow_unsigned = allow;
+        if !allow {
+            self.plain_peers.clear();
+        }
+    }
+
+    pub fn allows_unsigned(&self) -> bool {
+        self.allow_unsigned
+    }
+
+    /// Number of datagrams dropped for not being signed with the key or being replayed.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    fn next_counter(&mut self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        self.counter = now.max(self.counter + 1);
+        self.counter
+    }
+
+    fn accept<'a>(&mut self, datagram: &'a [u8], from: &T::Addr) -> Result<&'a [u8], AuthError> {
+        let (packet, counter) = match self.key.verify(datagram) {
+            Err(AuthError::TooShort(_) | AuthError::InvalidMagic(_)) if self.allow_unsigned => {
+                self.remember_plain(from);
+                return Ok(datagram);
+            }
+            verified => verified?,
+        };
+        let sender_id = u32::from_le_bytes(packet[12..16].try_into().unwrap());
+        let last = self.last_counters.entry(sender_id).or_insert(0);
+        if counter <= *last {
+            return Err(AuthError::Replayed {
+                counter,
+                last: *last,
+            });
+        }
+        *last = counter;
+        self.plain_peers.remove(from);
+        Ok(packet)
+    }
+
+    fn remember_plain(&mut self, from: &T::Addr) {
+        let now = Instant::now();
+        if self.plain_peers.len() >= MAX_PLAIN_PEERS && !self.plain_peers.contains_key(from) {
+            self.plain_peers
+                .retain(|_, seen| now.saturating_duration_since(*seen) < SUBSCRIPTION_TIMEOUT);
+            if self.plain_peers.len() >= MAX_PLAIN_PEERS {
+                return;
+            }
+        }
+        self.plain_peers.insert(from.clone(), now);
+    }
+}
+
+impl<T: Transport> Transport for AuthTransport<T> {
+    type Addr = T::Addr;
+
+    /// Reports the packet's length, not counting the trailer.
+    fn send_to(&mut self, bytes: &[u8], to: &T::Addr) -> io::Result<usize> {
+        if bytes.len() > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet is larger than any DSU message",
+            ));
+        }
+        if self.plain_peers.contains_key(to) {
+            return self.inner.send_to(bytes, to);
+        }
+        let counter = self.next_counter();
+        let mut datagram = [0u8; MAX_DATAGRAM_SIZE];
+        let len = bytes.len() + TRAILER_SIZE;
+        datagram[..bytes.len()].copy_from_slice(bytes);
+        datagram[bytes.len()..len].copy_from_slice(&self.key.sign(bytes, counter));
+        let sent = self.inner.send_to(&datagram[..len], to)?;
+        Ok(sent.saturating_sub(TRAILER_SIZE))
+    }
+
+    /// Receives the next authenticated packet, without its trailer.
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, T::Addr)> {
+        let mut datagram = [0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            let (len, from) = self.inner.recv_from(&mut datagram)?;
+            match self.accept(&datagram[..len], &from) {
+                Ok(packet) => {
+                    let len = packet.len().min(buf.len());
+                    buf[..len].copy_from_slice(&packet[..len]);
+                    return Ok((len, from));
+                }
+                Err(err) => {
+                    self.rejected += 1;
+                    log::debug!("dropping datagram from {:?}: {}", from, err);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{
+        client::Client,
+        crc::Crc32,
+        server::{Limits, Server},
+        transport::{MemoryNetwork, MemoryTransport},
+        types::*,
+        *,
+    };
+
+    const SERVER: ([u8; 4], u16) = ([127, 0, 0, 1], 26760);
+
+    fn key() -> Key {
+        Key::new(b"0123456789abcdef")
+    }
+
+    fn signed(packet: &[u8], counter: u64) -> Vec<u8> {
+        let mut datagram = packet.to_vec();
+        datagram.extend_from_slice(&key().sign(packet, counter));
+        datagram
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let packet = RequestProtocolVersionInfo::new(1, Crc32::new());
+        let datagram = signed(&packet.bytes, 7);
+        assert_eq!(datagram.len(), 20 + TRAILER_SIZE);
+        assert_eq!(key().verify(&datagram), Ok((&packet.bytes[..], 7)));
+
+        for bit in 0..datagram.len() * 8 {
+            let mut flipped = datagram.clone();
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert!(key().verify(&flipped).is_err(), "bit {}", bit);
+        }
+        assert_eq!(
+            Key::new(b"another key").verify(&datagram),
+            Err(AuthError::InvalidTag)
+        );
+        assert_eq!(key().verify(&packet.bytes), Err(AuthError::TooShort(20)));
+        let plain = ControllerData::new(
+            1,
+            0,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [0; 6],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        );
+        assert!(matches!(
+            key().verify(&plain.bytes),
+            Err(AuthError::InvalidMagic(_))
+        ));
+    }
+
+    #[test]
+    fn replays_are_rejected() {
+        let network = MemoryNetwork::new();
+        let mut receiver = AuthTransport::new(network.bind(SERVER).unwrap(), key());
+        let mut sender = network.bind(([127, 0, 0, 1], 0)).unwrap();
+        let mut elsewhere = network.bind(([127, 0, 0, 2], 0)).unwrap();
+        let packet = RequestProtocolVersionInfo::new(1, Crc32::new());
+        let other = RequestProtocolVersionInfo::new(2, Crc32::new());
+        let server: SocketAddr = SERVER.into();
+        for counter in [5, 5, 4, 6] {
+            sender
+                .send_to(&signed(&packet.bytes, counter), &server)
+                .unwrap();
+        }
+        // Captured packets don't start a new count from another address, but another sender
+        // id has its own.
+        elsewhere
+            .send_to(&signed(&packet.bytes, 6), &server)
+            .unwrap();
+        elsewhere
+            .send_to(&signed(&other.bytes, 1), &server)
+            .unwrap();
+
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let mut received = 0;
+        while receiver.recv_from(&mut buf).is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, 3);
+        assert_eq!(receiver.rejected(), 3);
+    }
+
+    #[test]
+    fn unsigned_allowed() {
+        let network = MemoryNetwork::new();
+        let mut transport = AuthTransport::new(network.bind(SERVER).unwrap(), key());
+        transport.set_allow_unsigned(true);
+        let mut server = Server::with_transport(transport);
+        server.set_limits(Limits::NONE);
+        let mut plain =
+            Client::with_transport(network.bind(([127, 0, 0, 1], 0)).unwrap(), SERVER.into());
+        let mut wrong = client(&network, Key::new(b"not the key"));
+        let mut right = client(&network, key());
+        for client in [&mut wrong, &mut right] {
+            client
+                .subscribe(Registration::AllControllers, 0, [0; 6])
+                .unwrap();
+        }
+        plain
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        server.poll().unwrap();
+        assert_eq!(server.subscriber_count(), 2);
+        assert_eq!(server.transport().rejected(), 1);
+
+        let data = ControllerData::new(
+            0,
+            0,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [1; 6],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        );
+        server.push(&data).unwrap();
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        assert!(matches!(
+            plain.try_recv(&mut buf).unwrap(),
+            Some(MessageRef::ControllerData(_))
+        ));
+        assert!(matches!(
+            right.try_recv(&mut buf).unwrap(),
+            Some(MessageRef::ControllerData(_))
+        ));
+    }
+
+    fn client(network: &MemoryNetwork, key: Key) -> Client<AuthTransport<MemoryTransport>> {
+        let transport = network.bind(([127, 0, 0, 1], 0)).unwrap();
+        Client::with_transport(AuthTransport::new(transport, key), SERVER.into())
+    }
+
+    #[test]
+    fn server_and_client() {
+        let network = MemoryNetwork::new();
+        let mut server =
+            Server::with_transport(AuthTransport::new(network.bind(SERVER).unwrap(), key()));
+        server.set_limits(Limits::NONE);
+        let mut plain =
+            Client::with_transport(network.bind(([127, 0, 0, 1], 0)).unwrap(), SERVER.into());
+        let mut wrong = client(&network, Key::new(b"not the key"));
+        let mut right = client(&network, key());
+
+        plain.request_protocol_version().unwrap();
+        plain
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        wrong.request_protocol_version().unwrap();
+        wrong
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        right.request_protocol_version().unwrap();
+        right
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        server.poll().unwrap();
+        assert_eq!(server.subscriber_count(), 1);
+        assert_eq!(server.transport().rejected(), 4);
+
+        let data = ControllerData::new(
+            0,
+            2,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [1; 6],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        );
+        assert_eq!(server.push(&data).unwrap(), 1);
+
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        assert!(matches!(
+            right.try_recv(&mut buf).unwrap(),
+            Some(MessageRef::ProtocolVersionInfo(_))
+        ));
+        match right.try_recv(&mut buf).unwrap() {
+            Some(MessageRef::ControllerData(data)) => {
+                assert_eq!(data.controller_header().slot(), 2)
+            }
+            _ => panic!("expected controller data"),
+        }
+        assert!(right.try_recv(&mut buf).unwrap().is_none());
+        assert!(plain.try_recv(&mut buf).unwrap().is_none());
+    }
+}
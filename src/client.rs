@@ -0,0 +1,236 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    crc::Crc32,
+    error::*,
+    transport::{bind_udp, RecvBatch, Transport},
+    types::*,
+    *,
+};
+
+pub const DEFAULT_PORT: u16 = 26760;
+
+/// How often subscriptions are renewed. Servers drop clients they haven't heard from in a
+/// few seconds.
+pub const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subscription {
+    pub registration: Registration,
+    pub slot: u8,
+    pub mac: [u8; 6],
+}
+
+/// A DSU client talking to a single server.
+///
+/// Subscriptions made with [`Client::subscribe`] are renewed automatically while waiting in
+/// [`Client::recv`]. When the server has been silent since the last renewal, the renewal
+/// starts with a protocol version request too, so servers that only accept subscriptions
+/// after a handshake take the client back after restarting. Datagrams from anyone but the
+/// server are ignored.
+pub struct Client<T: Transport = UdpSocket> {
+    transport: T,
+    server: T::Addr,
+    id: u32,
+    subscriptions: Vec<Subscription>,
+    last_subscribe: Option<Instant>,
+    last_heard: Option<Instant>,
+}
+
+impl Client {
+    /// Binds to any port of the server's address family, dual-stack for IPv6.
+    pub fn connect<A: ToSocketAddrs>(server: A) -> io::Result<Self> {
+        let server = server.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no server address given")
+        })?;
+        let local: SocketAddr = if server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = bind_udp(local)?;
+        socket.set_read_timeout(Some(RESUBSCRIBE_INTERVAL))?;
+        Ok(Client::with_transport(socket, server))
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.transport
+    }
+}
+
+impl<T: Transport> Client<T> {
+    /// Talks to `server` over `transport`. A transport that blocks should time out at least
+    /// every [`RESUBSCRIBE_INTERVAL`] so subscriptions are renewed.
+    pub fn with_transport(transport: T, server: T::Addr) -> Self {
+        Client {
+            transport,
+            server,
+            id: random_id(),
+            subscriptions: Vec::new(),
+            last_subscribe: None,
+            last_heard: None,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn server(&self) -> &T::Addr {
+        &self.server
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn subscriptions(&self) -> &[Subscription] {
+        &self.subscriptions
+    }
+
+    pub fn request_protocol_version(&mut self) -> io::Result<()> {
+        let request = RequestProtocolVersionInfo::new(self.id, Crc32::new());
+        self.send(&request.bytes)
+    }
+
+    pub fn request_controller_info(&mut self, slots: &[u8]) -> io::Result<()> {
+        let request = RequestControllerInfo::new(self.id, slots, Crc32::new())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.send(&request.bytes)
+    }
+
+    /// Subscribes to controller data and sends the request immediately.
+    pub fn subscribe(&mut self, registration: Registration, slot: u8, mac: [u8; 6]) -> io::Result<()> {
+        let subscription = Subscription {
+            registration,
+            slot,
+            mac,
+        };
+        if !self.subscriptions.contains(&subscription) {
+            self.subscriptions.push(subscription);
+        }
+        send_subscription(&mut self.transport, &self.server, self.id, &subscription)
+    }
+
+    /// Stops renewing subscriptions. The server will stop sending data once they time out.
+    pub fn unsubscribe_all(&mut self) {
+        self.subscriptions.clear();
+    }
+
+    /// Renews all subscriptions if [`RESUBSCRIBE_INTERVAL`] has passed since the last renewal,
+    /// requesting the protocol version first if nothing was heard from the server since.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        let due = self
+            .last_subscribe
+            .is_none_or(|last| last.elapsed() >= RESUBSCRIBE_INTERVAL);
+        if !due {
+            return Ok(());
+        }
+        let silent = self
+            .last_heard
+            .is_none_or(|heard| heard.elapsed() >= RESUBSCRIBE_INTERVAL);
+        self.last_subscribe = Some(Instant::now());
+        if silent && !self.subscriptions.is_empty() {
+            self.request_protocol_version()?;
+        }
+        for subscription in &self.subscriptions {
+            send_subscription(&mut self.transport, &self.server, self.id, subscription)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the next message from the server, renewing subscriptions while waiting.
+    pub fn recv<'a>(&mut self, buf: &'a mut [u8; MAX_MESSAGE_SIZE]) -> Result<MessageRef<'a>, RecvError> {
+        let len = loop {
+            self.keep_alive()?;
+            match self.transport.recv_from(buf) {
+                Ok((len, from)) if from == self.server => break len,
+                Ok(_) => continue,
+                Err(err) if is_timeout(&err) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        };
+        self.last_heard = Some(Instant::now());
+        Ok(MessageRef::parse(&buf[..len], Crc32::new())?)
+    }
+
+    /// Renews subscriptions if due and returns a message if one arrived, waiting at most the
+    /// transport's read timeout. Doesn't wait at all if the transport is nonblocking.
+    pub fn try_recv<'a>(
+        &mut self,
+        buf: &'a mut [u8; MAX_MESSAGE_SIZE],
+    ) -> Result<Option<MessageRef<'a>>, RecvError> {
+        self.keep_alive()?;
+        let len = loop {
+            match self.transport.recv_from(buf) {
+                Ok((len, from)) if from == self.server => break len,
+                Ok(_) => continue,
+                Err(err) if is_timeout(&err) => return Ok(None),
+                Err(err) => return Err(err.into()),
+            }
+        };
+        self.last_heard = Some(Instant::now());
+        Ok(Some(MessageRef::parse(&buf[..len], Crc32::new())?))
+    }
+
+    /// Like [`Client::try_recv`], but receives every waiting datagram that fits in `batch`
+    /// with one call to [`Transport::recv_batch`], and returns how many came from the server.
+    /// Others are dropped. Read them with [`RecvBatch::messages`].
+    pub fn try_recv_batch(&mut self, batch: &mut RecvBatch<T::Addr>) -> io::Result<usize> {
+        self.keep_alive()?;
+        match self.transport.recv_batch(batch) {
+            Ok(_) => {}
+            Err(err) if is_timeout(&err) => {
+                batch.clear();
+                return Ok(0);
+            }
+            Err(err) => return Err(err),
+        }
+        let server = &self.server;
+        batch.retain(|from| from == server);
+        if !batch.is_empty() {
+            self.last_heard = Some(Instant::now());
+        }
+        Ok(batch.len())
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.transport.send_to(bytes, &self.server)?;
+        Ok(())
+    }
+}
+
+fn send_subscription<T: Transport>(
+    transport: &mut T,
+    server: &T::Addr,
+    id: u32,
+    subscription: &Subscription,
+) -> io::Result<()> {
+    let request = RequestControllerData::new(
+        id,
+        subscription.registration,
+        subscription.slot,
+        subscription.mac,
+        Crc32::new(),
+    );
+    transport.send_to(&request.bytes, server)?;
+    Ok(())
+}
+
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+pub(crate) fn random_id() -> u32 {
+    RandomState::new().hash_one(Instant::now()) as u32
+}
@@ -0,0 +1,741 @@
+//! Checking a DSU server against what clients like Cemuhook expect.
+//!
+//! [`run`] drives a server through the handshake and each kind of subscription and returns a
+//! [`Report`] of the checks that passed and failed. Every response is checked for its size,
+//! `packet_length`, CRC, magic and protocol version.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{is_timeout, random_id, RESUBSCRIBE_INTERVAL},
+    crc::Crc32,
+    server::{SLOTS, SUBSCRIPTION_TIMEOUT},
+    transport::Transport,
+    types::*,
+    *,
+};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long to wait for responses to requests.
+    pub response_timeout: Duration,
+    /// How long to collect controller data for each kind of subscription.
+    pub data_duration: Duration,
+    /// Whether to check that subscriptions time out, which takes a few seconds.
+    pub check_timeout: bool,
+    /// How much later than [`SUBSCRIPTION_TIMEOUT`] data may keep coming after the last
+    /// renewal.
+    pub timeout_grace: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            response_timeout: Duration::from_secs(1),
+            data_duration: Duration::from_secs(2),
+            check_timeout: true,
+            timeout_grace: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Pass(String),
+    Fail(String),
+    /// Not checked, usually because no controller is connected.
+    Skip(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether no check failed.
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| !matches!(check.outcome, Outcome::Fail(_)))
+    }
+
+    pub fn check(&self, name: &str) -> Option<&Outcome> {
+        self.checks
+            .iter()
+            .find(|check| check.name == name)
+            .map(|check| &check.outcome)
+    }
+
+    fn push(&mut self, name: &'static str, outcome: Outcome) {
+        self.checks.push(Check { name, outcome });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in &self.checks {
+            let (status, detail) = match &check.outcome {
+                Outcome::Pass(detail) => ("PASS", detail),
+                Outcome::Fail(detail) => ("FAIL", detail),
+                Outcome::Skip(detail) => ("SKIP", detail),
+            };
+            writeln!(f, "{} {}: {}", status, check.name, detail)?;
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| matches!(check.outcome, Outcome::Fail(_)))
+            .count();
+        if failed == 0 {
+            write!(f, "all {} checks passed", self.checks.len())
+        } else {
+            write!(f, "{} of {} checks failed", failed, self.checks.len())
+        }
+    }
+}
+
+/// Runs every check against the server at `server`.
+///
+/// `transport` should be nonblocking or time out quickly, since responses are collected
+/// for fixed periods.
+pub fn run<T: Transport>(transport: T, server: T::Addr, config: &Config) -> io::Result<Report> {
+    let mut runner = Runner {
+        transport,
+        server,
+        config,
+        id: random_id(),
+        framing_errors: Vec::new(),
+        responses: 0,
+        sender_ids: BTreeSet::new(),
+        last_renewal: None,
+    };
+    let mut report = Report::default();
+
+    runner.check_protocol_version(&mut report)?;
+    let controllers = runner.check_controller_info(&mut report)?;
+    runner.check_invalid_request(&mut report)?;
+    runner.check_subscriptions(&mut report, &controllers)?;
+
+    report.push(
+        "framing",
+        if runner.framing_errors.is_empty() {
+            Outcome::Pass(format!("{} responses well formed", runner.responses))
+        } else {
+            Outcome::Fail(summarize(&runner.framing_errors))
+        },
+    );
+    report.push(
+        "sender id",
+        match runner.sender_ids.len() {
+            0 => Outcome::Skip("no responses".to_string()),
+            1 => Outcome::Pass(format!(
+                "{:#010x}",
+                runner.sender_ids.iter().next().unwrap()
+            )),
+            n => Outcome::Fail(format!("{} different sender ids", n)),
+        },
+    );
+    Ok(report)
+}
+
+/// A well formed response.
+struct Response {
+    at: Instant,
+    bytes: Vec<u8>,
+}
+
+impl Response {
+    fn message(&self) -> MessageRef<'_> {
+        // Responses are only kept if they parse.
+        MessageRef::parse(&self.bytes, Crc32::new()).unwrap()
+    }
+}
+
+/// A connected controller as reported by `ControllerInfo`.
+#[derive(Clone, Copy, Debug)]
+struct Controller {
+    slot: u8,
+    mac: [u8; 6],
+}
+
+struct Runner<'a, T: Transport> {
+    transport: T,
+    server: T::Addr,
+    config: &'a Config,
+    id: u32,
+    framing_errors: Vec<String>,
+    responses: usize,
+    sender_ids: BTreeSet<u32>,
+    last_renewal: Option<Instant>,
+}
+
+impl<T: Transport> Runner<'_, T> {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.transport.send_to(bytes, &self.server)?;
+        Ok(())
+    }
+
+    /// Collects well formed responses for `duration`, sending `renew` every
+    /// [`RESUBSCRIBE_INTERVAL`] if given.
+    fn collect(&mut self, duration: Duration, renew: &[Vec<u8>]) -> io::Result<Vec<Response>> {
+        let start = Instant::now();
+        let mut renewed = false;
+        let mut responses = Vec::new();
+        let mut buf = [0u8; 512];
+        while start.elapsed() < duration {
+            let due = !renewed
+                || self
+                    .last_renewal
+                    .is_none_or(|at| at.elapsed() >= RESUBSCRIBE_INTERVAL);
+            if !renew.is_empty() && due {
+                renewed = true;
+                self.last_renewal = Some(Instant::now());
+                for request in renew {
+                    self.send(request)?;
+                }
+            }
+            match self.transport.recv_from(&mut buf) {
+                Ok((len, from)) if from == self.server => {
+                    let bytes = &buf[..len];
+                    match framing(bytes) {
+                        Ok(sender_id) => {
+                            self.responses += 1;
+                            self.sender_ids.insert(sender_id);
+                            responses.push(Response {
+                                at: Instant::now(),
+                                bytes: bytes.to_vec(),
+                            });
+                        }
+                        Err(err) => self.framing_errors.push(err),
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if is_timeout(&err) => thread::sleep(Duration::from_millis(1)),
+                // Reported on some platforms when a previous send was rejected.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(responses)
+    }
+
+    fn check_protocol_version(&mut self, report: &mut Report) -> io::Result<()> {
+        let request = RequestProtocolVersionInfo::new(self.id, Crc32::new());
+        self.send(&request.bytes)?;
+        let responses = self.collect(self.config.response_timeout, &[])?;
+        let versions: Vec<_> = responses
+            .iter()
+            .filter_map(|response| match response.message() {
+                MessageRef::ProtocolVersionInfo(info) => Some(info.protocol()),
+                _ => None,
+            })
+            .collect();
+        report.push(
+            "protocol version",
+            match versions.as_slice() {
+                [] => Outcome::Fail("no response".to_string()),
+                [Ok(Protocol::Version1001)] => Outcome::Pass("1001".to_string()),
+                [Err(Invalid(version, _))] => {
+                    Outcome::Fail(format!("unsupported version {}", version))
+                }
+                _ => Outcome::Fail(format!("{} responses to one request", versions.len())),
+            },
+        );
+        Ok(())
+    }
+
+    fn check_controller_info(&mut self, report: &mut Report) -> io::Result<Vec<Controller>> {
+        let slots = [0, 1, 2, 3];
+        let request = RequestControllerInfo::new(self.id, &slots, Crc32::new())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.send(&request.bytes)?;
+        let responses = self.collect(self.config.response_timeout, &[])?;
+
+        let mut seen = BTreeMap::new();
+        let mut errors = Vec::new();
+        for response in &responses {
+            if let MessageRef::ControllerInfo(info) = response.message() {
+                let header = info.controller_header();
+                if seen.insert(header.slot(), header.clone()).is_some() {
+                    errors.push(format!("slot {} reported twice", header.slot()));
+                }
+                if header.slot() as usize >= SLOTS {
+                    errors.push(format!("slot {} was not requested", header.slot()));
+                }
+                if let Err(Invalid(state, _)) = header.state() {
+                    errors.push(format!(
+                        "slot {} has invalid state {}",
+                        header.slot(),
+                        state
+                    ));
+                }
+            }
+        }
+        for slot in slots {
+            if !seen.contains_key(&slot) {
+                errors.push(format!("no response for slot {}", slot));
+            }
+        }
+        let controllers: Vec<_> = seen
+            .values()
+            .filter(|header| matches!(header.state(), Ok(State::Connected)))
+            .map(|header| Controller {
+                slot: header.slot(),
+                mac: *header.mac(),
+            })
+            .collect();
+        report.push(
+            "controller info",
+            if errors.is_empty() {
+                Outcome::Pass(format!("{} controllers connected", controllers.len()))
+            } else {
+                Outcome::Fail(summarize(&errors))
+            },
+        );
+        Ok(controllers)
+    }
+
+    fn check_invalid_request(&mut self, report: &mut Report) -> io::Result<()> {
+        let mut request = RequestProtocolVersionInfo::new(self.id, Crc32::new());
+        let crc = request.header().crc32();
+        request.header_mut().set_crc32(!crc);
+        self.send(&request.bytes)?;
+        let responses = self.collect(self.config.response_timeout, &[])?;
+        report.push(
+            "invalid crc ignored",
+            if responses.is_empty() {
+                Outcome::Pass("no response".to_string())
+            } else {
+                Outcome::Fail(format!(
+                    "{} responses to a request with a bad CRC",
+                    responses.len()
+                ))
+            },
+        );
+        Ok(())
+    }
+
+    fn check_subscriptions(
+        &mut self,
+        report: &mut Report,
+        controllers: &[Controller],
+    ) -> io::Result<()> {
+        const NAMES: [&str; 6] = [
+            "mac subscription",
+            "slot subscription",
+            "all subscription",
+            "slot and mac consistency",
+            "packet numbers",
+            "subscription timeout",
+        ];
+        let first = match controllers.first() {
+            Some(first) => *first,
+            None => {
+                for name in NAMES {
+                    report.push(name, Outcome::Skip("no controller connected".to_string()));
+                }
+                return Ok(());
+            }
+        };
+        let subscribe = |registration, slot, mac| {
+            RequestControllerData::new(self.id, registration, slot, mac, Crc32::new())
+                .bytes
+                .to_vec()
+        };
+        let mac_request = subscribe(Registration::MacBased, 0, first.mac);
+        let slot_requests: Vec<_> = controllers
+            .iter()
+            .map(|c| subscribe(Registration::SlotBased, c.slot, [0; 6]))
+            .collect();
+        let all_request = subscribe(Registration::AllControllers, 0, [0; 6]);
+
+        let mut data = Vec::new();
+
+        // Only the one controller was asked for.
+        let responses = self.collect(
+            self.config.data_duration,
+            std::slice::from_ref(&mac_request),
+        )?;
+        let slots = data_slots(&responses);
+        report.push(
+            NAMES[0],
+            if slots.is_empty() {
+                Outcome::Fail(format!("no data for MAC {}", mac(&first.mac)))
+            } else if slots.iter().any(|&slot| slot != first.slot) {
+                Outcome::Fail(format!(
+                    "data for slots {:?}, subscribed to slot {}",
+                    slots, first.slot
+                ))
+            } else {
+                Outcome::Pass(format!("{} packets", count_data(&responses)))
+            },
+        );
+        data.extend(responses);
+
+        let mut renew = slot_requests;
+        renew.push(mac_request);
+        let responses = self.collect(self.config.data_duration, &renew)?;
+        let slots = data_slots(&responses);
+        let missing: Vec<_> = controllers
+            .iter()
+            .map(|c| c.slot)
+            .filter(|slot| !slots.contains(slot))
+            .collect();
+        report.push(
+            NAMES[1],
+            if missing.is_empty() {
+                Outcome::Pass(format!("{} packets", count_data(&responses)))
+            } else {
+                Outcome::Fail(format!("no data for slots {:?}", missing))
+            },
+        );
+        data.extend(responses);
+
+        let responses = self.collect(
+            self.config.data_duration,
+            std::slice::from_ref(&all_request),
+        )?;
+        let slots = data_slots(&responses);
+        let missing: Vec<_> = controllers
+            .iter()
+            .map(|c| c.slot)
+            .filter(|slot| !slots.contains(slot))
+            .collect();
+        report.push(
+            NAMES[2],
+            if missing.is_empty() {
+                Outcome::Pass(format!("{} packets", count_data(&responses)))
+            } else {
+                Outcome::Fail(format!("no data for slots {:?}", missing))
+            },
+        );
+        data.extend(responses);
+
+        let mut errors = Vec::new();
+        for response in &data {
+            if let MessageRef::ControllerData(packet) = response.message() {
+                let header = packet.controller_header();
+                match controllers.iter().find(|c| c.slot == header.slot()) {
+                    Some(controller) if controller.mac != *header.mac() => errors.push(format!(
+                        "slot {} data has MAC {}, info said {}",
+                        header.slot(),
+                        mac(header.mac()),
+                        mac(&controller.mac),
+                    )),
+                    Some(_) => {}
+                    None => errors.push(format!(
+                        "data for slot {} which isn't connected",
+                        header.slot()
+                    )),
+                }
+                if !packet.is_connected() {
+                    errors.push(format!(
+                        "slot {} data is marked disconnected",
+                        header.slot()
+                    ));
+                }
+            }
+        }
+        errors.dedup();
+        report.push(
+            NAMES[3],
+            if errors.is_empty() {
+                Outcome::Pass(format!("{} packets", count_data(&data)))
+            } else {
+                Outcome::Fail(summarize(&errors))
+            },
+        );
+
+        // Packets may be lost, but never go backwards.
+        let mut last = BTreeMap::new();
+        let mut errors = Vec::new();
+        let mut gaps = 0u64;
+        for response in &data {
+            if let MessageRef::ControllerData(packet) = response.message() {
+                let slot = packet.controller_header().slot();
+                let number = packet.packet_number();
+                if let Some(previous) = last.insert(slot, number) {
+                    if number <= previous {
+                        errors.push(format!(
+                            "slot {} packet {} after {}",
+                            slot, number, previous
+                        ));
+                    } else {
+                        gaps += (number - previous - 1) as u64;
+                    }
+                }
+            }
+        }
+        report.push(
+            NAMES[4],
+            if errors.is_empty() {
+                Outcome::Pass(format!("increasing, {} skipped", gaps))
+            } else {
+                Outcome::Fail(summarize(&errors))
+            },
+        );
+
+        if !self.config.check_timeout {
+            report.push(NAMES[5], Outcome::Skip("disabled".to_string()));
+            return Ok(());
+        }
+        // Stop renewing and see when the data stops.
+        let renewed = self.last_renewal.unwrap_or_else(Instant::now);
+        let wait = SUBSCRIPTION_TIMEOUT + self.config.timeout_grace + Duration::from_secs(1);
+        let responses = self.collect(wait, &[])?;
+        let last = responses
+            .iter()
+            .filter(|response| matches!(response.message(), MessageRef::ControllerData(_)))
+            .map(|response| response.at.duration_since(renewed))
+            .max();
+        let latest = SUBSCRIPTION_TIMEOUT + self.config.timeout_grace;
+        report.push(
+            NAMES[5],
+            match last {
+                None => Outcome::Fail("data stopped as soon as renewals stopped".to_string()),
+                Some(last) if last < RESUBSCRIBE_INTERVAL => Outcome::Fail(format!(
+                    "data stopped {:.1}s after the last renewal, before clients renew",
+                    last.as_secs_f64()
+                )),
+                Some(last) if last > latest => Outcome::Fail(format!(
+                    "data still sent {:.1}s after the last renewal",
+                    last.as_secs_f64()
+                )),
+                Some(last) => Outcome::Pass(format!(
+                    "data stopped {:.1}s after the last renewal",
+                    last.as_secs_f64()
+                )),
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Checks a response and returns its sender id.
+fn framing(bytes: &[u8]) -> Result<u32, String> {
+    let message = MessageRef::parse(bytes, Crc32::new()).map_err(|err| err.to_string())?;
+    let header = message.header();
+    let (name, size) = match message {
+        MessageRef::ProtocolVersionInfo(_) => ("ProtocolVersionInfo", 22),
+        MessageRef::ControllerInfo(_) => ("ControllerInfo", 32),
+        MessageRef::ControllerData(_) => ("ControllerData", 100),
+        MessageRef::RequestProtocolVersionInfo(_)
+        | MessageRef::RequestControllerInfo(_)
+        | MessageRef::RequestControllerData(_) => {
+            return Err("server sent a client request".to_string());
+        }
+    };
+    if bytes.len() != size {
+        return Err(format!(
+            "{} is {} bytes, expected {}",
+            name,
+            bytes.len(),
+            size
+        ));
+    }
+    if header.packet_length() as usize != size - 16 {
+        return Err(format!(
+            "{} packet_length is {}, expected {}",
+            name,
+            header.packet_length(),
+            size - 16
+        ));
+    }
+    if let Err(Invalid(protocol, _)) = header.protocol() {
+        return Err(format!("{} header has protocol {}", name, protocol));
+    }
+    Ok(header.sender_id())
+}
+
+fn data_slots(responses: &[Response]) -> BTreeSet<u8> {
+    responses
+        .iter()
+        .filter_map(|response| match response.message() {
+            MessageRef::ControllerData(packet) => Some(packet.controller_header().slot()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn count_data(responses: &[Response]) -> usize {
+    responses
+        .iter()
+        .filter(|response| matches!(response.message(), MessageRef::ControllerData(_)))
+        .count()
+}
+
+fn mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The first few errors and how many more there were.
+fn summarize(errors: &[String]) -> String {
+    const SHOWN: usize = 3;
+    let mut summary = errors[..errors.len().min(SHOWN)].join("; ");
+    if errors.len() > SHOWN {
+        summary += &format!("; and {} more", errors.len() - SHOWN);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server::Server,
+        transport::{MemoryNetwork, MemoryTransport},
+    };
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    const SERVER: ([u8; 4], u16) = ([127, 0, 0, 1], 26760);
+
+    fn controller_data(slot: u8) -> ControllerData {
+        ControllerData::new(
+            0,
+            slot,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [0, 0, 0, 0, 0, slot + 1],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        )
+    }
+
+    /// Runs a server pushing data for `slots` until the returned flag is set.
+    fn spawn_server(
+        mut server: Server<MemoryTransport>,
+        slots: &[u8],
+    ) -> (Arc<AtomicBool>, thread::JoinHandle<()>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let data: Vec<_> = slots.iter().map(|&slot| controller_data(slot)).collect();
+        let handle = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    server
+                        .poll_until(Instant::now() + Duration::from_millis(10))
+                        .unwrap();
+                    for data in &data {
+                        server.push(data).unwrap();
+                    }
+                }
+            }
+        });
+        (stop, handle)
+    }
+
+    #[test]
+    fn own_server() {
+        let network = MemoryNetwork::new();
+        let (stop, handle) = spawn_server(
+            Server::with_transport(network.bind(SERVER).unwrap()),
+            &[0, 2],
+        );
+        let config = Config {
+            response_timeout: Duration::from_millis(200),
+            data_duration: Duration::from_millis(300),
+            ..Config::default()
+        };
+        let report = run(
+            network.bind(([127, 0, 0, 1], 0)).unwrap(),
+            SERVER.into(),
+            &config,
+        )
+        .unwrap();
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.checks.len(), 11);
+        assert!(report
+            .checks
+            .iter()
+            .all(|check| matches!(check.outcome, Outcome::Pass(_))));
+    }
+
+    #[test]
+    fn no_controllers() {
+        let network = MemoryNetwork::new();
+        let (stop, handle) =
+            spawn_server(Server::with_transport(network.bind(SERVER).unwrap()), &[]);
+        let config = Config {
+            response_timeout: Duration::from_millis(200),
+            ..Config::default()
+        };
+        let report = run(
+            network.bind(([127, 0, 0, 1], 0)).unwrap(),
+            SERVER.into(),
+            &config,
+        )
+        .unwrap();
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(report.passed(), "{}", report);
+        assert!(matches!(
+            report.check("mac subscription"),
+            Some(Outcome::Skip(_))
+        ));
+    }
+
+    #[test]
+    fn no_server() {
+        let network = MemoryNetwork::new();
+        let config = Config {
+            response_timeout: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let report = run(
+            network.bind(([127, 0, 0, 1], 0)).unwrap(),
+            SERVER.into(),
+            &config,
+        )
+        .unwrap();
+        assert!(!report.passed());
+        assert!(matches!(
+            report.check("protocol version"),
+            Some(Outcome::Fail(_))
+        ));
+    }
+
+    #[test]
+    fn framing_errors() {
+        let info = ProtocolVersionInfo::new(1, Protocol::Version1001, Crc32::new());
+        assert_eq!(framing(&info.bytes), Ok(1));
+
+        let mut padded = info.bytes.to_vec();
+        padded.push(0);
+        assert!(framing(&padded).is_err());
+
+        let mut wrong_length = info.clone();
+        wrong_length.header_mut().set_packet_length(100);
+        wrong_length.update_crc(Crc32::new());
+        assert!(framing(&wrong_length.bytes).is_err());
+
+        let mut bad_crc = info.bytes;
+        bad_crc[20] ^= 1;
+        assert!(framing(&bad_crc).is_err());
+
+        let request = RequestProtocolVersionInfo::new(1, Crc32::new());
+        assert!(framing(&request.bytes).is_err());
+    }
+}
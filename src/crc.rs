@@ -0,0 +1,185 @@
+use core::{convert::TryInto, hash::Hasher};
+
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Tables for slice-by-8: `TABLES[k][b]` is the CRC of byte `b` followed by `k` zero bytes.
+static TABLES: [[u32; 256]; 8] = {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        tables[0][i] = crc;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = tables[k - 1][i];
+            tables[k][i] = (prev >> 8) ^ tables[0][(prev & 0xFF) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+};
+
+/// Feeds eight bytes, read as a little-endian word, to the CRC register.
+fn step(crc: u32, word: u64) -> u32 {
+    let lo = crc ^ word as u32;
+    let hi = (word >> 32) as u32;
+    TABLES[7][(lo & 0xFF) as usize]
+        ^ TABLES[6][((lo >> 8) & 0xFF) as usize]
+        ^ TABLES[5][((lo >> 16) & 0xFF) as usize]
+        ^ TABLES[4][(lo >> 24) as usize]
+        ^ TABLES[3][(hi & 0xFF) as usize]
+        ^ TABLES[2][((hi >> 8) & 0xFF) as usize]
+        ^ TABLES[1][((hi >> 16) & 0xFF) as usize]
+        ^ TABLES[0][(hi >> 24) as usize]
+}
+
+fn step_byte(crc: u32, byte: u8) -> u32 {
+    TABLES[0][((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+}
+
+fn word(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// Feeds `bytes` to the CRC register, eight at a time.
+///
+/// The register is linear: feeding `a ^ b` to `crc ^ other` gives the XOR of feeding `a` to
+/// `crc` and `b` to `other`. So the register for a changed message is the old one XORed with
+/// the register for the changed bits, fed from zero.
+pub(crate) fn update(mut crc: u32, bytes: &[u8]) -> u32 {
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        crc = step(crc, word(chunk));
+    }
+    chunks.remainder().iter().fold(crc, |crc, &byte| step_byte(crc, byte))
+}
+
+/// Feeds a fixed number of zero bytes to the CRC register with four table lookups.
+pub(crate) struct Zeros {
+    /// `tables[k][b]` is register byte `k` holding `b`, fed the zero bytes.
+    tables: [[u32; 256]; 4],
+}
+
+impl Zeros {
+    pub(crate) const fn new(len: usize) -> Self {
+        let mut tables = [[0u32; 256]; 4];
+        let mut k = 0;
+        while k < 4 {
+            let mut b = 0;
+            while b < 256 {
+                let mut crc = (b as u32) << (8 * k);
+                let mut bit = 0;
+                while bit < 8 * len {
+                    crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+                    bit += 1;
+                }
+                tables[k][b] = crc;
+                b += 1;
+            }
+            k += 1;
+        }
+        Zeros { tables }
+    }
+
+    pub(crate) fn feed(&self, crc: u32) -> u32 {
+        self.tables[0][(crc & 0xFF) as usize]
+            ^ self.tables[1][((crc >> 8) & 0xFF) as usize]
+            ^ self.tables[2][((crc >> 16) & 0xFF) as usize]
+            ^ self.tables[3][(crc >> 24) as usize]
+    }
+}
+
+/// The CRC-32 (IEEE 802.3) checksum used by DSU packets, as a `Hasher`.
+///
+/// ```
+/// use dsu_protocol::{crc::Crc32, RequestProtocolVersionInfo};
+///
+/// let request = RequestProtocolVersionInfo::new(0, Crc32::new());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32 { state: !0 }
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Hasher for Crc32 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.state = update(self.state, bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        (!self.state) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytewise(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc = TABLES[0][((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+        }
+        !crc
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.write(bytes);
+        crc.finish() as u32
+    }
+
+    fn message() -> [u8; 100] {
+        let mut bytes = [0u8; 100];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(97).wrapping_add(13);
+        }
+        bytes
+    }
+
+    #[test]
+    fn slice_by_8() {
+        let bytes = message();
+        for len in 0..bytes.len() {
+            assert_eq!(crc32(&bytes[..len]), bytewise(&bytes[..len]), "length {}", len);
+        }
+        for split in 0..bytes.len() {
+            let mut crc = Crc32::new();
+            crc.write(&bytes[..split]);
+            crc.write(&bytes[split..]);
+            assert_eq!(crc.finish() as u32, bytewise(&bytes), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn zeros() {
+        for len in [0, 1, 7, 32] {
+            let zeros = Zeros::new(len);
+            for crc in [0, 1, 0x8000_0000, 0xDEAD_BEEF, !0] {
+                assert_eq!(zeros.feed(crc), update(crc, &[0; 32][..len]), "{} zeros", len);
+            }
+        }
+    }
+}
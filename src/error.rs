@@ -1,8 +1,13 @@
-use std::{error::Error as StdError, fmt::{Debug, Display, Formatter, Result}};
+use core::fmt::{Debug, Display, Formatter, Result};
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+
+use crate::{types::{Magic, MessageType}, Header};
 
 #[derive(Clone, Debug)]
 pub struct Invalid<T>(pub T, pub &'static str);
 
+#[cfg(feature = "std")]
 impl<T: Debug + Display> StdError for Invalid<T> {}
 
 impl<T: Display> Display for Invalid<T> {
@@ -17,6 +22,7 @@ pub enum RequestControllerInfoError {
     InvalidSlotsLength(i32),
 }
 
+#[cfg(feature = "std")]
 impl StdError for RequestControllerInfoError {}
 
 impl Display for RequestControllerInfoError {
@@ -32,38 +38,184 @@ impl Display for RequestControllerInfoError {
     }
 }
 
+/// A CIDR block that isn't an IP address optionally followed by `/` and a prefix length.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CidrParseError(pub std::string::String);
+
+#[cfg(feature = "std")]
+impl StdError for CidrParseError {}
+
+#[cfg(feature = "std")]
+impl Display for CidrParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "invalid CIDR block `{}`", self.0)
+    }
+}
+
+/// Why an authenticated datagram was rejected.
+#[cfg(feature = "auth")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// Too short for a header and a trailer.
+    TooShort(usize),
+    /// No trailer, as in plain DSU.
+    InvalidMagic(u32),
+    /// Signed with another key or modified.
+    InvalidTag,
+    /// The counter isn't above the last one accepted from the sender.
+    Replayed { counter: u64, last: u64 },
+}
+
+#[cfg(feature = "auth")]
+impl StdError for AuthError {}
+
+#[cfg(feature = "auth")]
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            AuthError::TooShort(len) => write!(f, "datagram of {} bytes is too short to be signed", len),
+            AuthError::InvalidMagic(magic) => write!(f, "invalid trailer magic {:#X}", magic),
+            AuthError::InvalidTag => write!(f, "invalid authentication tag"),
+            AuthError::Replayed { counter, last } => {
+                write!(f, "replayed counter {}, last accepted was {}", counter, last)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum MessageParseError {
-    SliceTooSmall,
-    InvalidMagic(u32),
-    InvalidMessageId(u32),
+    SliceTooSmall {
+        message: Option<(Magic, MessageType)>,
+        expected: usize,
+        actual: usize,
+        header: Option<Header>,
+    },
+    /// Longer than the message the header announces. DSU datagrams hold exactly one message.
+    SliceTooLarge {
+        message: (Magic, MessageType),
+        expected: usize,
+        actual: usize,
+        header: Header,
+    },
+    InvalidMagic {
+        magic: u32,
+        header: Header,
+    },
+    InvalidMessageId {
+        id: u32,
+        magic: Magic,
+        header: Header,
+    },
     InvalidCrc32 {
         expected: u32,
         calculated: u32,
+        message: (Magic, MessageType),
+        header: Header,
+    },
+    /// A `RequestControllerInfo` with an invalid slot count. The header is there when the
+    /// error comes from parsing a whole message.
+    RequestControllerInfoError {
+        error: RequestControllerInfoError,
+        header: Option<Header>,
     },
-    RequestControllerInfoError(RequestControllerInfoError),
 }
 
+impl MessageParseError {
+    /// Stable identifier for this kind of error, suitable for grouping in logs.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MessageParseError::SliceTooSmall { .. } => "slice_too_small",
+            MessageParseError::SliceTooLarge { .. } => "slice_too_large",
+            MessageParseError::InvalidMagic { .. } => "invalid_magic",
+            MessageParseError::InvalidMessageId { .. } => "invalid_message_id",
+            MessageParseError::InvalidCrc32 { .. } => "invalid_crc32",
+            MessageParseError::RequestControllerInfoError {
+                error: RequestControllerInfoError::InvalidSlotsLength(_),
+                ..
+            } => "invalid_slots_length",
+        }
+    }
+
+    /// Byte offset of the field that caused the error.
+    ///
+    /// For `SliceTooSmall` this is the offset of the first missing byte, and for
+    /// `SliceTooLarge` the offset of the first extra one.
+    pub fn offset(&self) -> usize {
+        match self {
+            MessageParseError::SliceTooSmall { actual, .. } => *actual,
+            MessageParseError::SliceTooLarge { expected, .. } => *expected,
+            MessageParseError::InvalidMagic { .. } => 0,
+            MessageParseError::InvalidMessageId { .. } => 16,
+            MessageParseError::InvalidCrc32 { .. } => 8,
+            MessageParseError::RequestControllerInfoError { .. } => 20,
+        }
+    }
+
+    /// The message kind detected from the header, if it got that far.
+    pub fn message(&self) -> Option<(Magic, MessageType)> {
+        match self {
+            MessageParseError::SliceTooSmall { message, .. } => *message,
+            MessageParseError::SliceTooLarge { message, .. }
+            | MessageParseError::InvalidCrc32 { message, .. } => Some(*message),
+            MessageParseError::RequestControllerInfoError { .. } => {
+                Some((Magic::Client, MessageType::ControllerInfo))
+            }
+            MessageParseError::InvalidMagic { .. } | MessageParseError::InvalidMessageId { .. } => {
+                None
+            }
+        }
+    }
+
+    /// A copy of the header, if the slice was large enough to contain one.
+    pub fn header(&self) -> Option<&Header> {
+        match self {
+            MessageParseError::SliceTooSmall { header, .. } => header.as_ref(),
+            MessageParseError::SliceTooLarge { header, .. }
+            | MessageParseError::InvalidMagic { header, .. }
+            | MessageParseError::InvalidMessageId { header, .. }
+            | MessageParseError::InvalidCrc32 { header, .. } => Some(header),
+            MessageParseError::RequestControllerInfoError { header, .. } => header.as_ref(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl StdError for MessageParseError {}
 
 impl Display for MessageParseError {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "message parse error: ")?;
+        write!(f, "message parse error [{}] at offset {}: ", self.code(), self.offset())?;
         match self {
-            MessageParseError::SliceTooSmall => {
-                write!(f, "slice is too small")?;
+            MessageParseError::SliceTooSmall { message, expected, actual, .. } => {
+                write!(f, "slice is too small, expected {} bytes, got {}", expected, actual)?;
+                if let Some((magic, message_type)) = message {
+                    write!(f, " for {:?} {:?}", magic, message_type)?;
+                }
+            }
+            MessageParseError::SliceTooLarge { message: (magic, message_type), expected, actual, .. } => {
+                write!(
+                    f,
+                    "slice is too large, expected {} bytes, got {} for {:?} {:?}",
+                    expected, actual, magic, message_type,
+                )?;
             }
-            MessageParseError::InvalidMagic(magic) => {
+            MessageParseError::InvalidMagic { magic, .. } => {
                 write!(f, "invalid magic {:#X}", magic)?;
             }
-            MessageParseError::InvalidMessageId(val) => {
-                write!(f, "invalid message id {}", val)?;
+            MessageParseError::InvalidMessageId { id, magic, .. } => {
+                write!(f, "invalid message id {:#X} for {:?}", id, magic)?;
             }
-            MessageParseError::InvalidCrc32 { expected, calculated } => {
-                write!(f, "invalid crc32, expected {}, calculated {}", expected, calculated)?;
+            MessageParseError::InvalidCrc32 { expected, calculated, message: (magic, message_type), .. } => {
+                write!(
+                    f,
+                    "invalid crc32 for {:?} {:?}, expected {:#010X}, calculated {:#010X}",
+                    magic, message_type, expected, calculated,
+                )?;
             }
-            MessageParseError::RequestControllerInfoError(err) => {
-                write!(f, "{}", err)?;
+            MessageParseError::RequestControllerInfoError { error, .. } => {
+                write!(f, "{}", error)?;
             }
         }
 
@@ -73,6 +225,49 @@ impl Display for MessageParseError {
 
 impl From<RequestControllerInfoError> for MessageParseError {
     fn from(err: RequestControllerInfoError) -> Self {
-        MessageParseError::RequestControllerInfoError(err)
+        MessageParseError::RequestControllerInfoError {
+            error: err,
+            header: None,
+        }
+    }
+}
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum RecvError {
+    Io(std::io::Error),
+    Parse(MessageParseError),
+}
+
+#[cfg(feature = "std")]
+impl StdError for RecvError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            RecvError::Io(err) => Some(err),
+            RecvError::Parse(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Display for RecvError {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            RecvError::Io(err) => write!(f, "receive error: {}", err),
+            RecvError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for RecvError {
+    fn from(err: std::io::Error) -> Self {
+        RecvError::Io(err)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "std")]
+impl From<MessageParseError> for RecvError {
+    fn from(err: MessageParseError) -> Self {
+        RecvError::Parse(err)
+    }
+}
@@ -0,0 +1,409 @@
+//! Simulated network impairment: loss, delay, jitter, reordering, duplication and corruption.
+//!
+//! Decisions come from a seeded generator, so the same seed and the same sequence of sends
+//! impair the same packets in the same way.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::transport::Transport;
+
+/// How long packets are held back before being sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Delay {
+    #[default]
+    None,
+    Fixed(Duration),
+    /// Uniformly distributed between `min` and `max`.
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+    /// Normally distributed, never below zero.
+    Normal {
+        mean: Duration,
+        std_dev: Duration,
+    },
+}
+
+/// What to do to packets. Rates are probabilities between 0 and 1, applied to each packet
+/// independently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impairment {
+    pub drop: f64,
+    pub duplicate: f64,
+    /// Flips one random bit of the packet.
+    pub corrupt: f64,
+    /// Holds the packet back by an extra `reorder_delay` so later packets overtake it.
+    pub reorder: f64,
+    pub reorder_delay: Duration,
+    pub delay: Delay,
+}
+
+impl Default for Impairment {
+    fn default() -> Self {
+        Impairment {
+            drop: 0.0,
+            duplicate: 0.0,
+            corrupt: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(10),
+            delay: Delay::None,
+        }
+    }
+}
+
+/// Counts of what happened to packets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub received: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub corrupted: u64,
+    pub reordered: u64,
+    pub sent: u64,
+}
+
+/// SplitMix64, small and good enough for picking which packets to impair.
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+
+    fn delay(&mut self, delay: &Delay) -> Duration {
+        match *delay {
+            Delay::None => Duration::ZERO,
+            Delay::Fixed(delay) => delay,
+            Delay::Uniform { min, max } => {
+                let span = max.saturating_sub(min).as_secs_f64();
+                min + Duration::from_secs_f64(span * self.next_f64())
+            }
+            Delay::Normal { mean, std_dev } => {
+                // Box-Muller.
+                let u1 = 1.0 - self.next_f64();
+                let u2 = self.next_f64();
+                let normal = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                let secs = mean.as_secs_f64() + std_dev.as_secs_f64() * normal;
+                Duration::from_secs_f64(secs.max(0.0))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending<A> {
+    due: Instant,
+    /// Keeps packets due at the same time in the order they were sent.
+    sequence: u64,
+    to: A,
+    bytes: Vec<u8>,
+}
+
+impl<A> PartialEq for Pending<A> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.sequence) == (other.due, other.sequence)
+    }
+}
+
+impl<A> Eq for Pending<A> {}
+
+impl<A> PartialOrd for Pending<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<A> Ord for Pending<A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.sequence).cmp(&(other.due, other.sequence))
+    }
+}
+
+/// Decides what happens to packets and holds them until they're due, without doing any I/O.
+#[derive(Debug)]
+pub struct Impairer<A = SocketAddr> {
+    impairment: Impairment,
+    rng: Rng,
+    queue: BinaryHeap<Reverse<Pending<A>>>,
+    sequence: u64,
+    stats: Stats,
+}
+
+impl<A: Clone> Impairer<A> {
+    pub fn new(impairment: Impairment, seed: u64) -> Self {
+        Impairer {
+            impairment,
+            rng: Rng(seed),
+            queue: BinaryHeap::new(),
+            sequence: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn impairment(&self) -> &Impairment {
+        &self.impairment
+    }
+
+    pub fn set_impairment(&mut self, impairment: Impairment) {
+        self.impairment = impairment;
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Takes a packet sent at `now` to `to`.
+    pub fn push(&mut self, bytes: &[u8], to: A, now: Instant) {
+        self.stats.received += 1;
+        if self.rng.chance(self.impairment.drop) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let copies = if self.rng.chance(self.impairment.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let mut bytes = bytes.to_vec();
+            if !bytes.is_empty() && self.rng.chance(self.impairment.corrupt) {
+                let bit = self.rng.next_u64() as usize % (bytes.len() * 8);
+                bytes[bit / 8] ^= 1 << (bit % 8);
+                self.stats.corrupted += 1;
+            }
+            let mut delay = self.rng.delay(&self.impairment.delay);
+            if self.rng.chance(self.impairment.reorder) {
+                delay += self.impairment.reorder_delay;
+                self.stats.reordered += 1;
+            }
+            self.queue.push(Reverse(Pending {
+                due: now + delay,
+                sequence: self.sequence,
+                to: to.clone(),
+                bytes,
+            }));
+            self.sequence += 1;
+        }
+    }
+
+    /// Removes the next packet due at `now`.
+    pub fn pop_due(&mut self, now: Instant) -> Option<(Vec<u8>, A)> {
+        if self.queue.peek()?.0.due > now {
+            return None;
+        }
+        let Reverse(pending) = self.queue.pop()?;
+        self.stats.sent += 1;
+        Some((pending.bytes, pending.to))
+    }
+
+    /// When the next held packet is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.queue.peek().map(|pending| pending.0.due)
+    }
+
+    /// Number of packets held.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+/// A transport whose outgoing packets are impaired.
+///
+/// Held packets are sent by [`ImpairedTransport::flush`], which `send_to` and `recv_from`
+/// call, so a transport that's only received from should use a short timeout or be
+/// nonblocking.
+pub struct ImpairedTransport<T: Transport = UdpSocket> {
+    inner: T,
+    impairer: Impairer<T::Addr>,
+}
+
+impl<T: Transport> ImpairedTransport<T> {
+    pub fn new(inner: T, impairment: Impairment, seed: u64) -> Self {
+        ImpairedTransport {
+            inner,
+            impairer: Impairer::new(impairment, seed),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn impairer(&self) -> &Impairer<T::Addr> {
+        &self.impairer
+    }
+
+    pub fn impairer_mut(&mut self) -> &mut Impairer<T::Addr> {
+        &mut self.impairer
+    }
+
+    /// Sends the held packets that are due.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        while let Some((bytes, to)) = self.impairer.pop_due(now) {
+            match self.inner.send_to(&bytes, &to) {
+                Ok(_) => {}
+                // Lost like any other packet.
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::ConnectionRefused
+                    ) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Transport> Transport for ImpairedTransport<T> {
+    type Addr = T::Addr;
+
+    /// Queues a packet and sends whatever is due. Always reports the whole packet as sent.
+    fn send_to(&mut self, bytes: &[u8], to: &T::Addr) -> io::Result<usize> {
+        self.impairer.push(bytes, to.clone(), Instant::now());
+        self.flush()?;
+        Ok(bytes.len())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, T::Addr)> {
+        self.flush()?;
+        self.inner.recv_from(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What an impairer seeded with `seed` does to 1000 numbered packets: the packets it
+    /// sends, in order, with how long each was held.
+    fn run(seed: u64) -> (Vec<(Vec<u8>, Duration)>, Stats) {
+        let impairment = Impairment {
+            drop: 0.1,
+            duplicate: 0.1,
+            corrupt: 0.1,
+            reorder: 0.1,
+            reorder_delay: Duration::from_millis(10),
+            delay: Delay::Uniform {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+            },
+        };
+        let mut impairer = Impairer::new(impairment, seed);
+        let start = Instant::now();
+        for number in 0..1000u16 {
+            impairer.push(&number.to_le_bytes(), (), start);
+        }
+        let mut sent = Vec::new();
+        while let Some(due) = impairer.next_due() {
+            let (bytes, ()) = impairer.pop_due(due).unwrap();
+            sent.push((bytes, due - start));
+        }
+        (sent, impairer.stats())
+    }
+
+    #[test]
+    fn same_seed_same_decisions() {
+        let (sent, stats) = run(7);
+        assert_eq!(run(7), (sent.clone(), stats));
+        assert_eq!(stats.received, 1000);
+        assert_eq!(stats.sent, sent.len() as u64);
+        assert_eq!(stats.sent, 1000 - stats.dropped + stats.duplicated);
+        for count in [
+            stats.dropped,
+            stats.duplicated,
+            stats.corrupted,
+            stats.reordered,
+        ] {
+            assert!((50..150).contains(&count), "{:?}", stats);
+        }
+    }
+
+    #[test]
+    fn different_seeds_different_decisions() {
+        let runs: Vec<_> = (0..4).map(run).collect();
+        for (i, a) in runs.iter().enumerate() {
+            for b in &runs[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    /// Mean and standard deviation of 10000 delays, in milliseconds.
+    fn sample(delay: Delay, check: impl Fn(Duration) -> bool) -> (f64, f64) {
+        let mut rng = Rng(1);
+        let samples: Vec<f64> = (0..10_000)
+            .map(|_| {
+                let delay = rng.delay(&delay);
+                assert!(check(delay), "{:?}", delay);
+                delay.as_secs_f64() * 1000.0
+            })
+            .collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        (mean, variance.sqrt())
+    }
+
+    #[test]
+    fn delays() {
+        let ms = Duration::from_millis;
+        assert_eq!(sample(Delay::None, |d| d == Duration::ZERO), (0.0, 0.0));
+        assert_eq!(sample(Delay::Fixed(ms(3)), |d| d == ms(3)), (3.0, 0.0));
+
+        let (mean, std_dev) = sample(
+            Delay::Uniform {
+                min: ms(10),
+                max: ms(20),
+            },
+            |d| (ms(10)..ms(20)).contains(&d),
+        );
+        assert!((mean - 15.0).abs() < 0.2, "{}", mean);
+        // A uniform distribution over 10 ms has a standard deviation of 10 / sqrt(12).
+        assert!((std_dev - 2.887).abs() < 0.1, "{}", std_dev);
+
+        let (mean, std_dev) = sample(
+            Delay::Normal {
+                mean: ms(50),
+                std_dev: ms(5),
+            },
+            |_| true,
+        );
+        assert!((mean - 50.0).abs() < 0.2, "{}", mean);
+        assert!((std_dev - 5.0).abs() < 0.2, "{}", std_dev);
+
+        // Clamped at zero rather than going negative.
+        let (mean, _) = sample(
+            Delay::Normal {
+                mean: Duration::ZERO,
+                std_dev: ms(5),
+            },
+            |_| true,
+        );
+        // Half the samples are zero, the rest follow a half-normal with mean 5 * sqrt(2 / pi).
+        assert!((mean - 1.995).abs() < 0.1, "{}", mean);
+    }
+}
@@ -0,0 +1,307 @@
+//! Estimating input latency from the motion timestamps in controller data.
+//!
+//! A server stamps each packet with the time its motion was sampled, in microseconds on its own
+//! clock. The client's clock isn't synchronized with it, so one-way latency is only known up to
+//! the offset between the two. [`LatencyProbe`] estimates the offset from the fastest packet
+//! it has seen recently, taking that one to have arrived after the base latency: zero unless
+//! set, e.g. to half the round trip of a protocol version request. Without a base latency the
+//! reported latencies are the delay on top of the fastest delivery, which is what queuing and
+//! jitter add.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::ControllerData;
+
+/// Samples kept by default, ten seconds at 100 packets per second.
+pub const DEFAULT_WINDOW: usize = 1000;
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    packet_number: u32,
+    motion_timestamp: u64,
+    /// Microseconds since the probe was created.
+    received: u64,
+}
+
+impl Sample {
+    /// Microseconds from the sample to its receipt, plus the offset between the clocks.
+    fn delay(&self) -> i64 {
+        self.received.wrapping_sub(self.motion_timestamp) as i64
+    }
+}
+
+/// Correlates the controller data of one slot with when it was received.
+///
+/// Feed it every packet of the slot with [`LatencyProbe::record`]; packet numbers count per
+/// slot, so packets from other slots would look lost or reordered. Packets without a motion
+/// timestamp are ignored.
+#[derive(Clone, Debug)]
+pub struct LatencyProbe {
+    start: Instant,
+    window: usize,
+    base_latency: Duration,
+    samples: VecDeque<Sample>,
+    lost: u64,
+    reordered: u64,
+}
+
+/// Statistics over the samples in a [`LatencyProbe`]'s window.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub samples: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Mean time between packets as received.
+    pub interval: Duration,
+    /// Standard deviation of the time between packets as received, near zero for a server
+    /// sending at a steady rate over a quiet network. Motion timestamps aren't used, since a
+    /// server may stamp the time it meant to send at rather than when it did.
+    pub interval_jitter: Duration,
+    /// Packets missing from the sequence of packet numbers and not received late, since the
+    /// probe was created.
+    pub lost: u64,
+    /// Packets that arrived after a later one, since the probe was created. They aren't
+    /// sampled.
+    pub reordered: u64,
+}
+
+impl LatencyProbe {
+    pub fn new() -> Self {
+        LatencyProbe {
+            start: Instant::now(),
+            window: DEFAULT_WINDOW,
+            base_latency: Duration::ZERO,
+            samples: VecDeque::new(),
+            lost: 0,
+            reordered: 0,
+        }
+    }
+
+    /// Sets how many of the latest samples are kept. The clock offset is estimated from them
+    /// alone, so the window should be short enough for the clocks not to drift much within it.
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.max(1);
+        while self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Sets the latency of the fastest packet, see the [module docs](self).
+    pub fn set_base_latency(&mut self, base_latency: Duration) {
+        self.base_latency = base_latency;
+    }
+
+    /// Records that `data` was received at `received`.
+    ///
+    /// A packet number far below the last one means the server restarted, and starts over.
+    pub fn record(&mut self, data: &ControllerData, received: Instant) {
+        let motion_timestamp = data.motion_timestamp();
+        if motion_timestamp == 0 {
+            return;
+        }
+        let packet_number = data.packet_number();
+        if let Some(last) = self.samples.back() {
+            let ahead = packet_number.wrapping_sub(last.packet_number);
+            let behind = last.packet_number.wrapping_sub(packet_number);
+            if ahead == 0 {
+                return;
+            } else if behind < ahead && (behind as usize) < self.window {
+                // A copy of a packet already sampled isn't late.
+                if self.samples.iter().all(|s| s.packet_number != packet_number) {
+                    // It was counted as lost when the gap was seen.
+                    self.lost = self.lost.saturating_sub(1);
+                    self.reordered += 1;
+                }
+                return;
+            } else if behind < ahead {
+                self.samples.clear();
+            } else {
+                self.lost += u64::from(ahead - 1);
+            }
+        }
+        self.samples.push_back(Sample {
+            packet_number,
+            motion_timestamp,
+            received: received.saturating_duration_since(self.start).as_micros() as u64,
+        });
+        if self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Microseconds to add to a motion timestamp to get the time since the probe was created.
+    fn clock_offset(&self) -> Option<i64> {
+        let fastest = self.samples.iter().map(Sample::delay).min()?;
+        Some(fastest - self.base_latency.as_micros() as i64)
+    }
+
+    /// Returns when a packet's motion was sampled, by the local clock.
+    pub fn sampled_at(&self, motion_timestamp: u64) -> Option<Instant> {
+        let since_start = (motion_timestamp as i64).wrapping_add(self.clock_offset()?);
+        if since_start < 0 {
+            return self
+                .start
+                .checked_sub(Duration::from_micros(since_start.unsigned_abs()));
+        }
+        Some(self.start + Duration::from_micros(since_start as u64))
+    }
+
+    /// Latencies of the samples in the window, in ascending order.
+    fn latencies(&self) -> Vec<Duration> {
+        let offset = match self.clock_offset() {
+            Some(offset) => offset,
+            None => return Vec::new(),
+        };
+        let mut latencies: Vec<_> = self
+            .samples
+            .iter()
+            .map(|sample| Duration::from_micros((sample.delay() - offset) as u64))
+            .collect();
+        latencies.sort_unstable();
+        latencies
+    }
+
+    /// Returns the latency that `percent` of the samples in the window are at or below.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        percentile(&self.latencies(), percent)
+    }
+
+    /// Returns statistics over the window, or `None` if nothing was sampled yet.
+    pub fn report(&self) -> Option<LatencyReport> {
+        let latencies = self.latencies();
+        let max = *latencies.last()?;
+
+        // Microseconds per packet between consecutive samples, skipping over lost packets.
+        let intervals: Vec<f64> = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(a, b)| {
+                let elapsed = b.received as f64 - a.received as f64;
+                let packets = b.packet_number.wrapping_sub(a.packet_number);
+                elapsed / packets as f64
+            })
+            .collect();
+        let (interval, interval_jitter) = if intervals.is_empty() {
+            (0.0, 0.0)
+        } else {
+            let count = intervals.len() as f64;
+            let mean = intervals.iter().sum::<f64>() / count;
+            let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / count;
+            (mean, variance.sqrt())
+        };
+
+        Some(LatencyReport {
+            samples: latencies.len(),
+            p50: percentile(&latencies, 50.0)?,
+            p95: percentile(&latencies, 95.0)?,
+            p99: percentile(&latencies, 99.0)?,
+            max,
+            interval: micros(interval),
+            interval_jitter: micros(interval_jitter),
+            lost: self.lost,
+            reordered: self.reordered,
+        })
+    }
+}
+
+impl Default for LatencyProbe {
+    fn default() -> Self {
+        LatencyProbe::new()
+    }
+}
+
+fn micros(micros: f64) -> Duration {
+    Duration::from_nanos((micros.max(0.0) * 1000.0).round() as u64)
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[Duration], percent: f64) -> Option<Duration> {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len().max(1)) - 1).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crc::Crc32, types::*};
+
+    /// The server's clock, a minute ahead of the probe's.
+    const SERVER_CLOCK: u64 = 60_000_000;
+
+    fn data(packet_number: u32, motion_timestamp: u64) -> ControllerData {
+        let mut data = ControllerData::new(
+            0,
+            0,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [0; 6],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        );
+        data.set_packet_number(packet_number);
+        data.set_motion_timestamp(motion_timestamp);
+        data
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Packets sent every 10 ms, each received after the given latency.
+    fn probe(latencies: &[(u32, u64)]) -> LatencyProbe {
+        let mut probe = LatencyProbe::new();
+        for &(packet_number, latency) in latencies {
+            let sent = ms(10 * packet_number as u64);
+            let motion_timestamp = SERVER_CLOCK + sent.as_micros() as u64;
+            probe.record(
+                &data(packet_number, motion_timestamp),
+                probe.start + sent + ms(latency),
+            );
+        }
+        probe
+    }
+
+    #[test]
+    fn latencies() {
+        let latencies: Vec<_> = (0..100).map(|n| (n, 2 + (n as u64 % 10))).collect();
+        let mut probe = probe(&latencies);
+        let report = probe.report().unwrap();
+        assert_eq!(report.samples, 100);
+        assert_eq!(report.p50, ms(4));
+        assert_eq!(report.p95, ms(9));
+        assert_eq!(report.max, ms(9));
+        assert!(report.interval_jitter > ms(2));
+        assert_eq!((report.lost, report.reordered), (0, 0));
+        assert_eq!(probe.sampled_at(SERVER_CLOCK), Some(probe.start + ms(2)));
+
+        probe.set_base_latency(ms(2));
+        assert_eq!(probe.percentile(50.0), Some(ms(6)));
+        assert_eq!(probe.sampled_at(SERVER_CLOCK), Some(probe.start));
+    }
+
+    #[test]
+    fn lost_and_reordered() {
+        let probe = probe(&[(0, 1), (1, 1), (4, 1), (3, 1), (1, 1), (4, 1), (5, 1)]);
+        let report = probe.report().unwrap();
+        assert_eq!(report.samples, 4);
+        assert_eq!(report.interval, ms(10));
+        assert_eq!(report.interval_jitter, Duration::ZERO);
+        // Only 2 is missing: 3 arrived late, and 1 and 4 twice.
+        assert_eq!((report.lost, report.reordered), (1, 1));
+
+        // The server restarted.
+        let mut probe = probe;
+        probe.set_window(4);
+        probe.record(&data(0, 1), probe.start);
+        assert_eq!(probe.report().unwrap().samples, 1);
+        assert!(LatencyProbe::new().report().is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use core::fmt::{Display, Formatter, Result};
+
+use crate::*;
+
+/// Description of the byte layout of a buffer type, generated alongside its accessors.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub name: &'static str,
+    pub size: usize,
+    pub fields: &'static [Field],
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+    pub ty: &'static str,
+    pub kind: FieldKind,
+    /// Raw value and variant name for each value of an enum field.
+    pub values: &'static [(u64, &'static str)],
+    pub doc: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    Int,
+    Bool,
+    Bytes,
+    Enum,
+    Struct,
+    /// Bytes reserved in the layout whose accessors are written by hand.
+    Manual,
+}
+
+/// Layouts of every buffer type in the crate, in protocol order.
+pub const LAYOUTS: &[Layout] = &[
+    Header::LAYOUT,
+    ControllerHeader::LAYOUT,
+    Touch::LAYOUT,
+    RequestProtocolVersionInfo::LAYOUT,
+    ProtocolVersionInfo::LAYOUT,
+    RequestControllerInfo::LAYOUT,
+    ControllerInfo::LAYOUT,
+    RequestControllerData::LAYOUT,
+    ControllerData::LAYOUT,
+];
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        writeln!(f, "{} ({} bytes)", self.name, self.size)?;
+        writeln!(f, "{:>6}  {:>4}  {:<20} {:<16} description", "offset", "size", "field", "type")?;
+        for field in self.fields {
+            write!(
+                f,
+                "{:>6}  {:>4}  {:<20} {:<16} {}",
+                field.offset, field.size, field.name, field.ty, field.doc,
+            )?;
+            if !field.values.is_empty() {
+                if !field.doc.is_empty() {
+                    write!(f, " ")?;
+                }
+                write!(f, "[")?;
+                for (i, (raw, name)) in field.values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:#X} = {}", raw, name)?;
+                }
+                write!(f, "]")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,45 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![forbid(unsafe_code)]
+
+#[cfg(any(all(test, feature = "std"), feature = "proptest"))]
+pub mod arbitrary;
+#[cfg(feature = "std")]
+pub mod access;
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "std")]
+pub mod client;
+#[cfg(feature = "std")]
+pub mod conformance;
+pub mod crc;
 pub mod error;
+#[cfg(feature = "std")]
+pub mod impair;
+pub mod layout;
+#[cfg(feature = "std")]
+pub mod latency;
+pub mod merge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod pcap;
+#[cfg(feature = "std")]
+pub mod proxy;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+pub mod server;
+pub mod template;
+#[cfg(feature = "std")]
+pub mod transport;
 pub mod types;
 
 use core::convert::{TryFrom, TryInto};
 use core::hash::Hasher;
 
+use dsu_protocol_macros::layout;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout};
+
 use error::*;
 use types::*;
 
@@ -14,6 +50,9 @@ pub const MESSAGE_PROTOCOL: u32 = 0x100000;
 pub const MESSAGE_INFO: u32 = 0x100001;
 pub const MESSAGE_DATA: u32 = 0x100002;
 
+/// Size of the largest message, `ControllerData`.
+pub const MAX_MESSAGE_SIZE: usize = 100;
+
 trait BufType {
     const SIZE: usize;
 }
@@ -33,7 +72,7 @@ macro_rules! buf_type {
     };
     ($name:ident, $size:expr) => {
         #[repr(transparent)]
-        #[derive(Clone)]
+        #[derive(Clone, Debug, FromBytes, IntoBytes, Immutable, KnownLayout)]
         pub struct $name {
             pub bytes: [u8; $size],
         }
@@ -54,12 +93,12 @@ macro_rules! buf_type {
 
         impl<'a> From<&'a [u8; $size]> for &'a $name {
             fn from(bytes: &'a [u8; $size]) -> Self {
-                unsafe { std::mem::transmute(bytes) }
+                zerocopy::transmute_ref!(bytes)
             }
         }
 
         impl<'a> TryFrom<&'a [u8]> for &'a $name {
-            type Error = std::array::TryFromSliceError;
+            type Error = core::array::TryFromSliceError;
 
             fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
                 let bytes = <&[u8; $size] as TryFrom<&[u8]>>::try_from(bytes)?;
@@ -68,7 +107,7 @@ macro_rules! buf_type {
         }
 
         impl<'a> TryFrom<&'a mut [u8]> for &'a mut $name {
-            type Error = std::array::TryFromSliceError;
+            type Error = core::array::TryFromSliceError;
 
             fn try_from(bytes: &'a mut [u8]) -> Result<Self, Self::Error> {
                 let bytes = <&mut [u8; $size] as TryFrom<&mut [u8]>>::try_from(bytes)?;
@@ -78,11 +117,11 @@ macro_rules! buf_type {
 
         impl<'a> From<&'a mut [u8; $size]> for &'a mut $name {
             fn from(bytes: &'a mut [u8; $size]) -> Self {
-                unsafe { std::mem::transmute(bytes) }
+                zerocopy::transmute_mut!(bytes)
             }
         }
 
-        impl std::ops::Deref for $name {
+        impl core::ops::Deref for $name {
             type Target = [u8; $size];
             
             fn deref(&self) -> &[u8; $size] {
@@ -90,7 +129,7 @@ macro_rules! buf_type {
             }
         }
 
-        impl std::ops::DerefMut for $name {
+        impl core::ops::DerefMut for $name {
             fn deref_mut(&mut self) -> &mut [u8; $size] {
                 &mut self.bytes
             }
@@ -101,6 +140,7 @@ macro_rules! buf_type {
 macro_rules! impl_new {
     ($name:ty, $($field:ident : $fieldty:ty),* $(,)?) => {
         impl $name {
+            #[allow(clippy::too_many_arguments)]
             pub fn new<H: Hasher>(
                 $($field: $fieldty,)*
                 hasher: H
@@ -116,84 +156,27 @@ macro_rules! impl_new {
     };
 }
 
-macro_rules! int_fields {
-    ($name:ty, $($field:ident $set_field:ident : $itype:ty = $range:expr),* $(,)?) => {
-        impl $name {
-            $(
-                pub fn $field(&self) -> $itype {
-                    <$itype>::from_le_bytes(self.bytes[$range].try_into().unwrap())
-                }
-
-                pub fn $set_field(&mut self, val: $itype) {
-                    self.bytes[$range].copy_from_slice(&val.to_le_bytes());
-                }
-            )*
-        }
-    };
-}
-
-macro_rules! enum_fields {
-    ($name:ty, $($field:ident $set_field:ident from $valtype:ty [ $range:expr ] $enumtype:ty = $field_name:literal {
-        $($enumraw:expr => $enumval:path,)* $(,)?
-    })*) => {
-        impl $name {
-            $(
-                pub fn $field(&self) -> Result<$enumtype, Invalid<$valtype>> {
-                    match <$valtype>::from_le_bytes(self.bytes[$range].try_into().unwrap()) {
-                        $(val if val == $enumraw => Ok($enumval),)*
-                        invalid => Err(Invalid(invalid, $field_name)),
-                    }
-                }
-
-                pub fn $set_field(&mut self, val: $enumtype) {
-                    let intval: $valtype = match val {
-                        $($enumval => $enumraw,)*
-                    };
-                    self.bytes[$range].copy_from_slice(&intval.to_le_bytes());
-                }
-            )*
-        }
-    };
-}
-
-macro_rules! sub_fields {
-    ($name:ty, $($field:ident $field_mut:ident : $ftype:ty = $range:expr),* $(,)?) => {
-        impl $name {
-            $(
-                pub fn $field(&self) -> &$ftype {
-                    <&$ftype>::try_from(&self.bytes[$range]).unwrap()
-                }
-
-                pub fn $field_mut(&mut self) -> &mut $ftype {
-                    <&mut $ftype>::try_from(&mut self.bytes[$range]).unwrap()
-                }
-            )*
-        }
-    };
-}
-
-buf_type!(Header, 20);
-
-int_fields!(Header,
-    packet_length set_packet_length: u16 = 6..8,
-    crc32         set_crc32:         u32 = 8..12,
-    sender_id     set_sender_id:     u32 = 12..16,
-);
-
-enum_fields!(Header,
-    magic set_magic from u32[0..4] Magic = "magic" {
-        MAGIC_CLIENT => Magic::Client,
-        MAGIC_SERVER => Magic::Server,
-    }
-    message_type set_message_type from u32[16..20] MessageType = "message_type" {
-        MESSAGE_PROTOCOL => MessageType::ProtocolVersionInfo,
-        MESSAGE_INFO     => MessageType::ControllerInfo,
-        MESSAGE_DATA     => MessageType::ControllerData,
-    }
-    protocol set_protocol from u16[4..6] Protocol = "protocol" {
-        1001 => Protocol::Version1001,
+layout! {
+    Header[20] {
+        magic: u32 as Magic = 0 {
+            MAGIC_CLIENT => Magic::Client,
+            MAGIC_SERVER => Magic::Server,
+        },
+        protocol: u16 as Protocol = 4 {
+            1001 => Protocol::Version1001,
+        },
+        /// Length of the packet excluding the first 16 bytes.
+        packet_length: u16 = 6,
+        /// CRC32 of the whole packet, computed with this field zeroed.
+        crc32: u32 = 8,
+        sender_id: u32 = 12,
+        message_type: u32 as MessageType = 16 {
+            MESSAGE_PROTOCOL => MessageType::ProtocolVersionInfo,
+            MESSAGE_INFO     => MessageType::ControllerInfo,
+            MESSAGE_DATA     => MessageType::ControllerData,
+        },
     }
-);
+}
 
 impl Header {
     pub fn initialize(
@@ -214,11 +197,11 @@ impl Header {
     }
 }
 
-buf_type!(message RequestProtocolVersionInfo, 20);
-
-sub_fields!(RequestProtocolVersionInfo,
-    header header_mut: Header = 0..20,
-);
+layout! {
+    message RequestProtocolVersionInfo[20] {
+        header: Header = 0,
+    }
+}
 
 impl RequestProtocolVersionInfo {
     pub fn initialize<H: Hasher>(&mut self, sender_id: u32, hasher: H) {
@@ -236,17 +219,14 @@ impl RequestProtocolVersionInfo {
 
 impl_new!(RequestProtocolVersionInfo, sender_id: u32,);
 
-buf_type!(message ProtocolVersionInfo, 22);
-
-sub_fields!(ProtocolVersionInfo,
-    header header_mut: Header = 0..20,
-);
-
-enum_fields!(ProtocolVersionInfo,
-    protocol set_protocol from u16[(20 + 0)..22] Protocol = "protocol" {
-        1001 => Protocol::Version1001,
+layout! {
+    message ProtocolVersionInfo[22] {
+        header: Header = 0,
+        protocol: u16 as Protocol = 20 {
+            1001 => Protocol::Version1001,
+        },
     }
-);
+}
 
 impl ProtocolVersionInfo {
     pub fn initialize<H: Hasher>(&mut self, sender_id: u32, protocol: Protocol, hasher: H) {
@@ -265,40 +245,38 @@ impl ProtocolVersionInfo {
 
 impl_new!(ProtocolVersionInfo, sender_id: u32, protocol: Protocol,);
 
-buf_type!(ControllerHeader, 11);
-
-int_fields!(ControllerHeader,
-    slot set_slot: u8 = 0..1,
-);
-
-enum_fields!(ControllerHeader,
-    state set_state from u8[1..2] State = "state" {
-        0 => State::Disconnected,
-        1 => State::Reserved,
-        2 => State::Connected,
-    }
-    model set_model from u8[2..3] Model = "model" {
-        0 => Model::NotApplicable,
-        1 => Model::PartialGyro,
-        2 => Model::FullGyro,
-        3 => Model::Unused,
-    }
-    connection_type set_connection_type from u8[3..4] ConnectionType = "connection_type" {
-        0 => ConnectionType::NotApplicable,
-        1 => ConnectionType::Usb,
-        2 => ConnectionType::Bluetooth,
+layout! {
+    ControllerHeader[11] {
+        slot: u8 = 0,
+        state: u8 as State = 1 {
+            0 => State::Disconnected,
+            1 => State::Reserved,
+            2 => State::Connected,
+        },
+        model: u8 as Model = 2 {
+            0 => Model::NotApplicable,
+            1 => Model::PartialGyro,
+            2 => Model::FullGyro,
+            3 => Model::Unused,
+        },
+        connection_type: u8 as ConnectionType = 3 {
+            0 => ConnectionType::NotApplicable,
+            1 => ConnectionType::Usb,
+            2 => ConnectionType::Bluetooth,
+        },
+        mac: [u8; 6] = 4,
+        battery_status: u8 as BatteryStatus = 10 {
+            0x00 => BatteryStatus::NotApplicable,
+            0x01 => BatteryStatus::Dying,
+            0x02 => BatteryStatus::Low,
+            0x03 => BatteryStatus::Medium,
+            0x04 => BatteryStatus::High,
+            0x05 => BatteryStatus::Full,
+            0xEE => BatteryStatus::Charging,
+            0xEF => BatteryStatus::Charged,
+        },
     }
-    battery_status set_battery_status from u8[10..11] BatteryStatus = "battery_status" {
-        0x00 => BatteryStatus::NotApplicable,
-        0x01 => BatteryStatus::Dying,
-        0x02 => BatteryStatus::Low,
-        0x03 => BatteryStatus::Medium,
-        0x04 => BatteryStatus::High,
-        0x05 => BatteryStatus::Full,
-        0xEE => BatteryStatus::Charging,
-        0xEF => BatteryStatus::Charged,
-    }
-);
+}
 
 impl ControllerHeader {
     pub fn initialize(
@@ -317,22 +295,19 @@ impl ControllerHeader {
         *self.mac_mut() = mac;
         self.set_battery_status(battery_status);
     }
+}
 
-    pub fn mac(&self) -> &[u8; 6] {
-        self.bytes[4..10].try_into().unwrap()
-    }
-
-    pub fn mac_mut(&mut self) -> &mut [u8; 6] {
-        (&mut self.bytes[4..10]).try_into().unwrap()
+layout! {
+    message RequestControllerInfo[28] {
+        header: Header = 0,
+        /// Number of slots requested, between 0 and 4.
+        #[manual]
+        num_slots: i32 = 20,
+        #[manual]
+        slots: [u8; 4] = 24,
     }
 }
 
-buf_type!(message RequestControllerInfo, 28);
-
-sub_fields!(RequestControllerInfo,
-    header header_mut: Header = 0..20,
-);
-
 impl RequestControllerInfo {
     pub fn initialize<H: Hasher>(
         &mut self,
@@ -340,27 +315,27 @@ impl RequestControllerInfo {
         slots: &[u8],
         hasher: H,
     ) -> Result<(), RequestControllerInfoError> {
-        let len = self.num_slots()? as u16;
+        self.set_slots(slots)?;
+        let len = slots.len() as u16;
         self.header_mut().initialize(
             Magic::Client,
             Protocol::Version1001,
             24 + len - 16,
             0,
             sender_id,
-            MessageType::ProtocolVersionInfo,
+            MessageType::ControllerInfo,
         );
-        self.set_slots(slots)?;
         self.update_crc(hasher);
         Ok(())
     }
 
     pub fn slots(&self) -> Result<&[u8], RequestControllerInfoError> {
-        let port = self.num_slots()? as usize;
+        let port = self.num_slots()?;
         Ok(&self.bytes[24..][..port])
     }
 
     pub fn set_slots(&mut self, slots: &[u8]) -> Result<(), RequestControllerInfoError> {
-        if slots.len() < 1 || 4 < slots.len() {
+        if !(1..=4).contains(&slots.len()) {
             return Err(RequestControllerInfoError::InvalidSlotsLength(
                 slots.len() as u32 as i32,
             ));
@@ -372,7 +347,7 @@ impl RequestControllerInfo {
 
     pub fn num_slots(&self) -> Result<usize, RequestControllerInfoError> {
         let port = i32::from_le_bytes(self.bytes[20..24].try_into().unwrap());
-        if port < 0 || 4 < port {
+        if !(0..=4).contains(&port) {
             return Err(RequestControllerInfoError::InvalidSlotsLength(port));
         }
         Ok(port as usize)
@@ -391,14 +366,15 @@ impl RequestControllerInfo {
     }
 }
 
-buf_type!(message ControllerInfo, 32);
-
-sub_fields!(ControllerInfo,
-    header header_mut: Header = 0..20,
-    controller_header controller_header_mut: ControllerHeader = 20..31,
-);
+layout! {
+    message ControllerInfo[32] {
+        header: Header = 0,
+        controller_header: ControllerHeader = 20,
+    }
+}
 
 impl ControllerInfo {
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize<H: Hasher>(
         &mut self,
         sender_id: u32,
@@ -441,23 +417,18 @@ impl_new!(
     battery_status: BatteryStatus,
 );
 
-buf_type!(message RequestControllerData, 28);
-
-sub_fields!(RequestControllerData,
-    header header_mut: Header = 0..20,
-);
-
-int_fields!(RequestControllerData,
-    slot set_slot: u8 = 21..22,
-);
-
-enum_fields!(RequestControllerData,
-    registration set_registration from u8[20..21] Registration = "registration" {
-        0 => Registration::AllControllers,
-        1 => Registration::SlotBased,
-        2 => Registration::MacBased,
+layout! {
+    message RequestControllerData[28] {
+        header: Header = 0,
+        registration: u8 as Registration = 20 {
+            0 => Registration::AllControllers,
+            1 => Registration::SlotBased,
+            2 => Registration::MacBased,
+        },
+        slot: u8 = 21,
+        mac: [u8; 6] = 22,
     }
-);
+}
 
 impl RequestControllerData {
     pub fn initialize<H: Hasher>(
@@ -481,14 +452,6 @@ impl RequestControllerData {
         *self.mac_mut() = mac;
         self.update_crc(hasher);
     }
-
-    pub fn mac(&self) -> &[u8; 6] {
-        self.bytes[22..28].try_into().unwrap()
-    }
-
-    pub fn mac_mut(&mut self) -> &mut [u8; 6] {
-        (&mut self.bytes[22..28]).try_into().unwrap()
-    }
 }
 
 impl_new!(
@@ -499,45 +462,49 @@ impl_new!(
     mac: [u8; 6],
 );
 
-buf_type!(message ControllerData, 100);
-
-sub_fields!(ControllerData,
-    header header_mut: Header = 0..20,
-    controller_header controller_header_mut: ControllerHeader = 20..31,
-    touch1 touch1_mut: Touch = 56..62,
-    touch2 touch2_mut: Touch = 62..68,
-);
-
-int_fields!(ControllerData,
-    packet_number     set_packet_number:     u32 = 32..36,
-    ps_button         set_ps_button:         u8  = 38..39,
-    touch_button      set_touch_button:      u8  = 39..40,
-    left_stick_x      set_left_stick_x:      u8  = 40..41,
-    left_stick_y      set_left_stick_y:      u8  = 41..42,
-    right_stick_x     set_right_stick_x:     u8  = 42..43,
-    right_stick_y     set_right_stick_y:     u8  = 43..44,
-    analog_dpad_left  set_analog_dpad_left:  u8  = 44..45,
-    analog_dpad_down  set_analog_dpad_down:  u8  = 45..46,
-    analog_dpad_right set_analog_dpad_right: u8  = 46..47,
-    analog_dpad_up    set_analog_dpad_up:    u8  = 47..48,
-    analog_y          set_analog_y:          u8  = 48..49,
-    analog_b          set_analog_b:          u8  = 49..50,
-    analog_a          set_analog_a:          u8  = 50..51,
-    analog_x          set_analog_x:          u8  = 51..52,
-    analog_r1         set_analog_r1:         u8  = 52..53,
-    analog_l1         set_analog_l1:         u8  = 53..54,
-    analog_r2         set_analog_r2:         u8  = 54..55,
-    analog_l2         set_analog_l2:         u8  = 55..56,
-    motion_timestamp  set_motion_timestamp:  u64 = 68..76,
-    accel_x           set_accel_x:           f32 = 76..80,
-    accel_y           set_accel_y:           f32 = 80..84,
-    accel_z           set_accel_z:           f32 = 84..88,
-    gyro_pitch        set_gyro_pitch:        f32 = 88..92,
-    gyro_yaw          set_gyro_yaw:          f32 = 92..96,
-    gyro_roll         set_gyro_roll:         f32 = 96..100,
-);
+layout! {
+    message ControllerData[100] {
+        header: Header = 0,
+        controller_header: ControllerHeader = 20,
+        connected: bool = 31,
+        packet_number: u32 = 32,
+        #[manual(Buttons)]
+        buttons: [u8; 2] = 36,
+        ps_button: u8 = 38,
+        touch_button: u8 = 39,
+        left_stick_x: u8 = 40,
+        left_stick_y: u8 = 41,
+        right_stick_x: u8 = 42,
+        right_stick_y: u8 = 43,
+        analog_dpad_left: u8 = 44,
+        analog_dpad_down: u8 = 45,
+        analog_dpad_right: u8 = 46,
+        analog_dpad_up: u8 = 47,
+        analog_y: u8 = 48,
+        analog_b: u8 = 49,
+        analog_a: u8 = 50,
+        analog_x: u8 = 51,
+        analog_r1: u8 = 52,
+        analog_l1: u8 = 53,
+        analog_r2: u8 = 54,
+        analog_l2: u8 = 55,
+        touch1: Touch = 56,
+        touch2: Touch = 62,
+        /// Motion timestamp in microseconds.
+        motion_timestamp: u64 = 68,
+        /// Acceleration in g.
+        accel_x: f32 = 76,
+        accel_y: f32 = 80,
+        accel_z: f32 = 84,
+        /// Angular velocity in degrees per second.
+        gyro_pitch: f32 = 88,
+        gyro_yaw: f32 = 92,
+        gyro_roll: f32 = 96,
+    }
+}
 
 impl ControllerData {
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize<H: Hasher>(
         &mut self,
         sender_id: u32,
@@ -570,14 +537,6 @@ impl ControllerData {
         self.update_crc(hasher);
     }
 
-    pub fn is_connected(&self) -> bool {
-        self.bytes[31] != 0
-    }
-
-    pub fn set_connected(&mut self, val: bool) {
-        self.bytes[31] = if val { 1 } else { 0 };
-    }
-
     pub fn buttons(&self) -> Buttons {
         Buttons(self.bytes[36..38].try_into().unwrap())
     }
@@ -615,24 +574,16 @@ impl_new!(
     connected: bool,
 );
 
-buf_type!(Touch, 6);
-
-int_fields!(Touch,
-    touch_id set_touch_id: u8 = 1..2,
-    touch_x  set_touch_x:  u8 = 2..4,
-    touch_y  set_touch_y:  u8 = 4..6,
-);
-
-impl Touch {
-    pub fn is_active(&self) -> bool {
-        self.bytes[0] != 0
-    }
-
-    pub fn set_active(&mut self, val: bool) {
-        self.bytes[0] = if val { 1 } else { 0 };
+layout! {
+    Touch[6] {
+        active: bool = 0,
+        touch_id: u8 = 1,
+        touch_x: u16 = 2,
+        touch_y: u16 = 4,
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum MessageRef<'a> {
     RequestProtocolVersionInfo(&'a RequestProtocolVersionInfo),
     ProtocolVersionInfo(&'a ProtocolVersionInfo),
@@ -644,49 +595,83 @@ pub enum MessageRef<'a> {
 
 impl<'a> MessageRef<'a> {
     pub fn parse<H: Hasher>(buf: &'a [u8], mut hasher: H) -> Result<Self, MessageParseError> {
-        let header = <&Header>::try_from(
-            &buf[0..20],
-        ).map_err(|_| MessageParseError::SliceTooSmall)?;
+        let actual = buf.len();
+        let header = buf
+            .get(0..20)
+            .and_then(|bytes| <&Header>::try_from(bytes).ok())
+            .ok_or(MessageParseError::SliceTooSmall {
+                message: None,
+                expected: Header::SIZE,
+                actual,
+                header: None,
+            })?;
+        let header = header.clone();
         let magic = header
             .magic()
-            .map_err(|Invalid(magic, _)| MessageParseError::InvalidMagic(magic))?;
+            .map_err(|Invalid(magic, _)| MessageParseError::InvalidMagic {
+                magic,
+                header: header.clone(),
+            })?;
         let message_type = header
             .message_type()
-            .map_err(|Invalid(id, _)| MessageParseError::InvalidMessageId(id))?;
+            .map_err(|Invalid(id, _)| MessageParseError::InvalidMessageId {
+                id,
+                magic,
+                header: header.clone(),
+            })?;
+        let wrong_size = |expected: usize| {
+            if actual < expected {
+                MessageParseError::SliceTooSmall {
+                    message: Some((magic, message_type)),
+                    expected,
+                    actual,
+                    header: Some(header.clone()),
+                }
+            } else {
+                MessageParseError::SliceTooLarge {
+                    message: (magic, message_type),
+                    expected,
+                    actual,
+                    header: header.clone(),
+                }
+            }
+        };
 
         let this = match (magic, message_type) {
             (Magic::Client, MessageType::ProtocolVersionInfo) => {
                 Self::RequestProtocolVersionInfo(RequestProtocolVersionInfo::from_ref(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(RequestProtocolVersionInfo::SIZE))?,
                 ))
             }
             (Magic::Server, MessageType::ProtocolVersionInfo) => {
                 Self::ProtocolVersionInfo(ProtocolVersionInfo::from_ref(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(ProtocolVersionInfo::SIZE))?,
                 ))
             }
             (Magic::Client, MessageType::ControllerInfo) => {
-                Self::RequestControllerInfo(RequestControllerInfo::from_ref(buf.try_into()
-                .map_err(|_| MessageParseError::SliceTooSmall)?,))
+                Self::RequestControllerInfo(RequestControllerInfo::from_ref(
+                    buf.try_into()
+                        .map_err(|_| wrong_size(RequestControllerInfo::SIZE))?,
+                ))
             }
             (Magic::Server, MessageType::ControllerInfo) => {
                 Self::ControllerInfo(ControllerInfo::from_ref(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(ControllerInfo::SIZE))?,
                 ))
             }
             (Magic::Client, MessageType::ControllerData) => {
                 Self::RequestControllerData(RequestControllerData::from_ref(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(RequestControllerData::SIZE))?,
                 ))
             }
             (Magic::Server, MessageType::ControllerData) => {
                 Self::ControllerData(ControllerData::from_ref(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(ControllerData::SIZE))?,
                 ))
             }
         };
@@ -708,8 +693,18 @@ impl<'a> MessageRef<'a> {
             return Err(MessageParseError::InvalidCrc32 {
                 expected: hash,
                 calculated: calc_hash,
+                message: (magic, message_type),
+                header,
             });
         }
+        if let Self::RequestControllerInfo(request) = &this {
+            request
+                .num_slots()
+                .map_err(|error| MessageParseError::RequestControllerInfoError {
+                    error,
+                    header: Some(header),
+                })?;
+        }
 
         Ok(this)
     }
@@ -740,50 +735,83 @@ impl<'a> MessageMut<'a> {
         buf: &'a mut [u8],
         mut hasher: H,
     ) -> Result<Self, MessageParseError> {
-        let header = Header::from_mut(
-            (&mut buf[0..20]).try_into()
-                .map_err(|_| MessageParseError::SliceTooSmall)?,
-        );
+        let actual = buf.len();
+        let header = buf
+            .get_mut(0..20)
+            .and_then(|bytes| <&mut Header>::try_from(bytes).ok())
+            .ok_or(MessageParseError::SliceTooSmall {
+                message: None,
+                expected: Header::SIZE,
+                actual,
+                header: None,
+            })?;
+        let header = header.clone();
         let magic = header
             .magic()
-            .map_err(|Invalid(magic, _)| MessageParseError::InvalidMagic(magic))?;
+            .map_err(|Invalid(magic, _)| MessageParseError::InvalidMagic {
+                magic,
+                header: header.clone(),
+            })?;
         let message_type = header
             .message_type()
-            .map_err(|Invalid(id, _)| MessageParseError::InvalidMessageId(id))?;
+            .map_err(|Invalid(id, _)| MessageParseError::InvalidMessageId {
+                id,
+                magic,
+                header: header.clone(),
+            })?;
+        let wrong_size = |expected: usize| {
+            if actual < expected {
+                MessageParseError::SliceTooSmall {
+                    message: Some((magic, message_type)),
+                    expected,
+                    actual,
+                    header: Some(header.clone()),
+                }
+            } else {
+                MessageParseError::SliceTooLarge {
+                    message: (magic, message_type),
+                    expected,
+                    actual,
+                    header: header.clone(),
+                }
+            }
+        };
 
         let this = match (magic, message_type) {
             (Magic::Client, MessageType::ProtocolVersionInfo) => {
                 Self::RequestProtocolVersionInfo(RequestProtocolVersionInfo::from_mut(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(RequestProtocolVersionInfo::SIZE))?,
                 ))
             }
             (Magic::Server, MessageType::ProtocolVersionInfo) => {
                 Self::ProtocolVersionInfo(ProtocolVersionInfo::from_mut(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(ProtocolVersionInfo::SIZE))?,
                 ))
             }
             (Magic::Client, MessageType::ControllerInfo) => {
-                Self::RequestControllerInfo(RequestControllerInfo::from_mut(buf.try_into()
-                .map_err(|_| MessageParseError::SliceTooSmall)?,))
+                Self::RequestControllerInfo(RequestControllerInfo::from_mut(
+                    buf.try_into()
+                        .map_err(|_| wrong_size(RequestControllerInfo::SIZE))?,
+                ))
             }
             (Magic::Server, MessageType::ControllerInfo) => {
                 Self::ControllerInfo(ControllerInfo::from_mut(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(ControllerInfo::SIZE))?,
                 ))
             }
             (Magic::Client, MessageType::ControllerData) => {
                 Self::RequestControllerData(RequestControllerData::from_mut(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(RequestControllerData::SIZE))?,
                 ))
             }
             (Magic::Server, MessageType::ControllerData) => {
                 Self::ControllerData(ControllerData::from_mut(
                     buf.try_into()
-                        .map_err(|_| MessageParseError::SliceTooSmall)?,
+                        .map_err(|_| wrong_size(ControllerData::SIZE))?,
                 ))
             }
         };
@@ -805,8 +833,18 @@ impl<'a> MessageMut<'a> {
             return Err(MessageParseError::InvalidCrc32 {
                 expected: hash,
                 calculated: calc_hash,
+                message: (magic, message_type),
+                header,
             });
         }
+        if let Self::RequestControllerInfo(request) = &this {
+            request
+                .num_slots()
+                .map_err(|error| MessageParseError::RequestControllerInfoError {
+                    error,
+                    header: Some(header),
+                })?;
+        }
 
         Ok(this)
     }
@@ -0,0 +1,143 @@
+//! Combining the inputs of one controller with the motion of another, e.g. a gamepad without
+//! an IMU and a phone.
+
+use core::hash::Hasher;
+
+use crate::{types::Model, ControllerData};
+
+/// Returns `input` with the accelerometer, gyro and motion timestamp of `motion`, advertised
+/// as a [`Model::FullGyro`] controller.
+pub fn merge<H: Hasher>(
+    input: &ControllerData,
+    motion: &ControllerData,
+    hasher: H,
+) -> ControllerData {
+    let mut merged = input.clone();
+    merged.controller_header_mut().set_model(Model::FullGyro);
+    merged.set_motion_timestamp(motion.motion_timestamp());
+    merged.set_accel_x(motion.accel_x());
+    merged.set_accel_y(motion.accel_y());
+    merged.set_accel_z(motion.accel_z());
+    merged.set_gyro_pitch(motion.gyro_pitch());
+    merged.set_gyro_yaw(motion.gyro_yaw());
+    merged.set_gyro_roll(motion.gyro_roll());
+    merged.update_crc(hasher);
+    merged
+}
+
+/// Merges two streams of controller data, keeping the latest frame of each.
+///
+/// Each update returns a merged frame once both streams have been seen, so the output runs at
+/// the combined rate of the two.
+#[derive(Clone, Debug, Default)]
+pub struct Merge {
+    input: Option<ControllerData>,
+    motion: Option<ControllerData>,
+}
+
+impl Merge {
+    pub fn new() -> Self {
+        Merge::default()
+    }
+
+    /// Updates the buttons, sticks and triggers.
+    pub fn update_input<H: Hasher>(
+        &mut self,
+        input: &ControllerData,
+        hasher: H,
+    ) -> Option<ControllerData> {
+        self.input = Some(input.clone());
+        self.merged(hasher)
+    }
+
+    /// Updates the accelerometer and gyro.
+    pub fn update_motion<H: Hasher>(
+        &mut self,
+        motion: &ControllerData,
+        hasher: H,
+    ) -> Option<ControllerData> {
+        self.motion = Some(motion.clone());
+        self.merged(hasher)
+    }
+
+    pub fn merged<H: Hasher>(&self, hasher: H) -> Option<ControllerData> {
+        Some(merge(self.input.as_ref()?, self.motion.as_ref()?, hasher))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crc::Crc32, types::*, MessageRef};
+
+    /// A connected controller whose buttons, sticks, touches and motion are all `fill`.
+    fn frame(slot: u8, model: Model, fill: u8) -> ControllerData {
+        let mut data = ControllerData::new(
+            7,
+            slot,
+            State::Connected,
+            model,
+            ConnectionType::Bluetooth,
+            [slot; 6],
+            BatteryStatus::High,
+            true,
+            Crc32::new(),
+        );
+        data.bytes[32..].fill(fill);
+        data.update_crc(Crc32::new());
+        data
+    }
+
+    #[test]
+    fn fields() {
+        let input = frame(1, Model::PartialGyro, 0x11);
+        let motion = frame(2, Model::FullGyro, 0x22);
+        let merged = merge(&input, &motion, Crc32::new());
+
+        let header = merged.controller_header();
+        assert_eq!(header.slot(), 1);
+        assert_eq!(*header.mac(), [1; 6]);
+        assert_eq!(header.model().ok(), Some(Model::FullGyro));
+        assert_eq!(
+            header.connection_type().ok(),
+            Some(ConnectionType::Bluetooth)
+        );
+        assert_eq!(merged.header().sender_id(), 7);
+        // Packet number, buttons, sticks, triggers and touches from the input, motion
+        // timestamp, accelerometer and gyro from the motion controller.
+        assert_eq!(merged.bytes[32..68], input.bytes[32..68]);
+        assert_eq!(merged.bytes[68..], motion.bytes[68..]);
+        assert_eq!(merged.motion_timestamp(), motion.motion_timestamp());
+        assert_eq!(merged.gyro_roll(), motion.gyro_roll());
+
+        assert!(matches!(
+            MessageRef::parse(&merged.bytes, Crc32::new()),
+            Ok(MessageRef::ControllerData(_))
+        ));
+    }
+
+    #[test]
+    fn ready_once_both_are_seen() {
+        let mut state = Merge::new();
+        assert!(state.merged(Crc32::new()).is_none());
+        assert!(state
+            .update_motion(&frame(2, Model::FullGyro, 1), Crc32::new())
+            .is_none());
+        let merged = state.update_input(&frame(1, Model::PartialGyro, 2), Crc32::new());
+        assert_eq!(merged.map(|m| (m.bytes[40], m.bytes[90])), Some((2, 1)));
+
+        // From then on every update of either side emits, with the other side's latest frame.
+        let merged = state.update_motion(&frame(2, Model::FullGyro, 3), Crc32::new());
+        assert_eq!(merged.map(|m| (m.bytes[40], m.bytes[90])), Some((2, 3)));
+        let merged = state.update_input(&frame(1, Model::PartialGyro, 4), Crc32::new());
+        assert_eq!(merged.map(|m| (m.bytes[40], m.bytes[90])), Some((4, 3)));
+
+        let mut state = Merge::new();
+        assert!(state
+            .update_input(&frame(1, Model::PartialGyro, 2), Crc32::new())
+            .is_none());
+        assert!(state
+            .update_input(&frame(1, Model::PartialGyro, 2), Crc32::new())
+            .is_none());
+    }
+}
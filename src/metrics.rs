@@ -0,0 +1,462 @@
+//! Counters describing what a [`Server`](crate::server::Server) has been doing, and a tiny
+//! HTTP endpoint serving them in the Prometheus text format.
+//!
+//! Take a snapshot with `Server::metrics`, or call [`MetricsEndpoint::poll`] from the loop
+//! driving the server and let Prometheus scrape it:
+//!
+//! ```no_run
+//! use dsu_protocol::{metrics::MetricsEndpoint, server::Server};
+//!
+//! let mut server = Server::bind_default()?;
+//! let mut endpoint = MetricsEndpoint::bind("127.0.0.1:9477")?;
+//! loop {
+//!     server.poll()?;
+//!     endpoint.poll(|| server.metrics());
+//!     # break;
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::MessageParseError,
+    server::{Rejections, SLOTS},
+    types::MessageType,
+    MessageRef,
+};
+
+/// Datagrams of each message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MessageCounts {
+    pub request_protocol_version_info: u64,
+    pub protocol_version_info: u64,
+    pub request_controller_info: u64,
+    pub controller_info: u64,
+    pub request_controller_data: u64,
+    pub controller_data: u64,
+}
+
+impl MessageCounts {
+    pub(crate) fn count(&mut self, message: &MessageRef) {
+        *match message {
+            MessageRef::RequestProtocolVersionInfo(_) => &mut self.request_protocol_version_info,
+            MessageRef::ProtocolVersionInfo(_) => &mut self.protocol_version_info,
+            MessageRef::RequestControllerInfo(_) => &mut self.request_controller_info,
+            MessageRef::ControllerInfo(_) => &mut self.controller_info,
+            MessageRef::RequestControllerData(_) => &mut self.request_controller_data,
+            MessageRef::ControllerData(_) => &mut self.controller_data,
+        } += 1;
+    }
+
+    /// Counts messages sent by a server.
+    pub(crate) fn count_response(&mut self, message_type: MessageType, count: u64) {
+        *match message_type {
+            MessageType::ProtocolVersionInfo => &mut self.protocol_version_info,
+            MessageType::ControllerInfo => &mut self.controller_info,
+            MessageType::ControllerData => &mut self.controller_data,
+        } += count;
+    }
+
+    fn iter(&self) -> [(&'static str, u64); 6] {
+        [
+            (
+                "RequestProtocolVersionInfo",
+                self.request_protocol_version_info,
+            ),
+            ("ProtocolVersionInfo", self.protocol_version_info),
+            ("RequestControllerInfo", self.request_controller_info),
+            ("ControllerInfo", self.controller_info),
+            ("RequestControllerData", self.request_controller_data),
+            ("ControllerData", self.controller_data),
+        ]
+    }
+}
+
+/// Datagrams that weren't valid messages, by [`MessageParseError::code`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParseErrorCounts {
+    pub slice_too_small: u64,
+    pub slice_too_large: u64,
+    pub invalid_magic: u64,
+    pub invalid_message_id: u64,
+    pub invalid_crc32: u64,
+    pub invalid_slots_length: u64,
+}
+
+impl ParseErrorCounts {
+    pub(crate) fn count(&mut self, err: &MessageParseError) {
+        *match err {
+            MessageParseError::SliceTooSmall { .. } => &mut self.slice_too_small,
+            MessageParseError::SliceTooLarge { .. } => &mut self.slice_too_large,
+            MessageParseError::InvalidMagic { .. } => &mut self.invalid_magic,
+            MessageParseError::InvalidMessageId { .. } => &mut self.invalid_message_id,
+            MessageParseError::InvalidCrc32 { .. } => &mut self.invalid_crc32,
+            MessageParseError::RequestControllerInfoError { .. } => {
+                &mut self.invalid_slots_length
+            }
+        } += 1;
+    }
+
+    fn iter(&self) -> [(&'static str, u64); 6] {
+        [
+            ("slice_too_small", self.slice_too_small),
+            ("slice_too_large", self.slice_too_large),
+            ("invalid_magic", self.invalid_magic),
+            ("invalid_message_id", self.invalid_message_id),
+            ("invalid_crc32", self.invalid_crc32),
+            ("invalid_slots_length", self.invalid_slots_length),
+        ]
+    }
+}
+
+/// Clients with a live subscription of each registration. A client subscribed in several
+/// ways counts once for each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubscriberCounts {
+    pub all_controllers: u64,
+    pub slot_based: u64,
+    pub mac_based: u64,
+}
+
+/// A snapshot of a server's metrics. Counters start at zero when the server is created.
+///
+/// Rates, such as the packets sent for each slot per second, are the difference between two
+/// snapshots over the difference in [`Metrics::uptime`]; see [`Metrics::slot_rates`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub uptime: Duration,
+    /// Valid messages received, including ones ignored because a server doesn't handle them.
+    /// Requests rejected by the server's limits or access list aren't read.
+    pub received: MessageCounts,
+    /// Datagrams sent, including dropped ones.
+    pub sent: MessageCounts,
+    pub parse_errors: ParseErrorCounts,
+    pub subscribers: SubscriberCounts,
+    /// `ControllerData` datagrams sent for each slot, including dropped ones.
+    pub slot_sent: [u64; SLOTS],
+    /// Datagrams that couldn't be sent, because the socket's send buffer was full or the peer's
+    /// address was unusable.
+    pub dropped_sends: u64,
+    pub rejections: Rejections,
+}
+
+impl Metrics {
+    /// `ControllerData` datagrams sent for each slot per second since `earlier`.
+    pub fn slot_rates(&self, earlier: &Metrics) -> [f64; SLOTS] {
+        let elapsed = self.uptime.saturating_sub(earlier.uptime).as_secs_f64();
+        let mut rates = [0.0; SLOTS];
+        if elapsed > 0.0 {
+            for (slot, rate) in rates.iter_mut().enumerate() {
+                let sent = self.slot_sent[slot].saturating_sub(earlier.slot_sent[slot]);
+                *rate = sent as f64 / elapsed;
+            }
+        }
+        rates
+    }
+
+    /// Writes the metrics in the Prometheus text exposition format.
+    pub fn write_prometheus(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        writeln!(
+            out,
+            "# HELP dsu_uptime_seconds Time since the server was created."
+        )?;
+        writeln!(out, "# TYPE dsu_uptime_seconds gauge")?;
+        writeln!(out, "dsu_uptime_seconds {}", self.uptime.as_secs_f64())?;
+        let mut metric =
+            |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| -> fmt::Result {
+                writeln!(out, "# HELP dsu_{} {}", name, help)?;
+                writeln!(out, "# TYPE dsu_{} {}", name, kind)?;
+                for (labels, value) in samples {
+                    writeln!(out, "dsu_{}{} {}", name, labels, value)?;
+                }
+                Ok(())
+            };
+        let labeled = |label: &str, values: &[(&str, u64)]| -> Vec<(String, u64)> {
+            values
+                .iter()
+                .map(|(value, count)| (format!("{{{}=\"{}\"}}", label, value), *count))
+                .collect()
+        };
+        let slots: Vec<_> = (0..SLOTS).map(|slot| slot.to_string()).collect();
+        let slot_sent: Vec<_> = slots
+            .iter()
+            .map(String::as_str)
+            .zip(self.slot_sent.iter().copied())
+            .collect();
+        let rejections = &self.rejections;
+
+        metric(
+            "received_total",
+            "counter",
+            "Valid messages received.",
+            &labeled("message", &self.received.iter()),
+        )?;
+        metric(
+            "sent_total",
+            "counter",
+            "Datagrams sent, including dropped ones.",
+            &labeled("message", &self.sent.iter()),
+        )?;
+        metric(
+            "parse_errors_total",
+            "counter",
+            "Datagrams that weren't valid messages.",
+            &labeled("error", &self.parse_errors.iter()),
+        )?;
+        metric(
+            "subscribers",
+            "gauge",
+            "Clients with a live subscription.",
+            &labeled(
+                "registration",
+                &[
+                    ("AllControllers", self.subscribers.all_controllers),
+                    ("SlotBased", self.subscribers.slot_based),
+                    ("MacBased", self.subscribers.mac_based),
+                ],
+            ),
+        )?;
+        metric(
+            "slot_sent_total",
+            "counter",
+            "Controller data sent for each slot, including dropped datagrams.",
+            &labeled("slot", &slot_sent),
+        )?;
+        metric(
+            "dropped_sends_total",
+            "counter",
+            "Datagrams dropped because the send buffer was full.",
+            &[(String::new(), self.dropped_sends)],
+        )?;
+        metric(
+            "rejected_total",
+            "counter",
+            "Requests ignored because of the server's limits or access list.",
+            &labeled(
+                "reason",
+                &[
+                    ("denied", rejections.denied),
+                    ("rate_limited", rejections.rate_limited),
+                    ("no_handshake", rejections.no_handshake),
+                    ("too_many_subscribers", rejections.too_many_subscribers),
+                ],
+            ),
+        )
+    }
+}
+
+/// Serves [`Metrics`] over HTTP to whoever connects, without blocking the thread driving the
+/// server.
+///
+/// It answers every request with the metrics, whatever the path, and is only meant to be
+/// reachable from the same machine.
+#[derive(Debug)]
+pub struct MetricsEndpoint {
+    listener: TcpListener,
+    scrapes: Vec<Scrape>,
+}
+
+/// Longest a scrape may take, from connecting to reading the whole response, before it's
+/// dropped.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most scrapes answered at once. Connections beyond this are closed right away.
+const MAX_SCRAPES: usize = 16;
+
+/// Bytes of a request read, enough for the request line and the usual headers.
+const MAX_REQUEST_SIZE: usize = 1024;
+
+/// A connection being answered.
+#[derive(Debug)]
+struct Scrape {
+    stream: TcpStream,
+    from: SocketAddr,
+    accepted: Instant,
+    request: Vec<u8>,
+    /// The response once the request was read, and how much of it was written.
+    response: Option<(Vec<u8>, usize)>,
+}
+
+impl Scrape {
+    /// Reads what arrived of the request, and returns whether all of it has. Only the end
+    /// of the request headers matters.
+    fn read_request(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; MAX_REQUEST_SIZE];
+        loop {
+            if self.request.len() >= MAX_REQUEST_SIZE
+                || self.request.windows(4).any(|w| w == b"\r\n\r\n")
+            {
+                return Ok(true);
+            }
+            match self.stream.read(&mut buf[..MAX_REQUEST_SIZE - self.request.len()]) {
+                Ok(0) => return Ok(true),
+                Ok(read) => self.request.extend_from_slice(&buf[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Writes as much of the response as the socket takes, and returns whether all of it was
+    /// written.
+    fn write_response(&mut self) -> io::Result<bool> {
+        let (response, written) = self.response.as_mut().expect("the request was read");
+        while *written < response.len() {
+            match self.stream.write(&response[*written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(sent) => *written += sent,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl MetricsEndpoint {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(MetricsEndpoint {
+            listener,
+            scrapes: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts new scrapes and makes progress on the ones waiting, taking one snapshot with
+    /// `metrics` if any is ready for its response. Never blocks: a scrape that is slow to
+    /// send its request or read the response is carried over to the next call, and dropped
+    /// after a second. Failing to accept a connection is logged and retried on the next call,
+    /// so one misbehaving scraper can't stop the server.
+    pub fn poll(&mut self, mut metrics: impl FnMut() -> Metrics) {
+        loop {
+            let (stream, from) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    log::warn!("failed to accept a metrics scrape: {}", err);
+                    break;
+                }
+            };
+            if self.scrapes.len() >= MAX_SCRAPES {
+                log::debug!("too many scrapes, dropping {}", from);
+                continue;
+            }
+            if let Err(err) = stream.set_nonblocking(true) {
+                log::debug!("failed to serve metrics to {}: {}", from, err);
+                continue;
+            }
+            self.scrapes.push(Scrape {
+                stream,
+                from,
+                accepted: Instant::now(),
+                request: Vec::new(),
+                response: None,
+            });
+        }
+
+        let now = Instant::now();
+        let mut body = None;
+        self.scrapes.retain_mut(|scrape| {
+            match advance(scrape, &mut body, &mut metrics) {
+                Ok(true) => return false,
+                Ok(false) => {}
+                Err(err) => {
+                    log::debug!("failed to serve metrics to {}: {}", scrape.from, err);
+                    return false;
+                }
+            }
+            let live = now.saturating_duration_since(scrape.accepted) < SCRAPE_TIMEOUT;
+            if !live {
+                log::debug!("scrape from {} timed out", scrape.from);
+            }
+            live
+        });
+    }
+}
+
+/// Moves `scrape` along, and returns whether it's done.
+fn advance(
+    scrape: &mut Scrape,
+    body: &mut Option<String>,
+    metrics: &mut impl FnMut() -> Metrics,
+) -> io::Result<bool> {
+    if scrape.response.is_none() {
+        if !scrape.read_request()? {
+            return Ok(false);
+        }
+        let body = body.get_or_insert_with(|| {
+            let mut body = String::new();
+            metrics()
+                .write_prometheus(&mut body)
+                .expect("writing to a String can't fail");
+            body
+        });
+        let response = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            body.len(),
+            body,
+        );
+        scrape.response = Some((response.into_bytes(), 0));
+    }
+    scrape.write_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Shutdown, thread};
+
+    use super::*;
+
+    #[test]
+    fn endpoint() {
+        let mut endpoint = MetricsEndpoint::bind("127.0.0.1:0").unwrap();
+        endpoint.poll(|| panic!("nobody asked"));
+        let addr = endpoint.local_addr().unwrap();
+
+        // A client that never sends its request doesn't hold up the others.
+        let _idle = TcpStream::connect(addr).unwrap();
+        let scrape = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let mut metrics = Metrics::default();
+        metrics.slot_sent[2] = 7;
+        metrics.rejections.denied = 3;
+        while !scrape.is_finished() {
+            let start = Instant::now();
+            endpoint.poll(|| metrics.clone());
+            assert!(start.elapsed() < Duration::from_millis(50));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(endpoint.scrapes.len(), 1);
+
+        let response = scrape.join().unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(body.contains("# TYPE dsu_slot_sent_total counter\n"));
+        assert!(body.contains("dsu_slot_sent_total{slot=\"2\"} 7\n"));
+        assert!(body.contains("dsu_rejected_total{reason=\"denied\"} 3\n"));
+        assert!(body.contains("dsu_dropped_sends_total 0\n"));
+    }
+}
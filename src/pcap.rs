@@ -0,0 +1,647 @@
+//! Reading DSU traffic from pcap and pcapng captures, and writing it to pcapng.
+//!
+//! Captures may use Ethernet (with VLAN tags), BSD loopback, raw IP or Linux cooked link
+//! layers over IPv4 or IPv6. Fragmented IP packets are skipped. Written captures use the raw
+//! IP link type with one interface, which Wireshark opens directly.
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use crate::{client::DEFAULT_PORT, crc::Crc32, error::MessageParseError, MessageRef};
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IP_PROTOCOL_UDP: u8 = 17;
+
+/// A UDP datagram taken from or written to a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Datagram {
+    /// Capture timestamp, usually since the Unix epoch.
+    pub time: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+impl Datagram {
+    pub fn message(&self) -> Result<MessageRef<'_>, MessageParseError> {
+        MessageRef::parse(&self.payload, Crc32::new())
+    }
+}
+
+/// The datagrams exchanged between one client and one server, in capture order.
+#[derive(Clone, Debug)]
+pub struct Flow {
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    pub datagrams: Vec<Datagram>,
+}
+
+/// Reads every UDP datagram sent to or from `port` in a pcap or pcapng capture.
+pub fn read_datagrams<R: Read>(mut input: R, port: u16) -> io::Result<Vec<Datagram>> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let mut datagrams = Vec::new();
+    let magic = data
+        .get(0..4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid_data("capture is empty"))?;
+    if magic == PCAPNG_SECTION_HEADER {
+        read_pcapng(&data, &mut |time, linktype, packet| {
+            push_datagram(&mut datagrams, time, linktype, packet, port)
+        })?;
+    } else {
+        read_pcap(&data, &mut |time, linktype, packet| {
+            push_datagram(&mut datagrams, time, linktype, packet, port)
+        })?;
+    }
+    Ok(datagrams)
+}
+
+/// Like [`read_datagrams`] with the default DSU port.
+pub fn read_dsu_datagrams<R: Read>(input: R) -> io::Result<Vec<Datagram>> {
+    read_datagrams(input, DEFAULT_PORT)
+}
+
+/// Groups datagrams into flows. The server of a flow is the endpoint using `port`.
+pub fn flows(datagrams: impl IntoIterator<Item = Datagram>, port: u16) -> Vec<Flow> {
+    let mut flows: Vec<Flow> = Vec::new();
+    let mut index = HashMap::new();
+    for datagram in datagrams {
+        let (client, server) = if datagram.src.port() == port && datagram.dst.port() != port {
+            (datagram.dst, datagram.src)
+        } else {
+            (datagram.src, datagram.dst)
+        };
+        let key = if index.contains_key(&(server, client)) {
+            (server, client)
+        } else {
+            (client, server)
+        };
+        let i = *index.entry(key).or_insert_with(|| {
+            flows.push(Flow {
+                client: key.0,
+                server: key.1,
+                datagrams: Vec::new(),
+            });
+            flows.len() - 1
+        });
+        flows[i].datagrams.push(datagram);
+    }
+    flows
+}
+
+fn push_datagram(
+    datagrams: &mut Vec<Datagram>,
+    time: Duration,
+    linktype: u16,
+    packet: &[u8],
+    port: u16,
+) {
+    if let Some(datagram) = parse_packet(time, linktype, packet) {
+        if datagram.src.port() == port || datagram.dst.port() == port {
+            datagrams.push(datagram);
+        }
+    }
+}
+
+fn read_pcap(data: &[u8], on_packet: &mut dyn FnMut(Duration, u16, &[u8])) -> io::Result<()> {
+    let header = data
+        .get(0..24)
+        .ok_or_else(|| invalid_data("truncated pcap header"))?;
+    let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+    let (big_endian, nanos) = match magic {
+        PCAP_MAGIC_MICROS => (false, false),
+        PCAP_MAGIC_NANOS => (false, true),
+        m if m.swap_bytes() == PCAP_MAGIC_MICROS => (true, false),
+        m if m.swap_bytes() == PCAP_MAGIC_NANOS => (true, true),
+        _ => return Err(invalid_data("not a pcap or pcapng capture")),
+    };
+    let bytes = Bytes { big_endian };
+    let linktype = bytes.u32(&header[20..24]) as u16;
+
+    let mut rest = &data[24..];
+    while rest.len() >= 16 {
+        let seconds = bytes.u32(&rest[0..4]) as u64;
+        let fraction = bytes.u32(&rest[4..8]) as u64;
+        let captured = bytes.u32(&rest[8..12]) as usize;
+        let packet = rest
+            .get(16..16 + captured)
+            .ok_or_else(|| invalid_data("truncated pcap record"))?;
+        let time = Duration::from_secs(seconds)
+            + if nanos {
+                Duration::from_nanos(fraction)
+            } else {
+                Duration::from_micros(fraction)
+            };
+        on_packet(time, linktype, packet);
+        rest = &rest[16 + captured..];
+    }
+    Ok(())
+}
+
+struct Interface {
+    linktype: u16,
+    /// Timestamp units per second.
+    resolution: u64,
+}
+
+fn read_pcapng(data: &[u8], on_packet: &mut dyn FnMut(Duration, u16, &[u8])) -> io::Result<()> {
+    let mut bytes = Bytes { big_endian: false };
+    let mut interfaces = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 12 {
+        if u32::from_le_bytes(rest[0..4].try_into().unwrap()) == PCAPNG_SECTION_HEADER {
+            let bom = u32::from_le_bytes(rest[8..12].try_into().unwrap());
+            bytes.big_endian = match bom {
+                PCAPNG_BYTE_ORDER_MAGIC => false,
+                m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+                _ => return Err(invalid_data("invalid pcapng byte order magic")),
+            };
+            interfaces.clear();
+        }
+        let block_type = bytes.u32(&rest[0..4]);
+        let length = bytes.u32(&rest[4..8]) as usize;
+        if length < 12 || !length.is_multiple_of(4) {
+            return Err(invalid_data("invalid pcapng block length"));
+        }
+        let block = rest
+            .get(..length)
+            .ok_or_else(|| invalid_data("truncated pcapng block"))?;
+        let body = &block[8..length - 4];
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                interfaces.push(Interface {
+                    linktype: bytes.u16(&body[0..2]),
+                    resolution: tsresol(&bytes, &body[8..]),
+                });
+            }
+            PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                let interface = interfaces
+                    .get(bytes.u32(&body[0..4]) as usize)
+                    .ok_or_else(|| invalid_data("pcapng packet for unknown interface"))?;
+                let timestamp =
+                    (bytes.u32(&body[4..8]) as u64) << 32 | bytes.u32(&body[8..12]) as u64;
+                let captured = bytes.u32(&body[12..16]) as usize;
+                let packet = body
+                    .get(20..20 + captured)
+                    .ok_or_else(|| invalid_data("truncated pcapng packet"))?;
+                on_packet(
+                    pcapng_time(timestamp, interface.resolution),
+                    interface.linktype,
+                    packet,
+                );
+            }
+            PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| invalid_data("pcapng packet for unknown interface"))?;
+                let original = bytes.u32(&body[0..4]) as usize;
+                let packet = &body[4..][..original.min(body.len() - 4)];
+                on_packet(Duration::ZERO, interface.linktype, packet);
+            }
+            _ => {}
+        }
+        rest = &rest[length..];
+    }
+    Ok(())
+}
+
+/// Converts a timestamp in units of `1 / resolution` seconds. Times finer than a nanosecond,
+/// as with picosecond interfaces, are rounded down.
+fn pcapng_time(timestamp: u64, resolution: u64) -> Duration {
+    let nanos = (timestamp % resolution) as u128 * 1_000_000_000 / resolution as u128;
+    Duration::from_secs(timestamp / resolution) + Duration::from_nanos(nanos as u64)
+}
+
+/// Reads the `if_tsresol` option of an interface description block.
+fn tsresol(bytes: &Bytes, mut options: &[u8]) -> u64 {
+    while options.len() >= 4 {
+        let code = bytes.u16(&options[0..2]);
+        let length = bytes.u16(&options[2..4]) as usize;
+        if code == PCAPNG_OPTION_TSRESOL && length == 1 && options.len() > 4 {
+            let value = options[4];
+            let exponent = (value & 0x7F) as u32;
+            return if value & 0x80 == 0 {
+                10u64.checked_pow(exponent).unwrap_or(1_000_000)
+            } else {
+                2u64.checked_pow(exponent).unwrap_or(1_000_000)
+            };
+        }
+        if code == 0 {
+            break;
+        }
+        let padded = (length + 3) & !3;
+        options = options.get(4 + padded..).unwrap_or(&[]);
+    }
+    1_000_000
+}
+
+fn parse_packet(time: Duration, linktype: u16, packet: &[u8]) -> Option<Datagram> {
+    let ip = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype =
+                u16::from_be_bytes(packet.get(offset..offset + 2)?.try_into().ok()?);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16::from_be_bytes(packet.get(offset..offset + 2)?.try_into().ok()?);
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => packet.get(offset + 2..)?,
+                _ => return None,
+            }
+        }
+        LINKTYPE_NULL => packet.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => packet,
+        LINKTYPE_LINUX_SLL => packet.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => packet.get(20..)?,
+        _ => return None,
+    };
+    let (src, dst, udp) = match ip.first()? >> 4 {
+        4 => parse_ipv4(ip)?,
+        6 => parse_ipv6(ip)?,
+        _ => return None,
+    };
+    let src_port = u16::from_be_bytes(udp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let length = u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?) as usize;
+    let payload = udp.get(8..length.max(8))?;
+    Some(Datagram {
+        time,
+        src: SocketAddr::new(src, src_port),
+        dst: SocketAddr::new(dst, dst_port),
+        payload: payload.to_vec(),
+    })
+}
+
+fn parse_ipv4(ip: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    let header_len = ((ip.first()? & 0x0F) as usize) * 4;
+    let total_len = u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?) as usize;
+    let fragment = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?);
+    let more_fragments = fragment & 0x2000 != 0;
+    if more_fragments || fragment & 0x1FFF != 0 || *ip.get(9)? != IP_PROTOCOL_UDP {
+        return None;
+    }
+    let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+    let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+    let end = total_len.min(ip.len());
+    Some((
+        Ipv4Addr::from(src).into(),
+        Ipv4Addr::from(dst).into(),
+        ip.get(header_len..end)?,
+    ))
+}
+
+fn parse_ipv6(ip: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    let payload_len = u16::from_be_bytes(ip.get(4..6)?.try_into().ok()?) as usize;
+    let mut next = *ip.get(6)?;
+    let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+    let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+    let mut offset = 40;
+    loop {
+        match next {
+            IP_PROTOCOL_UDP => break,
+            // Hop-by-hop, routing and destination options.
+            0 | 43 | 60 => {
+                next = *ip.get(offset)?;
+                offset += (*ip.get(offset + 1)? as usize + 1) * 8;
+            }
+            _ => return None,
+        }
+    }
+    let end = (40 + payload_len).min(ip.len());
+    Some((
+        Ipv6Addr::from(src).into(),
+        Ipv6Addr::from(dst).into(),
+        ip.get(offset..end)?,
+    ))
+}
+
+struct Bytes {
+    big_endian: bool,
+}
+
+impl Bytes {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Writes datagrams to a pcapng capture.
+pub struct PcapngWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length is not known in advance.
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut out, PCAPNG_SECTION_HEADER, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut out, PCAPNG_INTERFACE_DESCRIPTION, &idb)?;
+
+        Ok(PcapngWriter { out })
+    }
+
+    /// Writes a datagram as an IP packet. Both addresses must be the same family.
+    pub fn write_datagram(&mut self, datagram: &Datagram) -> io::Result<()> {
+        let packet = ip_packet(datagram)?;
+        let micros = datagram.time.as_micros() as u64;
+        let mut epb = Vec::with_capacity(20 + packet.len() + 3);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&packet);
+        write_block(&mut self.out, PCAPNG_ENHANCED_PACKET, &epb)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+fn write_block<W: Write>(out: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let length = (12 + body.len() + padding) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&length.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&[0u8; 3][..padding])?;
+    out.write_all(&length.to_le_bytes())
+}
+
+fn ip_packet(datagram: &Datagram) -> io::Result<Vec<u8>> {
+    let udp_len = 8 + datagram.payload.len();
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&datagram.src.port().to_be_bytes());
+    udp.extend_from_slice(&datagram.dst.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(&datagram.payload);
+
+    let mut packet = Vec::new();
+    let mut pseudo = Vec::new();
+    match (datagram.src.ip(), datagram.dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            // Identification, don't fragment, TTL 64, UDP, checksum placeholder.
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, IP_PROTOCOL_UDP, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let checksum = internet_checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&[0, IP_PROTOCOL_UDP]);
+            pseudo.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            packet.extend_from_slice(&[IP_PROTOCOL_UDP, 64]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+
+            pseudo.extend_from_slice(&src.octets());
+            pseudo.extend_from_slice(&dst.octets());
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, IP_PROTOCOL_UDP]);
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram source and destination are different address families",
+            ))
+        }
+    }
+
+    let checksum = match internet_checksum(&[&pseudo, &udp]) {
+        // Zero means "no checksum", so it is sent as all ones.
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&udp);
+    Ok(packet)
+}
+
+fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for chunk in part.chunks(2) {
+            let word = match chunk {
+                [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+                [hi] => u16::from_be_bytes([*hi, 0]),
+                _ => unreachable!(),
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{types::Registration, RequestControllerData, RequestProtocolVersionInfo};
+
+    fn datagram(micros: u64, src: &str, dst: &str, payload: &[u8]) -> Datagram {
+        Datagram {
+            time: Duration::from_micros(micros),
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn captured() -> Vec<Datagram> {
+        let version = RequestProtocolVersionInfo::new(1, Crc32::new());
+        let subscribe =
+            RequestControllerData::new(1, Registration::AllControllers, 0, [0; 6], Crc32::new());
+        vec![
+            datagram(1_000_001, "10.0.0.2:5000", "10.0.0.1:26760", &version.bytes),
+            datagram(
+                1_000_250,
+                "10.0.0.1:26760",
+                "10.0.0.2:5000",
+                &subscribe.bytes,
+            ),
+            datagram(
+                2_500_000,
+                "[fd00::2]:6000",
+                "[fd00::1]:26760",
+                &subscribe.bytes,
+            ),
+            datagram(3_000_000, "10.0.0.2:5000", "10.0.0.3:53", b"not dsu"),
+            datagram(3_000_001, "10.0.0.2:5000", "10.0.0.1:26760", &[1]),
+        ]
+    }
+
+    /// A classic pcap capture of `datagrams` over Ethernet.
+    fn pcap(datagrams: &[Datagram], big_endian: bool) -> Vec<u8> {
+        let u32_bytes = |value: u32| {
+            if big_endian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            }
+        };
+        let mut out = Vec::new();
+        out.extend_from_slice(&u32_bytes(PCAP_MAGIC_NANOS));
+        out.extend_from_slice(&u32_bytes(0x0004_0002)[..]);
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&u32_bytes(65535));
+        out.extend_from_slice(&u32_bytes(LINKTYPE_ETHERNET as u32));
+        for datagram in datagrams {
+            let mut frame = vec![0; 12];
+            let ethertype = if datagram.src.is_ipv4() {
+                ETHERTYPE_IPV4
+            } else {
+                ETHERTYPE_IPV6
+            };
+            // One VLAN tag.
+            frame.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            frame.extend_from_slice(&[0, 7]);
+            frame.extend_from_slice(&ethertype.to_be_bytes());
+            frame.extend_from_slice(&ip_packet(datagram).unwrap());
+            out.extend_from_slice(&u32_bytes(datagram.time.as_secs() as u32));
+            out.extend_from_slice(&u32_bytes(datagram.time.subsec_nanos()));
+            out.extend_from_slice(&u32_bytes(frame.len() as u32));
+            out.extend_from_slice(&u32_bytes(frame.len() as u32));
+            out.extend_from_slice(&frame);
+        }
+        out
+    }
+
+    #[test]
+    fn pcapng_round_trip() {
+        let datagrams = captured();
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        for datagram in &datagrams {
+            writer.write_datagram(datagram).unwrap();
+        }
+        let capture = writer.into_inner();
+
+        let read = read_dsu_datagrams(&capture[..]).unwrap();
+        let dsu: Vec<_> = datagrams
+            .iter()
+            .filter(|d| d.dst.port() != 53)
+            .cloned()
+            .collect();
+        assert_eq!(read, dsu);
+        assert!(read[0].message().is_ok());
+        assert!(read[3].message().is_err());
+        assert_eq!(read_datagrams(&capture[..], 53).unwrap(), datagrams[3..4]);
+
+        let flows = flows(read, DEFAULT_PORT);
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].client, "10.0.0.2:5000".parse().unwrap());
+        assert_eq!(flows[0].server, "10.0.0.1:26760".parse().unwrap());
+        assert_eq!(flows[0].datagrams.len(), 3);
+        assert_eq!(flows[1].client, "[fd00::2]:6000".parse().unwrap());
+        assert_eq!(flows[1].datagrams.len(), 1);
+
+        let mixed = datagram(0, "10.0.0.2:5000", "[fd00::1]:26760", &[]);
+        let mut writer = PcapngWriter::new(Vec::new()).unwrap();
+        assert!(writer.write_datagram(&mixed).is_err());
+    }
+
+    #[test]
+    fn classic_pcap() {
+        let datagrams = captured();
+        for big_endian in [false, true] {
+            let read = read_dsu_datagrams(&pcap(&datagrams, big_endian)[..]).unwrap();
+            assert_eq!(read.len(), 4);
+            assert_eq!(read[..3], datagrams[..3]);
+            assert_eq!(read[3], datagrams[4]);
+        }
+
+        let mut truncated = pcap(&datagrams, false);
+        truncated.truncate(truncated.len() - 1);
+        assert!(read_dsu_datagrams(&truncated[..]).is_err());
+        assert!(read_dsu_datagrams(&b"not a capture at all"[..]).is_err());
+        assert!(read_dsu_datagrams(&[][..]).is_err());
+    }
+
+    #[test]
+    fn pcapng_times() {
+        let micros = 1_700_000_000_123_456;
+        assert_eq!(
+            pcapng_time(micros, 1_000_000),
+            Duration::new(1_700_000_000, 123_456_000)
+        );
+        let picos = 1_234_567_890_123;
+        assert_eq!(
+            pcapng_time(picos, 10u64.pow(12)),
+            Duration::new(1, 234_567_890)
+        );
+        assert_eq!(
+            pcapng_time(u64::MAX, 10u64.pow(19)),
+            Duration::new(1, 844_674_407)
+        );
+        assert_eq!(pcapng_time(3, 2), Duration::new(1, 500_000_000));
+    }
+}
@@ -0,0 +1,459 @@
+//! Merging the controllers of several upstream DSU servers into one downstream server.
+
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::Client,
+    crc::Crc32,
+    error::RecvError,
+    merge::Merge,
+    server::{Server, SLOTS, SUBSCRIPTION_TIMEOUT},
+    transport::Transport,
+    types::*,
+    *,
+};
+
+/// How long an automatic route is kept after its controller was last seen connected.
+pub const AUTO_ROUTE_TIMEOUT: Duration = SUBSCRIPTION_TIMEOUT;
+
+/// Where an upstream controller appears downstream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Route {
+    /// Index of the upstream server, in the order they were added.
+    pub upstream: usize,
+    /// Slot on the upstream server.
+    pub slot: u8,
+    /// Slot on the downstream server.
+    pub downstream: u8,
+    /// MAC address reported downstream, or `None` to keep the upstream one.
+    pub mac: Option<[u8; 6]>,
+}
+
+/// Two upstream controllers merged into one downstream slot, see [`crate::merge`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MergeRoute {
+    /// Upstream index and slot of the controller providing buttons, sticks and triggers.
+    pub input: (usize, u8),
+    /// Upstream index and slot of the controller providing the accelerometer and gyro.
+    pub motion: (usize, u8),
+    pub downstream: u8,
+    /// MAC address reported downstream, or `None` to keep the one of the input controller.
+    pub mac: Option<[u8; 6]>,
+}
+
+/// A DSU proxy: a client of every upstream server and a server to downstream clients.
+///
+/// Controllers are forwarded along explicit [`Route`]s. Connected controllers without a route
+/// are given the first free downstream slot, unless automatic routing is turned off. Those
+/// automatic routes are removed, and their downstream slot freed, once their controller
+/// hasn't been seen connected for the auto route timeout. The downstream server rewrites the
+/// sender id and packet number and recomputes the CRC.
+pub struct Proxy<T: Transport = UdpSocket> {
+    server: Server<T>,
+    upstreams: Vec<Client<T>>,
+    routes: Vec<Route>,
+    /// Upstream index and slot of every automatic route, and when its controller was last
+    /// seen connected.
+    auto_routes: Vec<((usize, u8), Instant)>,
+    merges: Vec<(MergeRoute, Merge)>,
+    auto_route: bool,
+    auto_route_timeout: Duration,
+}
+
+impl Proxy {
+    /// Connects to an upstream server and subscribes to all of its controllers. Returns the
+    /// index of the upstream.
+    pub fn add_upstream<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<usize> {
+        let client = Client::connect(addr)?;
+        client.socket().set_nonblocking(true)?;
+        self.add_upstream_client(client)
+    }
+}
+
+impl<T: Transport> Proxy<T> {
+    pub fn new(server: Server<T>) -> Self {
+        Proxy {
+            server,
+            upstreams: Vec::new(),
+            routes: Vec::new(),
+            auto_routes: Vec::new(),
+            merges: Vec::new(),
+            auto_route: true,
+            auto_route_timeout: AUTO_ROUTE_TIMEOUT,
+        }
+    }
+
+    /// Subscribes to all controllers of the client's server and forwards them. The client's
+    /// transport must not block. Returns the index of the upstream.
+    pub fn add_upstream_client(&mut self, mut client: Client<T>) -> io::Result<usize> {
+        allow_refused(client.request_controller_info(&[0, 1, 2, 3]))?;
+        allow_refused(client.subscribe(Registration::AllControllers, 0, [0; 6]))?;
+        self.upstreams.push(client);
+        Ok(self.upstreams.len() - 1)
+    }
+
+    /// Adds a route, replacing any route from the same upstream slot or to the same
+    /// downstream slot.
+    pub fn route(&mut self, route: Route) {
+        self.routes.retain(|r| {
+            (r.upstream, r.slot) != (route.upstream, route.slot) && r.downstream != route.downstream
+        });
+        self.merges
+            .retain(|(m, _)| m.downstream != route.downstream);
+        self.routes.push(route);
+        self.retain_auto_routes(|&source| source != (route.upstream, route.slot));
+    }
+
+    /// Adds a merge, replacing any route from either upstream slot or to the same downstream
+    /// slot.
+    pub fn merge(&mut self, merge: MergeRoute) {
+        self.routes.retain(|r| {
+            let from = (r.upstream, r.slot);
+            from != merge.input && from != merge.motion && r.downstream != merge.downstream
+        });
+        self.merges
+            .retain(|(m, _)| m.downstream != merge.downstream);
+        self.merges.push((merge, Merge::new()));
+        self.retain_auto_routes(|_| true);
+    }
+
+    pub fn auto_route(&mut self, auto_route: bool) {
+        self.auto_route = auto_route;
+    }
+
+    /// Sets how long an automatic route is kept after its controller was last seen
+    /// connected. Defaults to [`AUTO_ROUTE_TIMEOUT`].
+    pub fn set_auto_route_timeout(&mut self, timeout: Duration) {
+        self.auto_route_timeout = timeout;
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    pub fn merges(&self) -> impl Iterator<Item = &MergeRoute> {
+        self.merges.iter().map(|(merge, _)| merge)
+    }
+
+    pub fn server(&self) -> &Server<T> {
+        &self.server
+    }
+
+    pub fn server_mut(&mut self) -> &mut Server<T> {
+        &mut self.server
+    }
+
+    /// Forwards everything received from upstream servers and answers downstream requests,
+    /// without blocking.
+    pub fn poll(&mut self) -> io::Result<()> {
+        self.server.poll()?;
+        self.expire_auto_routes();
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        for upstream in 0..self.upstreams.len() {
+            loop {
+                let message = match self.upstreams[upstream].try_recv(&mut buf) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(RecvError::Parse(_)) => continue,
+                    // The upstream server isn't up (yet); subscriptions keep being renewed.
+                    Err(RecvError::Io(err)) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                        break
+                    }
+                    Err(RecvError::Io(err)) => return Err(err),
+                };
+                match message {
+                    MessageRef::ControllerInfo(info) => {
+                        let header = info.controller_header();
+                        let source = (upstream, header.slot());
+                        if let Some((merge, _)) =
+                            self.merges.iter().find(|(m, _)| m.input == source)
+                        {
+                            let mut header = header.clone();
+                            header.set_model(Model::FullGyro);
+                            remap(&mut header, merge.downstream, merge.mac);
+                            self.server.set_controller(header);
+                            continue;
+                        }
+                        if self.merges.iter().any(|(m, _)| m.motion == source) {
+                            continue;
+                        }
+                        let connected = matches!(header.state(), Ok(State::Connected));
+                        if let Some(route) = self.find_route(upstream, header.slot(), connected) {
+                            let mut header = header.clone();
+                            remap(&mut header, route.downstream, route.mac);
+                            self.server.set_controller(header);
+                        }
+                    }
+                    MessageRef::ControllerData(data) => {
+                        let header = data.controller_header();
+                        let source = (upstream, header.slot());
+                        if let Some(merged) = self.update_merges(source, data) {
+                            if let Some(merged) = merged {
+                                self.server.push(&merged)?;
+                            }
+                            continue;
+                        }
+                        if let Some(route) =
+                            self.find_route(upstream, header.slot(), data.is_connected())
+                        {
+                            let mut data = data.clone();
+                            remap(data.controller_header_mut(), route.downstream, route.mac);
+                            self.server.push(&data)?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls forever.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            self.poll()?;
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Feeds data from `source` to the merges using it. Returns `None` if no merge uses it,
+    /// and the merged frame for the downstream slot, if ready, otherwise.
+    fn update_merges(
+        &mut self,
+        source: (usize, u8),
+        data: &ControllerData,
+    ) -> Option<Option<ControllerData>> {
+        let (merge, state) = self
+            .merges
+            .iter_mut()
+            .find(|(m, _)| m.input == source || m.motion == source)?;
+        let merged = if merge.input == source {
+            state.update_input(data, Crc32::new())
+        } else {
+            state.update_motion(data, Crc32::new())
+        };
+        Some(merged.map(|mut merged| {
+            remap(merged.controller_header_mut(), merge.downstream, merge.mac);
+            merged
+        }))
+    }
+
+    fn find_route(&mut self, upstream: usize, slot: u8, connected: bool) -> Option<Route> {
+        if let Some(route) = self
+            .routes
+            .iter()
+            .find(|r| r.upstream == upstream && r.slot == slot)
+        {
+            if connected {
+                if let Some((_, seen)) = self
+                    .auto_routes
+                    .iter_mut()
+                    .find(|(s, _)| *s == (upstream, slot))
+                {
+                    *seen = Instant::now();
+                }
+            }
+            return Some(*route);
+        }
+        if !self.auto_route || !connected {
+            return None;
+        }
+        let downstream = (0..SLOTS as u8).find(|&d| {
+            self.routes.iter().all(|r| r.downstream != d)
+                && self.merges.iter().all(|(m, _)| m.downstream != d)
+        })?;
+        let route = Route {
+            upstream,
+            slot,
+            downstream,
+            mac: None,
+        };
+        self.routes.push(route);
+        self.auto_routes.push(((upstream, slot), Instant::now()));
+        Some(route)
+    }
+
+    /// Removes automatic routes whose controller hasn't been seen connected for the timeout,
+    /// and disconnects their downstream controllers.
+    fn expire_auto_routes(&mut self) {
+        let timeout = self.auto_route_timeout;
+        let mut expired = Vec::new();
+        self.auto_routes.retain(|&(source, seen)| {
+            let live = seen.elapsed() < timeout;
+            if !live {
+                expired.push(source);
+            }
+            live
+        });
+        for source in expired {
+            if let Some(index) = self
+                .routes
+                .iter()
+                .position(|r| (r.upstream, r.slot) == source)
+            {
+                let route = self.routes.remove(index);
+                self.server.disconnect_controller(route.downstream);
+            }
+        }
+    }
+
+    /// Keeps the automatic routes for which `keep` is true and whose route is still there.
+    fn retain_auto_routes(&mut self, mut keep: impl FnMut(&(usize, u8)) -> bool) {
+        let routes = &self.routes;
+        self.auto_routes.retain(|(source, _)| {
+            keep(source) && routes.iter().any(|r| (r.upstream, r.slot) == *source)
+        });
+    }
+}
+
+fn remap(header: &mut ControllerHeader, downstream: u8, mac: Option<[u8; 6]>) {
+    header.set_slot(downstream);
+    if let Some(mac) = mac {
+        *header.mac_mut() = mac;
+    }
+}
+
+fn allow_refused(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::transport::{MemoryNetwork, MemoryTransport};
+
+    fn data(slot: u8, connected: bool) -> ControllerData {
+        let state = if connected {
+            State::Connected
+        } else {
+            State::Disconnected
+        };
+        ControllerData::new(
+            0,
+            slot,
+            state,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [slot; 6],
+            BatteryStatus::Full,
+            connected,
+            Crc32::new(),
+        )
+    }
+
+    fn upstream(
+        network: &MemoryNetwork,
+        addr: SocketAddr,
+    ) -> (Server<MemoryTransport>, Client<MemoryTransport>) {
+        let server = Server::with_transport(network.bind(addr).unwrap());
+        let client = Client::with_transport(network.bind(([10, 0, 0, 1], 0)).unwrap(), addr);
+        (server, client)
+    }
+
+    #[test]
+    fn forwards_over_memory_network() {
+        let network = MemoryNetwork::new();
+        let downstream_addr: SocketAddr = ([10, 0, 0, 1], 26760).into();
+        let mut proxy = Proxy::new(Server::with_transport(
+            network.bind(downstream_addr).unwrap(),
+        ));
+        let (mut first, client) = upstream(&network, ([10, 0, 0, 2], 26760).into());
+        proxy.add_upstream_client(client).unwrap();
+        let (mut second, client) = upstream(&network, ([10, 0, 0, 3], 26760).into());
+        proxy.add_upstream_client(client).unwrap();
+        proxy.route(Route {
+            upstream: 1,
+            slot: 0,
+            downstream: 3,
+            mac: Some([9; 6]),
+        });
+
+        let mut downstream =
+            Client::with_transport(network.bind(([10, 0, 0, 4], 0)).unwrap(), downstream_addr);
+        downstream
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        first.poll().unwrap();
+        second.poll().unwrap();
+        proxy.poll().unwrap();
+
+        first.push(&data(2, true)).unwrap();
+        second.push(&data(0, true)).unwrap();
+        proxy.poll().unwrap();
+
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        let mut received = Vec::new();
+        while let Some(message) = downstream.try_recv(&mut buf).unwrap() {
+            if let MessageRef::ControllerData(data) = message {
+                let header = data.controller_header();
+                received.push((header.slot(), *header.mac()));
+            }
+        }
+        assert_eq!(received, [(0, [2; 6]), (3, [9; 6])]);
+        assert_eq!(
+            proxy.routes()[1],
+            Route {
+                upstream: 0,
+                slot: 2,
+                downstream: 0,
+                mac: None,
+            }
+        );
+    }
+
+    #[test]
+    fn auto_routes_expire() {
+        let network = MemoryNetwork::new();
+        let mut proxy = Proxy::new(Server::with_transport(
+            network.bind(([10, 0, 0, 1], 26760)).unwrap(),
+        ));
+        proxy.set_auto_route_timeout(Duration::from_millis(50));
+        let (mut upstream, client) = upstream(&network, ([10, 0, 0, 2], 26760).into());
+        proxy.add_upstream_client(client).unwrap();
+        upstream.poll().unwrap();
+        proxy.route(Route {
+            upstream: 0,
+            slot: 3,
+            downstream: 3,
+            mac: None,
+        });
+
+        for slot in [0, 1, 3] {
+            upstream.push(&data(slot, true)).unwrap();
+        }
+        proxy.poll().unwrap();
+        assert_eq!(proxy.routes().len(), 3);
+
+        // Slot 0 keeps sending, slot 1 disconnects, and the explicit route never expires.
+        thread::sleep(Duration::from_millis(30));
+        upstream.push(&data(0, true)).unwrap();
+        upstream.push(&data(1, false)).unwrap();
+        proxy.poll().unwrap();
+        thread::sleep(Duration::from_millis(30));
+        proxy.poll().unwrap();
+        let sources: Vec<_> = proxy
+            .routes()
+            .iter()
+            .map(|r| (r.slot, r.downstream))
+            .collect();
+        assert_eq!(sources, [(3, 3), (0, 0)]);
+
+        // The freed slot goes to the next controller that shows up.
+        upstream.push(&data(2, true)).unwrap();
+        proxy.poll().unwrap();
+        assert_eq!(
+            proxy.routes().last().map(|r| (r.slot, r.downstream)),
+            Some((2, 1))
+        );
+    }
+}
@@ -0,0 +1,416 @@
+//! Recording and replaying controller data.
+//!
+//! A recording starts with the magic `DSUREC` followed by the format version as a
+//! little-endian `u16`. Each entry that follows is a one byte tag, the time since the start of
+//! the recording in microseconds as a little-endian `u64`, and the raw message:
+//!
+//! | tag | message          | size |
+//! |-----|------------------|------|
+//! | 1   | `ControllerInfo` | 32   |
+//! | 2   | `ControllerData` | 100  |
+//!
+//! Messages are stored as received, CRC included. A recording cut off in the middle of its
+//! last entry, as when the recorder is killed, reads as if it ended before that entry.
+
+use std::{
+    convert::TryInto,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    server::{Server, SLOTS},
+    transport::Transport,
+    *,
+};
+
+pub const MAGIC: [u8; 6] = *b"DSUREC";
+pub const VERSION: u16 = 1;
+
+const TAG_INFO: u8 = 1;
+const TAG_DATA: u8 = 2;
+
+#[derive(Clone, Debug)]
+pub enum Entry {
+    Info { time: Duration, info: ControllerInfo },
+    Data { time: Duration, data: ControllerData },
+}
+
+impl Entry {
+    pub fn time(&self) -> Duration {
+        match self {
+            Entry::Info { time, .. } | Entry::Data { time, .. } => *time,
+        }
+    }
+}
+
+/// Writes a recording, timestamping entries with the time since the recorder was created.
+pub struct Recorder<W: Write> {
+    out: W,
+    start: Instant,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        Ok(Recorder {
+            out,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record_info(&mut self, info: &ControllerInfo) -> io::Result<()> {
+        let time = self.start.elapsed();
+        self.write_entry(&Entry::Info {
+            time,
+            info: info.clone(),
+        })
+    }
+
+    pub fn record_data(&mut self, data: &ControllerData) -> io::Result<()> {
+        let time = self.start.elapsed();
+        self.write_entry(&Entry::Data {
+            time,
+            data: data.clone(),
+        })
+    }
+
+    /// Writes an entry with its own timestamp, with a single write.
+    pub fn write_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        let (tag, bytes): (u8, &[u8]) = match entry {
+            Entry::Info { info, .. } => (TAG_INFO, &info.bytes),
+            Entry::Data { data, .. } => (TAG_DATA, &data.bytes),
+        };
+        let micros = entry.time().as_micros() as u64;
+        let mut buf = [0u8; 9 + 100];
+        buf[0] = tag;
+        buf[1..9].copy_from_slice(&micros.to_le_bytes());
+        buf[9..9 + bytes.len()].copy_from_slice(bytes);
+        self.out.write_all(&buf[..9 + bytes.len()])
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Reads the entries of a recording.
+pub struct Reader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header)?;
+        if header[0..6] != MAGIC {
+            return Err(invalid_data("not a DSU recording"));
+        }
+        let version = u16::from_le_bytes(header[6..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid_data("unsupported DSU recording version"));
+        }
+        Ok(Reader { input })
+    }
+
+    /// Reads the next entry, or `None` at the end of the recording, including a truncated
+    /// last entry.
+    pub fn read_entry(&mut self) -> io::Result<Option<Entry>> {
+        let mut head = [0u8; 9];
+        if !self.read_all(&mut head)? {
+            return Ok(None);
+        }
+        let time = Duration::from_micros(u64::from_le_bytes(head[1..9].try_into().unwrap()));
+        let entry = match head[0] {
+            TAG_INFO => {
+                let mut info = ControllerInfo { bytes: [0; 32] };
+                if !self.read_all(&mut info.bytes)? {
+                    return Ok(None);
+                }
+                Entry::Info { time, info }
+            }
+            TAG_DATA => {
+                let mut data = ControllerData { bytes: [0; 100] };
+                if !self.read_all(&mut data.bytes)? {
+                    return Ok(None);
+                }
+                Entry::Data { time, data }
+            }
+            _ => return Err(invalid_data("unknown DSU recording entry")),
+        };
+        Ok(Some(entry))
+    }
+
+    /// Fills `buf`, or returns false if the recording ends first.
+    fn read_all(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.input.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    if filled > 0 {
+                        log::warn!("ignoring a truncated entry at the end of the recording");
+                    }
+                    return Ok(false);
+                }
+                Ok(read) => filled += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Plays back a recording, optionally faster or slower and looped.
+///
+/// [`Player::next_entry`] steps through the recording without waiting, which is useful in
+/// tests; [`Player::play`] sends it through a [`Server`] in real time.
+pub struct Player {
+    entries: Vec<Entry>,
+    speed: f64,
+    looping: bool,
+    index: usize,
+    loop_offset: Duration,
+}
+
+impl Player {
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Player {
+            entries,
+            speed: 1.0,
+            looping: false,
+            index: 0,
+            loop_offset: Duration::ZERO,
+        }
+    }
+
+    pub fn read<R: Read>(input: R) -> io::Result<Self> {
+        let entries = Reader::new(input)?.collect::<io::Result<Vec<_>>>()?;
+        Ok(Player::new(entries))
+    }
+
+    /// Sets the playback speed, where `2.0` plays twice as fast. Panics unless `speed` is
+    /// finite and above zero.
+    pub fn speed(mut self, speed: f64) -> Self {
+        assert!(
+            speed > 0.0 && speed.is_finite(),
+            "playback speed must be finite and above zero, not {}",
+            speed
+        );
+        self.speed = speed;
+        self
+    }
+
+    /// Starts over at the end of the recording. Recordings with no duration aren't looped.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Length of the recording at normal speed.
+    pub fn duration(&self) -> Duration {
+        self.entries.last().map_or(Duration::ZERO, Entry::time)
+    }
+
+    /// The last `ControllerInfo` recorded for each slot.
+    pub fn controllers(&self) -> [Option<&ControllerInfo>; SLOTS] {
+        let mut controllers = [None; SLOTS];
+        for entry in &self.entries {
+            if let Entry::Info { info, .. } = entry {
+                if let Some(slot) = controllers.get_mut(info.controller_header().slot() as usize) {
+                    *slot = Some(info);
+                }
+            }
+        }
+        controllers
+    }
+
+    /// Returns the next entry and when it should be played, relative to the start of playback
+    /// and scaled by the playback speed.
+    pub fn next_entry(&mut self) -> Option<(Duration, &Entry)> {
+        if self.index >= self.entries.len() {
+            let duration = self.duration();
+            if !self.looping || duration == Duration::ZERO {
+                return None;
+            }
+            self.index = 0;
+            self.loop_offset += duration;
+        }
+        let entry = &self.entries[self.index];
+        self.index += 1;
+        let at = (self.loop_offset + entry.time()).div_f64(self.speed);
+        Some((at, entry))
+    }
+
+    pub fn rewind(&mut self) {
+        self.index = 0;
+        self.loop_offset = Duration::ZERO;
+    }
+
+    /// Plays the recording through `server` in real time, answering requests between entries.
+    pub fn play<T: Transport>(&mut self, server: &mut Server<T>) -> io::Result<()> {
+        for info in self.controllers().iter().flatten() {
+            server.set_controller(info.controller_header().clone());
+        }
+        let start = Instant::now();
+        while let Some((at, entry)) = self.next_entry() {
+            let entry = entry.clone();
+            server.poll_until(start + at)?;
+            match entry {
+                Entry::Info { info, .. } => server.set_controller(info.controller_header().clone()),
+                Entry::Data { data, .. } => {
+                    server.push(&data)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crc::Crc32, types::*};
+
+    fn info(slot: u8) -> ControllerInfo {
+        ControllerInfo::new(
+            1,
+            slot,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [slot; 6],
+            BatteryStatus::Full,
+            Crc32::new(),
+        )
+    }
+
+    fn data(slot: u8) -> ControllerData {
+        ControllerData::new(
+            1,
+            slot,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [slot; 6],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        )
+    }
+
+    fn recording() -> Vec<u8> {
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        let entries = [
+            Entry::Info {
+                time: Duration::ZERO,
+                info: info(1),
+            },
+            Entry::Data {
+                time: Duration::from_millis(10),
+                data: data(1),
+            },
+            Entry::Data {
+                time: Duration::from_millis(30),
+                data: data(2),
+            },
+        ];
+        for entry in &entries {
+            recorder.write_entry(entry).unwrap();
+        }
+        recorder.into_inner()
+    }
+
+    fn times(player: &mut Player, count: usize) -> Vec<u64> {
+        (0..count)
+            .map_while(|_| player.next_entry().map(|(at, _)| at.as_millis() as u64))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = recording();
+        assert_eq!(bytes.len(), 8 + (9 + 32) + 2 * (9 + 100));
+        let entries = Reader::new(&bytes[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(entries.len(), 3);
+        match &entries[1] {
+            Entry::Data { time, data: read } => {
+                assert_eq!(*time, Duration::from_millis(10));
+                assert_eq!(read.bytes, data(1).bytes);
+            }
+            entry => panic!("expected data, got {:?}", entry),
+        }
+
+        let player = Player::read(&bytes[..]).unwrap();
+        assert_eq!(player.duration(), Duration::from_millis(30));
+        let controllers = player.controllers();
+        assert_eq!(controllers[1].map(|info| info.bytes), Some(info(1).bytes));
+        assert!(controllers[0].is_none());
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let bytes = recording();
+        for cut in [1, 9, 50, 108] {
+            let player = Player::read(&bytes[..bytes.len() - cut]).unwrap();
+            assert_eq!(player.entries().len(), 2, "cut {} bytes", cut);
+        }
+        assert!(Reader::new(&bytes[..5]).is_err());
+        let mut unknown = bytes.clone();
+        unknown[8] = 9;
+        assert!(Player::read(&unknown[..]).is_err());
+    }
+
+    #[test]
+    fn timing() {
+        let entries = Reader::new(&recording()[..])
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        let mut player = Player::new(entries.clone());
+        assert_eq!(times(&mut player, 5), [0, 10, 30]);
+        player.rewind();
+        assert_eq!(times(&mut player, 1), [0]);
+
+        let mut player = Player::new(entries.clone()).speed(2.0);
+        assert_eq!(times(&mut player, 5), [0, 5, 15]);
+
+        let mut player = Player::new(entries.clone()).speed(0.5).looping(true);
+        assert_eq!(times(&mut player, 7), [0, 20, 60, 60, 80, 120, 120]);
+
+        // A recording without a duration would loop forever without time passing.
+        let mut player = Player::new(entries[..1].to_vec()).looping(true);
+        assert_eq!(times(&mut player, 3), [0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "playback speed")]
+    fn zero_speed() {
+        let _ = Player::new(Vec::new()).speed(0.0);
+    }
+}
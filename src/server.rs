@@ -0,0 +1,933 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    access::AccessList,
+    client::{random_id, DEFAULT_PORT},
+    crc::Crc32,
+    template::DataTemplate,
+    transport::{bind_udp, Address, Transport},
+    types::*,
+    *,
+};
+#[cfg(feature = "metrics")]
+use crate::metrics::{Metrics, SubscriberCounts};
+
+/// How long a subscription lasts without being renewed.
+pub const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const SLOTS: usize = 4;
+
+/// Hosts whose access decision is remembered, so it's logged once while they are active.
+/// Once there are more, ones not heard from in [`SUBSCRIPTION_TIMEOUT`] are forgotten, and
+/// decisions for new hosts are only counted in [`Rejections::denied`] until there is room,
+/// so spoofed sources can't grow memory or flood the log.
+pub const MAX_ACCESS_LOGGED: usize = 1024;
+
+/// A token bucket: `per_second` requests on average, and up to `burst` at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+/// Protections against clients using the server to flood someone else.
+///
+/// UDP source addresses can be spoofed, so a single `RequestControllerData` could make the
+/// server send data to anyone for [`SUBSCRIPTION_TIMEOUT`]. The defaults allow a few
+/// emulators per machine and are meant for servers reachable from a network; servers bound
+/// to loopback can use [`Limits::NONE`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Most clients subscribed at once.
+    pub max_subscribers: Option<usize>,
+    /// Most clients subscribed at once from one host.
+    pub max_subscribers_per_host: Option<usize>,
+    /// Requests handled from all hosts together. Others are dropped unread.
+    pub rate: Option<RateLimit>,
+    /// Requests handled from each host.
+    pub rate_per_host: Option<RateLimit>,
+    /// Only accept subscriptions from addresses that have requested the protocol version or
+    /// controller info in the last [`SUBSCRIPTION_TIMEOUT`], as clients do when they connect.
+    ///
+    /// This only filters out bare subscription requests, such as stray ones from a client that
+    /// was talking to a server that has since restarted. The handshake is a single request
+    /// whose source can be spoofed as easily as the subscription's, so it doesn't protect
+    /// anyone from being flooded. Off by default, since some clients never send it.
+    pub require_handshake: bool,
+}
+
+impl Limits {
+    pub const NONE: Limits = Limits {
+        max_subscribers: None,
+        max_subscribers_per_host: None,
+        rate: None,
+        rate_per_host: None,
+        require_handshake: false,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_subscribers: Some(64),
+            max_subscribers_per_host: Some(8),
+            rate: Some(RateLimit {
+                per_second: 2000,
+                burst: 4000,
+            }),
+            rate_per_host: Some(RateLimit {
+                per_second: 100,
+                burst: 200,
+            }),
+            require_handshake: false,
+        }
+    }
+}
+
+/// Counts of requests the server ignored because of its [`Limits`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rejections {
+    /// Requests from addresses the [`AccessList`] doesn't allow.
+    pub denied: u64,
+    pub rate_limited: u64,
+    pub no_handshake: u64,
+    pub too_many_subscribers: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second as f64).min(limit.burst as f64);
+        self.updated = now;
+    }
+
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Subscriber {
+    all: Option<Instant>,
+    slots: [Option<Instant>; SLOTS],
+    macs: Vec<([u8; 6], Instant)>,
+}
+
+impl Subscriber {
+    fn wants(&self, header: &ControllerHeader, now: Instant) -> bool {
+        let live = |since: &Instant| now.duration_since(*since) < SUBSCRIPTION_TIMEOUT;
+        self.all.as_ref().is_some_and(live)
+            || self
+                .slots
+                .get(header.slot() as usize)
+                .and_then(Option::as_ref)
+                .is_some_and(live)
+            || self
+                .macs
+                .iter()
+                .any(|(mac, since)| mac == header.mac() && live(since))
+    }
+
+    #[cfg(feature = "metrics")]
+    fn count(&self, counts: &mut SubscriberCounts, now: Instant) {
+        let live = |since: &Instant| now.duration_since(*since) < SUBSCRIPTION_TIMEOUT;
+        counts.all_controllers += self.all.as_ref().is_some_and(live) as u64;
+        counts.slot_based += self.slots.iter().flatten().any(live) as u64;
+        counts.mac_based += self.macs.iter().any(|(_, since)| live(since)) as u64;
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        let dead = |since: &Instant| now.duration_since(*since) >= SUBSCRIPTION_TIMEOUT;
+        self.all.as_ref().is_none_or(dead)
+            && self.slots.iter().all(|slot| slot.as_ref().is_none_or(dead))
+            && self.macs.iter().all(|(_, since)| dead(since))
+    }
+}
+
+/// A DSU server serving up to four controller slots.
+///
+/// Call [`Server::poll`] regularly to answer requests and [`Server::push`] to send controller
+/// data to subscribed clients. Requests are subject to the server's [`Limits`].
+pub struct Server<T: Transport = UdpSocket> {
+    transport: T,
+    id: u32,
+    controllers: [Option<ControllerHeader>; SLOTS],
+    packet_numbers: [u32; SLOTS],
+    /// The last packet sent for each slot.
+    templates: [DataTemplate; SLOTS],
+    subscribers: HashMap<T::Addr, Subscriber>,
+    limits: Limits,
+    access: AccessList,
+    /// When each host's access was last logged, so it's logged once while the host is active.
+    access_logged: HashMap<IpAddr, Instant>,
+    rejections: Rejections,
+    /// When each address last requested the protocol version or controller info.
+    handshakes: HashMap<T::Addr, Instant>,
+    bucket: Option<Bucket>,
+    host_buckets: HashMap<<T::Addr as Address>::Host, Bucket>,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+    #[cfg(feature = "metrics")]
+    created: Instant,
+}
+
+impl Server {
+    /// Binds to the default port on loopback, reachable only from this machine.
+    pub fn bind_default() -> io::Result<Self> {
+        Server::bind((Ipv4Addr::LOCALHOST, DEFAULT_PORT))
+    }
+
+    /// Binds to `port` on every interface for IPv6 and IPv4 clients alike, or only IPv4 if
+    /// IPv6 is unavailable.
+    pub fn bind_any(port: u16) -> io::Result<Self> {
+        Server::bind((Ipv6Addr::UNSPECIFIED, port)).or_else(|err| {
+            log::warn!("serving IPv4 only, binding to [::]:{} failed: {}", port, err);
+            Server::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|_| err)
+        })
+    }
+
+    /// Binding to anything but loopback makes the server reachable from the network, where
+    /// its [`Limits`] matter. Binding to `[::]` accepts IPv4 clients too, see [`bind_udp`].
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = bind_udp(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Server::with_transport(socket))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+}
+
+impl<T: Transport> Server<T> {
+    /// Serves over `transport`, which must not block when there is nothing to receive.
+    pub fn with_transport(transport: T) -> Self {
+        Server {
+            transport,
+            id: random_id(),
+            controllers: Default::default(),
+            packet_numbers: [0; SLOTS],
+            templates: Default::default(),
+            subscribers: HashMap::new(),
+            limits: Limits::default(),
+            access: AccessList::default(),
+            access_logged: HashMap::new(),
+            rejections: Rejections::default(),
+            handshakes: HashMap::new(),
+            bucket: None,
+            host_buckets: HashMap::new(),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
+            #[cfg(feature = "metrics")]
+            created: Instant::now(),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Replaces the limits. Existing subscriptions are kept even if they exceed the new
+    /// limits.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.bucket = None;
+        self.host_buckets.clear();
+    }
+
+    pub fn access(&self) -> &AccessList {
+        &self.access
+    }
+
+    /// Replaces the access list. Subscribers that are no longer allowed are dropped.
+    pub fn set_access(&mut self, access: AccessList) {
+        self.subscribers
+            .retain(|addr, _| addr.ip().is_none_or(|ip| access.allows(ip)));
+        self.access = access;
+        self.access_logged.clear();
+    }
+
+    pub fn rejections(&self) -> Rejections {
+        self.rejections
+    }
+
+    /// Returns a snapshot of the server's metrics.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        let now = Instant::now();
+        let mut metrics = self.metrics.clone();
+        metrics.uptime = now.duration_since(self.created);
+        metrics.rejections = self.rejections;
+        for subscriber in self.subscribers.values() {
+            subscriber.count(&mut metrics.subscribers, now);
+        }
+        metrics
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Number of clients with at least one live subscription.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn controller(&self, slot: u8) -> Option<&ControllerHeader> {
+        self.controllers.get(slot as usize)?.as_ref()
+    }
+
+    /// Sets the controller reported for its slot in `ControllerInfo` responses.
+    ///
+    /// Slots outside `0..4` are ignored.
+    pub fn set_controller(&mut self, header: ControllerHeader) {
+        if let Some(controller) = self.controllers.get_mut(header.slot() as usize) {
+            *controller = Some(header);
+        }
+    }
+
+    pub fn disconnect_controller(&mut self, slot: u8) {
+        if let Some(controller) = self.controllers.get_mut(slot as usize) {
+            *controller = None;
+        }
+    }
+
+    /// Answers all pending requests without blocking and drops expired subscriptions.
+    pub fn poll(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; MAX_MESSAGE_SIZE];
+        loop {
+            match self.transport.recv_from(&mut buf) {
+                Ok((len, from)) => self.handle_request(&buf[..len], from)?,
+                Err(err) if client::is_timeout(&err) => break,
+                // Reported on some platforms when a previous send was rejected.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        let now = Instant::now();
+        self.subscribers.retain(|_, subscriber| !subscriber.expired(now));
+        self.handshakes
+            .retain(|_, since| now.duration_since(*since) < SUBSCRIPTION_TIMEOUT);
+        self.access_logged
+            .retain(|_, since| now.duration_since(*since) < SUBSCRIPTION_TIMEOUT);
+        // A full bucket is the same as no bucket.
+        if let Some(limit) = &self.limits.rate_per_host {
+            self.host_buckets.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst as f64
+            });
+        }
+        Ok(())
+    }
+
+    /// Answers requests until `deadline`.
+    pub fn poll_until(&mut self, deadline: Instant) -> io::Result<()> {
+        loop {
+            self.poll()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(1)));
+        }
+    }
+
+    /// Handles one datagram received from `from`. Anything that isn't a valid client request is
+    /// ignored, as are requests over the server's [`Limits`] and all requests from addresses
+    /// its [`AccessList`] doesn't allow.
+    pub fn handle_request(&mut self, buf: &[u8], from: T::Addr) -> io::Result<()> {
+        let now = Instant::now();
+        // Denied hosts mustn't use up the rate limits of allowed ones.
+        if !self.allowed(&from, now) {
+            self.rejections.denied += 1;
+            return Ok(());
+        }
+        if !self.within_rate(&from, now) {
+            self.rejections.rate_limited += 1;
+            return Ok(());
+        }
+        let message = MessageRef::parse(buf, Crc32::new());
+        self.count_received(&message);
+        let message = match message {
+            Ok(message) => message,
+            Err(_) => return Ok(()),
+        };
+        match message {
+            MessageRef::RequestProtocolVersionInfo(_) => {
+                self.handshakes.insert(from.clone(), now);
+                let response = ProtocolVersionInfo::new(self.id, Protocol::Version1001, Crc32::new());
+                self.respond(&response.bytes, &from, MessageType::ProtocolVersionInfo);
+            }
+            MessageRef::RequestControllerInfo(request) => {
+                self.handshakes.insert(from.clone(), now);
+                for &slot in request.slots().unwrap_or(&[]) {
+                    if slot as usize >= SLOTS {
+                        continue;
+                    }
+                    let mut response = ControllerInfo::new(
+                        self.id,
+                        slot,
+                        State::Disconnected,
+                        Model::NotApplicable,
+                        ConnectionType::NotApplicable,
+                        [0; 6],
+                        BatteryStatus::NotApplicable,
+                        Crc32::new(),
+                    );
+                    if let Some(header) = &self.controllers[slot as usize] {
+                        *response.controller_header_mut() = header.clone();
+                        response.update_crc(Crc32::new());
+                    }
+                    self.respond(&response.bytes, &from, MessageType::ControllerInfo);
+                }
+            }
+            MessageRef::RequestControllerData(request) => {
+                if !self.may_subscribe(&from, now) {
+                    return Ok(());
+                }
+                let subscriber = self.subscribers.entry(from).or_default();
+                match request.registration() {
+                    Ok(Registration::AllControllers) => subscriber.all = Some(now),
+                    Ok(Registration::SlotBased) => {
+                        if let Some(slot) = subscriber.slots.get_mut(request.slot() as usize) {
+                            *slot = Some(now);
+                        }
+                    }
+                    Ok(Registration::MacBased) => {
+                        let mac = *request.mac();
+                        match subscriber.macs.iter_mut().find(|(m, _)| *m == mac) {
+                            Some((_, since)) => *since = now,
+                            None => subscriber.macs.push((mac, now)),
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+            MessageRef::ProtocolVersionInfo(_)
+            | MessageRef::ControllerInfo(_)
+            | MessageRef::ControllerData(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Counts a datagram that was read, and whether it parsed.
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn count_received(&mut self, message: &Result<MessageRef, MessageParseError>) {
+        #[cfg(feature = "metrics")]
+        match message {
+            Ok(message) => self.metrics.received.count(message),
+            Err(err) => self.metrics.parse_errors.count(err),
+        }
+    }
+
+    /// Sends a response to `to`. A full socket buffer loses it like any UDP packet, and any
+    /// other error means `to` can't be sent to at all.
+    fn respond(&mut self, bytes: &[u8], to: &T::Addr, message_type: MessageType) {
+        let sent = match self.transport.send_to(bytes, to) {
+            Ok(_) => true,
+            Err(err) => {
+                if !client::is_timeout(&err) {
+                    self.unreachable(to, &err);
+                }
+                false
+            }
+        };
+        self.count_response(message_type, sent);
+    }
+
+    /// Forgets a peer that sending to failed for another reason than a full buffer, such as
+    /// a spoofed port 0 or a removed socket path.
+    fn unreachable(&mut self, addr: &T::Addr, err: &io::Error) {
+        log::debug!("dropping {:?}, sending to it failed: {}", addr, err);
+        self.subscribers.remove(addr);
+        self.handshakes.remove(addr);
+    }
+
+    /// Counts a response, and whether it was dropped.
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn count_response(&mut self, message_type: MessageType, sent: bool) {
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.sent.count_response(message_type, 1);
+            self.metrics.dropped_sends += !sent as u64;
+        }
+    }
+
+    /// Checks `from` against the access list, logging the decision if it hasn't been logged
+    /// for a while and fewer than [`MAX_ACCESS_LOGGED`] hosts are remembered.
+    fn allowed(&mut self, from: &T::Addr, now: Instant) -> bool {
+        let ip = match from.ip() {
+            Some(ip) if !self.access.is_open() => ip,
+            _ => return true,
+        };
+        let allowed = self.access.allows(ip);
+        if self.access_logged.len() >= MAX_ACCESS_LOGGED && !self.access_logged.contains_key(&ip) {
+            self.access_logged
+                .retain(|_, since| now.duration_since(*since) < SUBSCRIPTION_TIMEOUT);
+            if self.access_logged.len() >= MAX_ACCESS_LOGGED {
+                return allowed;
+            }
+        }
+        if let Entry::Vacant(entry) = self.access_logged.entry(ip) {
+            entry.insert(now);
+            if allowed {
+                log::info!("allowing requests from {}", ip);
+            } else {
+                log::warn!("denying requests from {}", ip);
+            }
+        }
+        allowed
+    }
+
+    /// Takes a token from the global bucket and `from`'s host's bucket.
+    fn within_rate(&mut self, from: &T::Addr, now: Instant) -> bool {
+        if let Some(limit) = &self.limits.rate {
+            let bucket = self.bucket.get_or_insert_with(|| Bucket::new(limit, now));
+            if !bucket.take(limit, now) {
+                return false;
+            }
+        }
+        if let Some(limit) = &self.limits.rate_per_host {
+            let bucket = self
+                .host_buckets
+                .entry(from.host())
+                .or_insert_with(|| Bucket::new(limit, now));
+            if !bucket.take(limit, now) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether a subscription request from `from` is accepted, counting it if not.
+    fn may_subscribe(&mut self, from: &T::Addr, now: Instant) -> bool {
+        if self.limits.require_handshake {
+            match self.handshakes.get_mut(from) {
+                // Renewals keep the handshake alive.
+                Some(since) => *since = now,
+                None => {
+                    self.rejections.no_handshake += 1;
+                    return false;
+                }
+            }
+        }
+        if self.subscribers.contains_key(from) {
+            return true;
+        }
+        let full = self
+            .limits
+            .max_subscribers
+            .is_some_and(|max| self.subscribers.len() >= max)
+            || self.limits.max_subscribers_per_host.is_some_and(|max| {
+                let host = from.host();
+                self.subscribers.keys().filter(|addr| addr.host() == host).count() >= max
+            });
+        if full {
+            self.rejections.too_many_subscribers += 1;
+        }
+        !full
+    }
+
+    /// Sends `data` to every client subscribed to its slot or MAC address, and returns how
+    /// many clients it was sent to.
+    ///
+    /// The sender id, packet number and CRC are filled in by the server, and the controller
+    /// header becomes the one reported for the slot. Packets that couldn't be sent aren't
+    /// counted, and subscribers that can't be sent to at all are dropped.
+    pub fn push(&mut self, data: &ControllerData) -> io::Result<usize> {
+        self.push_many(std::slice::from_ref(data))
+    }
+
+    /// Like [`Server::push`] for several packets at once, such as one per slot every tick,
+    /// and returns how many packets were sent. They are all sent with
+    /// [`Transport::send_batch`].
+    pub fn push_many(&mut self, data: &[ControllerData]) -> io::Result<usize> {
+        let mut packets = Vec::with_capacity(data.len());
+        for data in data {
+            let slot = data.controller_header().slot() as usize;
+            if slot >= SLOTS {
+                continue;
+            }
+            self.controllers[slot] = Some(data.controller_header().clone());
+
+            let packet = self.templates[slot].update(data, self.id, self.packet_numbers[slot]);
+            self.packet_numbers[slot] = self.packet_numbers[slot].wrapping_add(1);
+            packets.push(packet.clone());
+        }
+
+        let now = Instant::now();
+        let mut datagrams = Vec::new();
+        #[cfg(feature = "metrics")]
+        let mut slot_sent = [0; SLOTS];
+        for packet in &packets {
+            for (addr, subscriber) in &self.subscribers {
+                if subscriber.wants(packet.controller_header(), now) {
+                    datagrams.push((&packet.bytes[..], addr));
+                    #[cfg(feature = "metrics")]
+                    {
+                        slot_sent[packet.controller_header().slot() as usize] += 1;
+                    }
+                }
+            }
+        }
+        let mut unreachable = Vec::new();
+        let dropped = send_batch(&mut self.transport, &datagrams, &mut unreachable);
+        let attempted = datagrams.len();
+        #[cfg(feature = "metrics")]
+        {
+            for (total, sent) in self.metrics.slot_sent.iter_mut().zip(slot_sent) {
+                *total += sent;
+            }
+            let sent = attempted as u64;
+            self.metrics.sent.count_response(MessageType::ControllerData, sent);
+            self.metrics.dropped_sends += dropped as u64;
+        }
+        for (addr, err) in unreachable {
+            self.unreachable(&addr, &err);
+        }
+        Ok(attempted - dropped)
+    }
+}
+
+/// Sends datagrams, treating any failure like a lost packet, and returns how many were lost.
+/// Peers that failed for another reason than a full socket buffer are added to
+/// `unreachable` once each.
+fn send_batch<T: Transport>(
+    transport: &mut T,
+    mut datagrams: &[(&[u8], &T::Addr)],
+    unreachable: &mut Vec<(T::Addr, io::Error)>,
+) -> usize {
+    let mut dropped = 0;
+    while !datagrams.is_empty() {
+        match transport.send_batch(datagrams) {
+            // Nothing sent without an error would loop forever, so that datagram is lost.
+            Ok(0) => {
+                dropped += 1;
+                datagrams = &datagrams[1..];
+            }
+            Ok(sent) => datagrams = &datagrams[sent.min(datagrams.len())..],
+            Err(err) => {
+                let to = datagrams[0].1;
+                if !client::is_timeout(&err) && unreachable.iter().all(|(addr, _)| addr != to) {
+                    unreachable.push((to.clone(), err));
+                }
+                dropped += 1;
+                datagrams = &datagrams[1..];
+            }
+        }
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::Client,
+        transport::{MemoryNetwork, MemoryTransport},
+    };
+
+    fn server(limits: Limits) -> (MemoryNetwork, Server<MemoryTransport>) {
+        let network = MemoryNetwork::new();
+        let mut server = Server::with_transport(network.bind(([127, 0, 0, 1], 26760)).unwrap());
+        server.set_limits(limits);
+        (network, server)
+    }
+
+    fn subscribe<T: Transport<Addr = SocketAddr>>(server: &mut Server<T>, from: SocketAddr) {
+        let request =
+            RequestControllerData::new(1, Registration::AllControllers, 0, [0; 6], Crc32::new());
+        server.handle_request(&request.bytes, from).unwrap();
+    }
+
+    fn handshake(server: &mut Server<MemoryTransport>, from: SocketAddr) {
+        let request = RequestProtocolVersionInfo::new(1, Crc32::new());
+        server.handle_request(&request.bytes, from).unwrap();
+    }
+
+    #[test]
+    fn handshake_required() {
+        let (_network, mut server) = server(Limits {
+            require_handshake: true,
+            ..Limits::default()
+        });
+        let client = ([127, 0, 0, 1], 5000).into();
+        subscribe(&mut server, client);
+        assert_eq!(server.subscriber_count(), 0);
+        assert_eq!(server.rejections().no_handshake, 1);
+
+        handshake(&mut server, client);
+        subscribe(&mut server, client);
+        assert_eq!(server.subscriber_count(), 1);
+
+        server.set_limits(Limits::NONE);
+        subscribe(&mut server, ([127, 0, 0, 1], 5001).into());
+        assert_eq!(server.subscriber_count(), 2);
+    }
+
+    #[test]
+    fn client_handshakes_when_unanswered() {
+        let (network, mut server) = server(Limits {
+            require_handshake: true,
+            ..Limits::NONE
+        });
+        let transport = network.bind(([127, 0, 0, 1], 0)).unwrap();
+        let mut client = Client::with_transport(transport, ([127, 0, 0, 1], 26760).into());
+        client
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        server.poll().unwrap();
+        assert_eq!(server.subscriber_count(), 0);
+
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        assert!(client.try_recv(&mut buf).unwrap().is_none());
+        server.poll().unwrap();
+        assert_eq!(server.subscriber_count(), 1);
+    }
+
+    #[test]
+    fn access_list() {
+        let (_network, mut server) = server(Limits::NONE);
+        server.set_access(AccessList {
+            allow: vec!["127.0.0.0/8".parse().unwrap()],
+            deny: vec!["127.0.0.2".parse().unwrap()],
+        });
+        subscribe(&mut server, ([127, 0, 0, 1], 1).into());
+        subscribe(&mut server, ([127, 0, 0, 2], 1).into());
+        subscribe(&mut server, ([10, 0, 0, 1], 1).into());
+        subscribe(&mut server, ([0xfe80, 0, 0, 0, 0, 0, 0, 1], 1).into());
+        assert_eq!(server.subscriber_count(), 1);
+        assert_eq!(server.rejections().denied, 3);
+
+        server.set_access(AccessList {
+            allow: Vec::new(),
+            deny: vec!["127.0.0.1".parse().unwrap()],
+        });
+        assert_eq!(server.subscriber_count(), 0);
+        subscribe(&mut server, ([10, 0, 0, 1], 1).into());
+        assert_eq!(server.subscriber_count(), 1);
+
+        // Denied hosts don't use up the global rate limit.
+        server.set_limits(Limits {
+            rate: Some(RateLimit {
+                per_second: 1,
+                burst: 1,
+            }),
+            ..Limits::NONE
+        });
+        for _ in 0..10 {
+            subscribe(&mut server, ([127, 0, 0, 1], 1).into());
+        }
+        subscribe(&mut server, ([10, 0, 0, 2], 1).into());
+        assert_eq!(server.subscriber_count(), 2);
+        assert_eq!(server.rejections().rate_limited, 0);
+    }
+
+    #[test]
+    fn access_log_is_capped() {
+        let (_network, mut server) = server(Limits::NONE);
+        server.set_access(AccessList {
+            allow: Vec::new(),
+            deny: vec!["10.0.0.0/8".parse().unwrap()],
+        });
+        for host in 0..MAX_ACCESS_LOGGED as u32 * 2 {
+            let ip = std::net::Ipv4Addr::from(0x0A00_0000 | host);
+            subscribe(&mut server, (ip, 1).into());
+        }
+        assert_eq!(server.access_logged.len(), MAX_ACCESS_LOGGED);
+        assert_eq!(server.rejections().denied, MAX_ACCESS_LOGGED as u64 * 2);
+        // Allowed hosts are still served once the log is full.
+        subscribe(&mut server, ([192, 168, 0, 1], 1).into());
+        assert_eq!(server.subscriber_count(), 1);
+    }
+
+    #[test]
+    fn subscriber_caps() {
+        let (_network, mut server) = server(Limits {
+            max_subscribers: Some(3),
+            max_subscribers_per_host: Some(2),
+            ..Limits::NONE
+        });
+        for (ip, port) in [(1, 1), (1, 2), (1, 3), (2, 1), (3, 1)] {
+            subscribe(&mut server, ([127, 0, 0, ip], port).into());
+        }
+        assert_eq!(server.subscriber_count(), 3);
+        assert_eq!(server.rejections().too_many_subscribers, 2);
+
+        // Renewals are still accepted.
+        subscribe(&mut server, ([127, 0, 0, 1], 1).into());
+        assert_eq!(server.rejections().too_many_subscribers, 2);
+    }
+
+    #[test]
+    fn rate_limits() {
+        let (network, mut server) = server(Limits {
+            rate_per_host: Some(RateLimit {
+                per_second: 1,
+                burst: 3,
+            }),
+            ..Limits::NONE
+        });
+        let mut client = network.bind(([127, 0, 0, 1], 0)).unwrap();
+        let mut other = network.bind(([127, 0, 0, 2], 0)).unwrap();
+        for _ in 0..5 {
+            handshake(&mut server, client.local_addr());
+        }
+        handshake(&mut server, other.local_addr());
+        assert_eq!(server.rejections().rate_limited, 2);
+
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let mut count = |transport: &mut MemoryTransport| {
+            std::iter::from_fn(|| transport.recv_from(&mut buf).ok()).count()
+        };
+        assert_eq!(count(&mut client), 3);
+        assert_eq!(count(&mut other), 1);
+
+        server.set_limits(Limits {
+            rate: Some(RateLimit {
+                per_second: 1,
+                burst: 2,
+            }),
+            ..Limits::NONE
+        });
+        handshake(&mut server, client.local_addr());
+        handshake(&mut server, other.local_addr());
+        handshake(&mut server, other.local_addr());
+        assert_eq!(server.rejections().rate_limited, 3);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics() {
+        let (network, mut server) = server(Limits {
+            require_handshake: true,
+            ..Limits::default()
+        });
+        let all = network.bind(([127, 0, 0, 1], 0)).unwrap();
+        let slot = network.bind(([127, 0, 0, 2], 0)).unwrap();
+        subscribe(&mut server, all.local_addr());
+        handshake(&mut server, all.local_addr());
+        subscribe(&mut server, all.local_addr());
+        handshake(&mut server, slot.local_addr());
+        let request = RequestControllerData::new(1, Registration::SlotBased, 1, [0; 6], Crc32::new());
+        server.handle_request(&request.bytes, slot.local_addr()).unwrap();
+        let mut corrupt = request.clone();
+        corrupt.bytes[27] ^= 1;
+        server.handle_request(&corrupt.bytes, slot.local_addr()).unwrap();
+        server.handle_request(&request.bytes[..20], slot.local_addr()).unwrap();
+        let mut info = RequestControllerInfo::new(1, &[0], Crc32::new()).unwrap();
+        info.bytes[20] = 9;
+        info.update_crc(Crc32::new());
+        server.handle_request(&info.bytes, slot.local_addr()).unwrap();
+
+        let data = |slot| {
+            ControllerData::new(
+                0,
+                slot,
+                State::Connected,
+                Model::FullGyro,
+                ConnectionType::Usb,
+                [slot; 6],
+                BatteryStatus::Full,
+                true,
+                Crc32::new(),
+            )
+        };
+        assert_eq!(server.push_many(&[data(0), data(1), data(2)]).unwrap(), 4);
+
+        let metrics = server.metrics();
+        assert_eq!(metrics.received.request_protocol_version_info, 2);
+        assert_eq!(metrics.received.request_controller_data, 3);
+        assert_eq!(metrics.sent.protocol_version_info, 2);
+        assert_eq!(metrics.sent.controller_data, 4);
+        assert_eq!(metrics.parse_errors.invalid_crc32, 1);
+        assert_eq!(metrics.parse_errors.slice_too_small, 1);
+        assert_eq!(metrics.parse_errors.invalid_slots_length, 1);
+        assert_eq!(metrics.received.request_controller_info, 0);
+        assert_eq!(metrics.slot_sent, [1, 2, 1, 0]);
+        assert_eq!(metrics.dropped_sends, 0);
+        assert_eq!(metrics.rejections.no_handshake, 1);
+        assert_eq!(
+            (metrics.subscribers.all_controllers, metrics.subscribers.slot_based),
+            (1, 1)
+        );
+
+        let mut text = String::new();
+        metrics.write_prometheus(&mut text).unwrap();
+        assert!(text.contains("dsu_sent_total{message=\"ControllerData\"} 4\n"));
+        assert!(text.contains("dsu_subscribers{registration=\"SlotBased\"} 1\n"));
+    }
+
+    #[test]
+    fn unsendable_peers_are_dropped() {
+        let mut server = Server::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        peer.set_nonblocking(true).unwrap();
+        let spoofed: SocketAddr = (Ipv4Addr::LOCALHOST, 0).into();
+
+        // Sending to port 0 fails with `InvalidInput`, which mustn't stop the server.
+        let request = RequestProtocolVersionInfo::new(1, Crc32::new());
+        server.handle_request(&request.bytes, spoofed).unwrap();
+        subscribe(&mut server, spoofed);
+        subscribe(&mut server, peer.local_addr().unwrap());
+        assert_eq!(server.subscriber_count(), 2);
+
+        let data = ControllerData::new(
+            0,
+            0,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [0; 6],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        );
+        assert_eq!(server.push(&data).unwrap(), 1);
+        assert_eq!(server.subscriber_count(), 1);
+        assert_eq!(server.push(&data).unwrap(), 1);
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(peer.recv(&mut buf).unwrap(), 100);
+        assert_eq!(peer.recv(&mut buf).unwrap(), 100);
+        #[cfg(feature = "metrics")]
+        {
+            let metrics = server.metrics();
+            assert_eq!(metrics.dropped_sends, 2);
+            assert_eq!(metrics.sent.controller_data, 3);
+        }
+    }
+}
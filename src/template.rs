@@ -0,0 +1,155 @@
+//! Packets kept encoded between sends, so only the bytes that changed are rehashed.
+
+use core::hash::Hasher;
+
+use crate::{
+    crc::{self, Crc32, Zeros},
+    ControllerData,
+};
+
+/// Where the packet number starts. Everything before it only changes with the controller.
+const PREFIX: usize = 32;
+/// Where the motion data starts. Between the packet number and it are the buttons, sticks
+/// and touches.
+const MOTION: usize = 68;
+
+/// Feeds the buttons, sticks and touches, when they didn't change.
+static INPUT_ZEROS: Zeros = Zeros::new(MOTION - PREFIX - 4);
+
+/// The last `ControllerData` sent for a slot.
+///
+/// From one tick to the next usually only the packet number and motion data change.
+/// [`DataTemplate::update`] then rewrites those and patches the CRC for the changed bits, in
+/// about half the time a full rehash takes. When the buttons, sticks or touches changed it
+/// hashes everything after the header, and only when the controller changed everything.
+/// [`Server`](crate::server::Server) keeps one template per slot.
+#[derive(Clone, Debug)]
+pub struct DataTemplate {
+    packet: ControllerData,
+    /// The CRC register after the first [`PREFIX`] bytes of `packet`, with the CRC field
+    /// zeroed.
+    prefix: Crc32,
+}
+
+impl DataTemplate {
+    /// Starts out all zeros.
+    pub fn new() -> Self {
+        let mut packet = ControllerData { bytes: [0; 100] };
+        let prefix = prefix_crc(&packet);
+        rehash(&mut packet, &prefix);
+        DataTemplate { packet, prefix }
+    }
+
+    pub fn packet(&self) -> &ControllerData {
+        &self.packet
+    }
+
+    /// Makes the packet `data` with `sender_id` and `packet_number` filled in and a valid
+    /// CRC, and returns it. `data`'s own CRC is ignored.
+    pub fn update(
+        &mut self,
+        data: &ControllerData,
+        sender_id: u32,
+        packet_number: u32,
+    ) -> &ControllerData {
+        let packet = &mut self.packet;
+        // The CRC field, bytes 8..12, isn't hashed.
+        let same_prefix = packet.bytes[..8] == data.bytes[..8]
+            && packet.bytes[16..PREFIX] == data.bytes[16..PREFIX]
+            && packet.header().sender_id() == sender_id;
+        if !same_prefix {
+            packet.bytes[..PREFIX].copy_from_slice(&data.bytes[..PREFIX]);
+            packet.header_mut().set_sender_id(sender_id);
+            self.prefix = prefix_crc(packet);
+        }
+        if !same_prefix || packet.bytes[PREFIX + 4..MOTION] != data.bytes[PREFIX + 4..MOTION] {
+            packet.bytes[PREFIX..].copy_from_slice(&data.bytes[PREFIX..]);
+            packet.set_packet_number(packet_number);
+            rehash(packet, &self.prefix);
+            return packet;
+        }
+
+        let mut number = packet_number.to_le_bytes();
+        for (changed, old) in number.iter_mut().zip(&packet.bytes[PREFIX..PREFIX + 4]) {
+            *changed ^= old;
+        }
+        let mut motion = [0u8; 100 - MOTION];
+        let motions = packet.bytes[MOTION..].iter().zip(&data.bytes[MOTION..]);
+        for (changed, (old, new)) in motion.iter_mut().zip(motions) {
+            *changed = old ^ new;
+        }
+        let delta = crc::update(INPUT_ZEROS.feed(crc::update(0, &number)), &motion);
+        packet.set_packet_number(packet_number);
+        packet.bytes[MOTION..].copy_from_slice(&data.bytes[MOTION..]);
+        let crc = packet.header().crc32() ^ delta;
+        packet.header_mut().set_crc32(crc);
+        packet
+    }
+}
+
+impl Default for DataTemplate {
+    fn default() -> Self {
+        DataTemplate::new()
+    }
+}
+
+fn prefix_crc(packet: &ControllerData) -> Crc32 {
+    let mut crc = Crc32::new();
+    crc.write(&packet.bytes[..8]);
+    crc.write(&[0; 4]);
+    crc.write(&packet.bytes[12..PREFIX]);
+    crc
+}
+
+/// Sets the CRC of `packet` from the register after its first [`PREFIX`] bytes.
+fn rehash(packet: &mut ControllerData, prefix: &Crc32) {
+    let mut crc = prefix.clone();
+    crc.write(&packet.bytes[PREFIX..]);
+    packet.header_mut().set_crc32(crc.finish() as u32);
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    /// Bytes changed in a tick, and the sender id and packet number it's sent with.
+    type Tick = (Vec<(usize, u8)>, u32, u32);
+
+    fn check(ticks: &[Tick], first: ControllerData) -> Result<(), TestCaseError> {
+        let mut template = DataTemplate::new();
+        let mut data = first;
+        for (changes, sender_id, number) in ticks {
+            for &(offset, byte) in changes {
+                data.bytes[offset] = byte;
+            }
+            let mut expected = data.clone();
+            expected.header_mut().set_sender_id(*sender_id);
+            expected.set_packet_number(*number);
+            expected.update_crc(Crc32::new());
+
+            let packet = template.update(&data, *sender_id, *number);
+            prop_assert_eq!(&packet.bytes[..], &expected.bytes[..]);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn matches_full_encoding(
+            first in any::<ControllerData>(),
+            ticks in vec((vec((0usize..100, any::<u8>()), 0..12), 0u32..3, any::<u32>()), 1..20),
+        ) {
+            check(&ticks, first)?;
+        }
+
+        #[test]
+        fn matches_full_encoding_with_only_motion_changes(
+            first in any::<ControllerData>(),
+            ticks in vec((vec((MOTION..100, any::<u8>()), 0..12), Just(1u32), any::<u32>()), 1..20),
+        ) {
+            check(&ticks, first)?;
+        }
+    }
+}
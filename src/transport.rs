@@ -0,0 +1,631 @@
+//! Datagram transports for [`Server`](crate::server::Server) and
+//! [`Client`](crate::client::Client).
+//!
+//! Besides UDP, there is an in-memory [`MemoryNetwork`] for deterministic tests and, on Unix,
+//! datagram sockets addressed by path.
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+};
+
+use crate::{crc::Crc32, error::MessageParseError, MessageRef, MAX_MESSAGE_SIZE};
+
+/// The address of a transport's peer.
+pub trait Address: Clone + Eq + Hash + core::fmt::Debug {
+    /// The machine an address belongs to, which per-host limits are applied to.
+    type Host: Clone + Eq + Hash + core::fmt::Debug;
+
+    fn host(&self) -> Self::Host;
+
+    /// The IP address, which access lists are applied to.
+    fn ip(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+/// IPv4-mapped IPv6 addresses belong to the same host as the IPv4 address.
+impl Address for SocketAddr {
+    type Host = IpAddr;
+
+    fn host(&self) -> IpAddr {
+        self.ip().to_canonical()
+    }
+
+    fn ip(&self) -> Option<IpAddr> {
+        Some(SocketAddr::ip(self).to_canonical())
+    }
+}
+
+/// Every path is its own host.
+impl Address for std::path::PathBuf {
+    type Host = std::path::PathBuf;
+
+    fn host(&self) -> std::path::PathBuf {
+        self.clone()
+    }
+}
+
+/// Sends and receives datagrams.
+///
+/// A server's transport must not block in `recv_from` but return
+/// [`io::ErrorKind::WouldBlock`] when there is nothing to receive. A client's transport may
+/// block, preferably with a timeout so subscriptions can be renewed.
+pub trait Transport {
+    type Addr: Address;
+
+    fn send_to(&mut self, buf: &[u8], to: &Self::Addr) -> io::Result<usize>;
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, Self::Addr)>;
+
+    /// Sends datagrams in order and returns how many were sent, which may be fewer than all.
+    /// Fails only if the first one couldn't be sent, like `sendmmsg`.
+    ///
+    /// Sends them one at a time unless the transport can do better.
+    fn send_batch(&mut self, datagrams: &[(&[u8], &Self::Addr)]) -> io::Result<usize> {
+        for (sent, (buf, to)) in datagrams.iter().enumerate() {
+            if let Err(err) = self.send_to(buf, to) {
+                return if sent == 0 { Err(err) } else { Ok(sent) };
+            }
+        }
+        Ok(datagrams.len())
+    }
+
+    /// Replaces the contents of `batch` with as many waiting datagrams as fit and returns how
+    /// many. Waits like `recv_from` for the first one.
+    ///
+    /// Receives only one unless the transport can do better.
+    fn recv_batch(&mut self, batch: &mut RecvBatch<Self::Addr>) -> io::Result<usize> {
+        batch.clear();
+        let (len, from) = self.recv_from(&mut batch.bufs[0])?;
+        batch.received.push((len, from));
+        Ok(1)
+    }
+}
+
+/// With the `linux-batch` feature on Linux, batches use `sendmmsg` and `recvmmsg`.
+impl Transport for UdpSocket {
+    type Addr = SocketAddr;
+
+    fn send_to(&mut self, buf: &[u8], to: &SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, to)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    #[cfg(all(feature = "linux-batch", target_os = "linux"))]
+    fn send_batch(&mut self, datagrams: &[(&[u8], &SocketAddr)]) -> io::Result<usize> {
+        dsu_mmsg::send(self, datagrams)
+    }
+
+    #[cfg(all(feature = "linux-batch", target_os = "linux"))]
+    fn recv_batch(&mut self, batch: &mut RecvBatch<SocketAddr>) -> io::Result<usize> {
+        batch.clear();
+        dsu_mmsg::recv(self, &mut batch.bufs, &mut batch.received)
+    }
+}
+
+/// Buffers for receiving several datagrams with one call to [`Transport::recv_batch`].
+#[derive(Clone, Debug)]
+pub struct RecvBatch<A> {
+    bufs: Vec<[u8; MAX_MESSAGE_SIZE]>,
+    /// The length and sender of the datagram in each buffer.
+    received: Vec<(usize, A)>,
+}
+
+impl<A> RecvBatch<A> {
+    /// Room for `capacity` datagrams, at least one.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        RecvBatch {
+            bufs: vec![[0; MAX_MESSAGE_SIZE]; capacity],
+            received: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bufs.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.received.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.received.clear();
+    }
+
+    /// The datagrams received and who sent them.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &A)> {
+        self.bufs
+            .iter()
+            .zip(&self.received)
+            .map(|(buf, (len, from))| (&buf[..*len], from))
+    }
+
+    /// Parses each datagram received.
+    pub fn messages(&self) -> impl Iterator<Item = (Result<MessageRef<'_>, MessageParseError>, &A)> {
+        self.iter()
+            .map(|(bytes, from)| (MessageRef::parse(bytes, Crc32::new()), from))
+    }
+
+    /// Keeps only the datagrams from senders that `keep` returns true for.
+    pub fn retain(&mut self, mut keep: impl FnMut(&A) -> bool) {
+        let mut kept = 0;
+        for i in 0..self.received.len() {
+            if keep(&self.received[i].1) {
+                self.bufs.swap(kept, i);
+                self.received.swap(kept, i);
+                kept += 1;
+            }
+        }
+        self.received.truncate(kept);
+    }
+}
+
+/// Binds a UDP socket like [`UdpSocket::bind`], except that binding to `[::]` accepts IPv4
+/// as well as IPv6 wherever the platform supports it, whatever the platform's default.
+///
+/// IPv4 peers of a dual-stack socket have IPv4-mapped IPv6 addresses like
+/// `[::ffff:127.0.0.1]:1234`.
+pub fn bind_udp<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match bind_one(addr) {
+            Ok(socket) => return Ok(socket),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "no address to bind to")
+    }))
+}
+
+fn bind_one(addr: SocketAddr) -> io::Result<UdpSocket> {
+    if addr.ip() != Ipv6Addr::UNSPECIFIED {
+        return UdpSocket::bind(addr);
+    }
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV6,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    if let Err(err) = socket.set_only_v6(false) {
+        log::warn!("{} only accepts IPv6: {}", addr, err);
+    }
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Datagrams from unnamed sockets are skipped since they can't be answered.
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixDatagram {
+    type Addr = std::path::PathBuf;
+
+    fn send_to(&mut self, buf: &[u8], to: &Self::Addr) -> io::Result<usize> {
+        std::os::unix::net::UnixDatagram::send_to(self, buf, to)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, Self::Addr)> {
+        loop {
+            let (len, from) = std::os::unix::net::UnixDatagram::recv_from(self, buf)?;
+            if let Some(path) = from.as_pathname() {
+                return Ok((len, path.to_path_buf()));
+            }
+        }
+    }
+}
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// An in-memory network of [`MemoryTransport`]s, addressed like UDP sockets.
+///
+/// Datagrams are delivered immediately and in order, and datagrams to addresses nobody is
+/// bound to are lost.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryNetwork {
+    endpoints: Arc<Mutex<HashMap<SocketAddr, Sender<Datagram>>>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        MemoryNetwork::default()
+    }
+
+    /// Binds a transport to `addr`. Port 0 picks a free port.
+    pub fn bind<A: Into<SocketAddr>>(&self, addr: A) -> io::Result<MemoryTransport> {
+        let mut addr = addr.into();
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if addr.port() == 0 {
+            let port = (49152..=u16::MAX)
+                .find(|&port| !endpoints.contains_key(&SocketAddr::new(addr.ip(), port)))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::AddrInUse, "no free port"))?;
+            addr.set_port(port);
+        }
+        if endpoints.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "address in use"));
+        }
+        let (sender, receiver) = mpsc::channel();
+        endpoints.insert(addr, sender);
+        Ok(MemoryTransport {
+            addr,
+            receiver,
+            network: self.clone(),
+        })
+    }
+}
+
+/// A transport bound to a [`MemoryNetwork`]. Never blocks.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    receiver: Receiver<Datagram>,
+    network: MemoryNetwork,
+}
+
+impl MemoryTransport {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Addr = SocketAddr;
+
+    fn send_to(&mut self, buf: &[u8], to: &SocketAddr) -> io::Result<usize> {
+        let endpoints = self.network.endpoints.lock().unwrap();
+        if let Some(sender) = endpoints.get(to) {
+            let _ = sender.send((buf.to_vec(), self.addr));
+        }
+        Ok(buf.len())
+    }
+
+    /// Datagrams longer than `buf` are truncated, like UDP.
+    fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.receiver.try_recv() {
+            Ok((bytes, from)) => {
+                let len = bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+                Ok((len, from))
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        if let Ok(mut endpoints) = self.network.endpoints.lock() {
+            endpoints.remove(&self.addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{
+        client::Client,
+        crc::Crc32,
+        error::RecvError,
+        impair::{ImpairedTransport, Impairment},
+        server::Server,
+        types::*,
+        *,
+    };
+
+    const SERVER: ([u8; 4], u16) = ([127, 0, 0, 1], 26760);
+
+    fn setup() -> (
+        MemoryNetwork,
+        Server<MemoryTransport>,
+        Client<MemoryTransport>,
+    ) {
+        let network = MemoryNetwork::new();
+        let server = Server::with_transport(network.bind(SERVER).unwrap());
+        let client =
+            Client::with_transport(network.bind(([127, 0, 0, 1], 0)).unwrap(), SERVER.into());
+        (network, server, client)
+    }
+
+    fn controller_data(slot: u8) -> ControllerData {
+        ControllerData::new(
+            0,
+            slot,
+            State::Connected,
+            Model::FullGyro,
+            ConnectionType::Usb,
+            [0, 0, 0, 0, 0, slot],
+            BatteryStatus::Full,
+            true,
+            Crc32::new(),
+        )
+    }
+
+    #[test]
+    fn protocol_version() {
+        let (_network, mut server, mut client) = setup();
+        client.request_protocol_version().unwrap();
+        server.poll().unwrap();
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        match client.try_recv(&mut buf).unwrap() {
+            Some(MessageRef::ProtocolVersionInfo(info)) => {
+                assert!(matches!(info.protocol(), Ok(Protocol::Version1001)));
+                assert_eq!(info.header().sender_id(), server.id());
+            }
+            _ => panic!("unexpected message"),
+        }
+        assert!(client.try_recv(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn controller_info() {
+        let (_network, mut server, mut client) = setup();
+        server.set_controller(controller_data(1).controller_header().clone());
+        client.request_controller_info(&[1, 2]).unwrap();
+        server.poll().unwrap();
+
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let mut states = Vec::new();
+        while let Some(message) = client.try_recv(&mut buf).unwrap() {
+            match message {
+                MessageRef::ControllerInfo(info) => {
+                    let header = info.controller_header();
+                    states.push((header.slot(), header.state().unwrap()));
+                }
+                _ => panic!("unexpected message"),
+            }
+        }
+        assert_eq!(states, [(1, State::Connected), (2, State::Disconnected)]);
+    }
+
+    #[test]
+    fn subscriptions() {
+        let (network, mut server, mut client) = setup();
+        let mut other =
+            Client::with_transport(network.bind(([127, 0, 0, 1], 0)).unwrap(), SERVER.into());
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        for client in [&mut client, &mut other] {
+            client.request_protocol_version().unwrap();
+            server.poll().unwrap();
+            assert!(client.try_recv(&mut buf).unwrap().is_some());
+        }
+        client
+            .subscribe(Registration::SlotBased, 0, [0; 6])
+            .unwrap();
+        other
+            .subscribe(Registration::MacBased, 0, [0, 0, 0, 0, 0, 1])
+            .unwrap();
+        server.poll().unwrap();
+        assert_eq!(server.subscriber_count(), 2);
+
+        assert_eq!(server.push(&controller_data(0)).unwrap(), 1);
+        assert_eq!(server.push(&controller_data(0)).unwrap(), 1);
+        assert_eq!(server.push(&controller_data(1)).unwrap(), 1);
+        assert_eq!(server.push(&controller_data(2)).unwrap(), 0);
+
+        for expected in 0..2 {
+            match client.try_recv(&mut buf).unwrap() {
+                Some(MessageRef::ControllerData(data)) => {
+                    assert_eq!(data.controller_header().slot(), 0);
+                    assert_eq!(data.packet_number(), expected);
+                    assert_eq!(data.header().sender_id(), server.id());
+                }
+                _ => panic!("unexpected message"),
+            }
+        }
+        assert!(client.try_recv(&mut buf).unwrap().is_none());
+        match other.try_recv(&mut buf).unwrap() {
+            Some(MessageRef::ControllerData(data)) => {
+                assert_eq!(data.controller_header().slot(), 1)
+            }
+            _ => panic!("unexpected message"),
+        }
+    }
+
+    #[test]
+    fn client_ignores_strangers() {
+        let (network, _server, mut client) = setup();
+        let mut stranger = network.bind(([127, 0, 0, 2], 26760)).unwrap();
+        let info = ProtocolVersionInfo::new(1, Protocol::Version1001, Crc32::new());
+        stranger
+            .send_to(&info.bytes, &client.transport().local_addr())
+            .unwrap();
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        assert!(client.try_recv(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn bind_conflicts() {
+        let network = MemoryNetwork::new();
+        let first = network.bind(SERVER).unwrap();
+        assert_eq!(
+            network.bind(SERVER).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+        drop(first);
+        network.bind(SERVER).unwrap();
+    }
+
+    #[test]
+    fn impaired() {
+        let network = MemoryNetwork::new();
+        let mut server = Server::with_transport(network.bind(SERVER).unwrap());
+        let lossy = Impairment {
+            drop: 1.0,
+            ..Impairment::default()
+        };
+        let transport =
+            ImpairedTransport::new(network.bind(([127, 0, 0, 1], 0)).unwrap(), lossy, 1);
+        let mut client = Client::with_transport(transport, SERVER.into());
+        client.request_protocol_version().unwrap();
+        server.poll().unwrap();
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        assert!(client.try_recv(&mut buf).unwrap().is_none());
+        assert_eq!(client.transport().impairer().stats().dropped, 1);
+
+        // Corrupting requests makes the server ignore them; corrupting responses makes them
+        // fail to parse.
+        let corrupt = Impairment {
+            corrupt: 1.0,
+            ..Impairment::default()
+        };
+        let network = MemoryNetwork::new();
+        let transport = ImpairedTransport::new(network.bind(SERVER).unwrap(), corrupt, 1);
+        let mut server = Server::with_transport(transport);
+        let mut client =
+            Client::with_transport(network.bind(([127, 0, 0, 1], 0)).unwrap(), SERVER.into());
+        client.request_protocol_version().unwrap();
+        server.poll().unwrap();
+        assert!(matches!(
+            client.try_recv(&mut buf),
+            Err(RecvError::Parse(_))
+        ));
+    }
+
+    /// Handshakes, subscribes to all controllers and receives one packet of controller data
+    /// over real sockets.
+    fn udp_round_trip(server: &mut Server, clients: &mut [Client]) {
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let settle = || Instant::now() + Duration::from_millis(100);
+        for client in clients.iter_mut() {
+            client.request_protocol_version().unwrap();
+        }
+        server.poll_until(settle()).unwrap();
+        for client in clients.iter_mut() {
+            let received = client.recv(&mut buf).unwrap();
+            assert!(matches!(received, MessageRef::ProtocolVersionInfo(_)));
+            client
+                .subscribe(Registration::AllControllers, 0, [0; 6])
+                .unwrap();
+        }
+        server.poll_until(settle()).unwrap();
+        assert_eq!(server.subscriber_count(), clients.len());
+        assert_eq!(server.push(&controller_data(0)).unwrap(), clients.len());
+        for client in clients.iter_mut() {
+            let received = client.recv(&mut buf).unwrap();
+            assert!(matches!(received, MessageRef::ControllerData(_)));
+        }
+    }
+
+    #[test]
+    fn ipv6_loopback() {
+        let mut server = Server::bind("[::1]:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        assert!(addr.is_ipv6());
+        let mut client = Client::connect(addr).unwrap();
+        assert!(client.socket().local_addr().unwrap().is_ipv6());
+        udp_round_trip(&mut server, std::slice::from_mut(&mut client));
+    }
+
+    #[test]
+    fn dual_stack() {
+        let mut server = Server::bind_any(0).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let mut clients = [
+            Client::connect(("127.0.0.1", port)).unwrap(),
+            Client::connect(("::1", port)).unwrap(),
+        ];
+        udp_round_trip(&mut server, &mut clients);
+    }
+
+    /// Receives batches until `count` messages from the server have arrived, and returns
+    /// their slots.
+    fn recv_slots<T: Transport>(client: &mut Client<T>, count: usize) -> Vec<u8> {
+        let mut batch = RecvBatch::new(8);
+        let mut slots = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while slots.len() < count && Instant::now() < deadline {
+            client.try_recv_batch(&mut batch).unwrap();
+            for (message, from) in batch.messages() {
+                assert_eq!(from, client.server());
+                match message {
+                    Ok(MessageRef::ControllerData(data)) => {
+                        slots.push(data.controller_header().slot())
+                    }
+                    _ => panic!("unexpected message"),
+                }
+            }
+        }
+        slots
+    }
+
+    #[test]
+    fn batches() {
+        let (network, mut server, mut client) = setup();
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        client.request_protocol_version().unwrap();
+        server.poll().unwrap();
+        assert!(client.try_recv(&mut buf).unwrap().is_some());
+        client
+            .subscribe(Registration::AllControllers, 0, [0; 6])
+            .unwrap();
+        server.poll().unwrap();
+
+        let mut stranger = network.bind(([127, 0, 0, 2], 26760)).unwrap();
+        let info = ProtocolVersionInfo::new(1, Protocol::Version1001, Crc32::new());
+        stranger
+            .send_to(&info.bytes, &client.transport().local_addr())
+            .unwrap();
+        let tick: Vec<_> = (0..4).map(controller_data).collect();
+        assert_eq!(server.push_many(&tick).unwrap(), 4);
+        assert_eq!(recv_slots(&mut client, 4), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn udp_batches() {
+        let mut server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut clients = [Client::connect(addr).unwrap(), Client::connect(addr).unwrap()];
+        udp_round_trip(&mut server, &mut clients);
+
+        let tick: Vec<_> = (0..4).map(controller_data).collect();
+        assert_eq!(server.push_many(&tick).unwrap(), 8);
+        for client in &mut clients {
+            assert_eq!(recv_slots(client, 4), [0, 1, 2, 3]);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_datagram() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = std::env::temp_dir().join(format!("dsu-protocol-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server_path = dir.join("server");
+        let client_path = dir.join("client");
+        let _ = std::fs::remove_file(&server_path);
+        let _ = std::fs::remove_file(&client_path);
+
+        let socket = UnixDatagram::bind(&server_path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut server = Server::with_transport(socket);
+        let socket = UnixDatagram::bind(&client_path).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut client = Client::with_transport(socket, server_path.clone());
+
+        client.request_protocol_version().unwrap();
+        server.poll().unwrap();
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let received = client.try_recv(&mut buf).unwrap();
+        assert!(matches!(received, Some(MessageRef::ProtocolVersionInfo(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
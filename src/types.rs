@@ -1,15 +1,18 @@
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Magic {
     Server,
     Client,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Protocol {
     Version1001,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageType {
     ProtocolVersionInfo,
     ControllerInfo,
@@ -17,6 +20,7 @@ pub enum MessageType {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum State {
     Disconnected,
     Reserved,
@@ -24,6 +28,7 @@ pub enum State {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Model {
     NotApplicable,
     PartialGyro,
@@ -32,6 +37,7 @@ pub enum Model {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConnectionType {
     NotApplicable,
     Usb,
@@ -39,6 +45,7 @@ pub enum ConnectionType {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BatteryStatus {
     NotApplicable,
     Dying,
@@ -51,13 +58,14 @@ pub enum BatteryStatus {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Registration {
     AllControllers,
     SlotBased,
     MacBased,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Buttons(pub(crate) [u8; 2]);
 
 impl Buttons {
@@ -68,9 +76,51 @@ impl Buttons {
     pub fn clear(&mut self) {
         self.0 = [0; 2];
     }
+
+    pub fn contains(&self, button: Button) -> bool {
+        let (bit, index) = button.bit_and_index();
+        self.0[index] & (1 << bit) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Button> + '_ {
+        Button::ALL.iter().copied().filter(move |&button| self.contains(button))
+    }
 }
 
-impl std::ops::BitOr<Button> for Buttons {
+/// Serialized as the list of pressed buttons.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Buttons {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Buttons {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ButtonsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ButtonsVisitor {
+            type Value = Buttons;
+
+            fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(f, "a list of buttons")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Buttons, A::Error> {
+                let mut buttons = Buttons::new();
+                while let Some(button) = seq.next_element::<Button>()? {
+                    buttons = buttons | button;
+                }
+                Ok(buttons)
+            }
+        }
+
+        deserializer.deserialize_seq(ButtonsVisitor)
+    }
+}
+
+impl core::ops::BitOr<Button> for Buttons {
     type Output = Buttons;
 
     fn bitor(mut self, rhs: Button) -> Buttons {
@@ -80,7 +130,8 @@ impl std::ops::BitOr<Button> for Buttons {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Button {
     Left,
     Down,
@@ -101,6 +152,25 @@ pub enum Button {
 }
 
 impl Button {
+    pub const ALL: [Button; 16] = [
+        Button::Left,
+        Button::Down,
+        Button::Right,
+        Button::Up,
+        Button::Start,
+        Button::RStick,
+        Button::LStick,
+        Button::Select,
+        Button::Y,
+        Button::B,
+        Button::A,
+        Button::X,
+        Button::R1,
+        Button::L1,
+        Button::R2,
+        Button::L2,
+    ];
+
     fn bit_and_index(&self) -> (u8, usize) {
         match self {
             Button::Left => (7, 0),
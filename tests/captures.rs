@@ -0,0 +1,124 @@
+//! DSU traffic captured from other implementations, checked against the crate's parser and
+//! encoder.
+//!
+//! Captures live in `tests/captures/` as pcap or pcapng files of traffic on the default DSU
+//! port, recorded with Wireshark or tcpdump while the implementation talked to a client or
+//! server. Every capture must be listed in [`CAPTURES`] with the implementation and version
+//! it came from, and what it talked to. Captures of this crate's own tools don't belong here,
+//! since they would only check the crate against itself. Together the captures must contain
+//! all six message types.
+//!
+//! No captures have been added yet, so [`captures_parse_and_rebuild`] is ignored until there
+//! are some, and the crate is so far only checked against the spec in `tests/spec.rs`.
+#![cfg(feature = "std")]
+
+use std::{ffi::OsStr, fs, fs::File, path::PathBuf};
+
+use dsu_protocol::{crc::Crc32, pcap, *};
+
+/// Capture file names in `tests/captures/`, and where each came from, e.g.
+/// `("ds4windows-3.3.3-cemu.pcapng", "DS4Windows 3.3.3 serving Cemu 2.0, one DS4 over USB")`.
+const CAPTURES: &[(&str, &str)] = &[];
+
+fn captures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/captures")
+}
+
+#[test]
+fn every_capture_names_its_source() {
+    let dir = captures_dir();
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries {
+            let path = entry.unwrap().path();
+            if !matches!(
+                path.extension().and_then(OsStr::to_str),
+                Some("pcap" | "pcapng")
+            ) {
+                continue;
+            }
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert!(
+                CAPTURES.iter().any(|(file, _)| *file == name),
+                "{} isn't listed in CAPTURES",
+                name
+            );
+        }
+    }
+    for (file, source) in CAPTURES {
+        assert!(dir.join(file).is_file(), "{} ({}) is missing", file, source);
+    }
+}
+
+#[test]
+#[ignore = "no captures from other implementations in tests/captures yet"]
+fn captures_parse_and_rebuild() {
+    let mut seen = Vec::new();
+    for (file, source) in CAPTURES {
+        let input = File::open(captures_dir().join(file)).unwrap();
+        let datagrams = pcap::read_dsu_datagrams(input).unwrap();
+        assert!(!datagrams.is_empty(), "no DSU traffic in {}", file);
+        for (index, datagram) in datagrams.iter().enumerate() {
+            let context = format!("datagram {} of {} ({})", index, file, source);
+            let message = datagram
+                .message()
+                .unwrap_or_else(|err| panic!("{}: {}", context, err));
+            check(&message, &datagram.payload, &context);
+            let kind = (message.header().magic().ok(), message.header().message_type().ok());
+            if !seen.contains(&kind) {
+                seen.push(kind);
+            }
+        }
+    }
+    assert_eq!(seen.len(), 6, "captures only contain {:?}", seen);
+}
+
+/// Checks that the fields of a captured message are ones the crate knows, and that the crate
+/// encodes the simpler messages byte for byte like the capture.
+fn check(message: &MessageRef, payload: &[u8], context: &str) {
+    let header = message.header();
+    assert!(header.magic().is_ok(), "{}: magic", context);
+    assert!(header.protocol().is_ok(), "{}: protocol", context);
+    let sender_id = header.sender_id();
+    match message {
+        MessageRef::RequestProtocolVersionInfo(_) => {
+            let built = RequestProtocolVersionInfo::new(sender_id, Crc32::new());
+            assert_eq!(&built.bytes[..], payload, "{}", context);
+        }
+        MessageRef::ProtocolVersionInfo(info) => {
+            let protocol = info.protocol().expect(context);
+            let built = ProtocolVersionInfo::new(sender_id, protocol, Crc32::new());
+            assert_eq!(&built.bytes[..], payload, "{}", context);
+        }
+        MessageRef::RequestControllerInfo(request) => {
+            assert!(request.slots().is_ok(), "{}: slots", context);
+        }
+        MessageRef::ControllerInfo(info) => check_controller(info.controller_header(), context),
+        MessageRef::RequestControllerData(request) => {
+            let registration = request.registration().expect(context);
+            let built = RequestControllerData::new(
+                sender_id,
+                registration,
+                request.slot(),
+                *request.mac(),
+                Crc32::new(),
+            );
+            assert_eq!(&built.bytes[..], payload, "{}", context);
+        }
+        MessageRef::ControllerData(data) => check_controller(data.controller_header(), context),
+    }
+}
+
+fn check_controller(controller: &ControllerHeader, context: &str) {
+    assert!(controller.state().is_ok(), "{}: state", context);
+    assert!(controller.model().is_ok(), "{}: model", context);
+    assert!(
+        controller.connection_type().is_ok(),
+        "{}: connection type",
+        context
+    );
+    assert!(
+        controller.battery_status().is_ok(),
+        "{}: battery status",
+        context
+    );
+}
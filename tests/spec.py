@@ -0,0 +1,95 @@
+#!/usr/bin/env python3
+"""Generates the packets in spec.rs.
+
+The packets are assembled field by field from the cemuhook protocol description, and
+CRCs come from zlib, so nothing here shares code with the crate. Run it and paste the
+output over the constants in spec.rs.
+"""
+
+import struct
+import zlib
+
+CLIENT = 0x43555344
+SERVER = 0x53555344
+PROTOCOL, INFO, DATA = 0x100000, 0x100001, 0x100002
+
+CLIENT_ID = 0x12345678
+SERVER_ID = 0x9ABCDEF0
+MAC = bytes([0x01, 0x23, 0x45, 0x67, 0x89, 0xAB])
+
+
+def packet(magic, sender_id, message_type, payload, length=None):
+    if length is None:
+        length = 4 + len(payload)
+    header = struct.pack("<IHHII", magic, 1001, length, 0, sender_id)
+    body = struct.pack("<I", message_type) + payload
+    crc = zlib.crc32(header + body)
+    return header[:8] + struct.pack("<I", crc) + header[12:] + body
+
+
+def controller_header(slot, state, model, connection, mac, battery):
+    return struct.pack("<BBBB", slot, state, model, connection) + mac + bytes([battery])
+
+
+def touch(active, touch_id, x, y):
+    return struct.pack("<BBHH", active, touch_id, x, y)
+
+
+VECTORS = {
+    "REQUEST_PROTOCOL_VERSION_INFO": packet(CLIENT, CLIENT_ID, PROTOCOL, b""),
+    "PROTOCOL_VERSION_INFO": packet(SERVER, SERVER_ID, PROTOCOL, struct.pack("<H", 1001)),
+    "REQUEST_CONTROLLER_INFO": packet(
+        CLIENT, CLIENT_ID, INFO, struct.pack("<i", 4) + bytes([0, 1, 2, 3])
+    ),
+    # Only the first slot byte counts, but the buffer is always 28 bytes.
+    "REQUEST_CONTROLLER_INFO_ONE_SLOT": packet(
+        CLIENT, CLIENT_ID, INFO, struct.pack("<i", 1) + bytes([2, 0, 0, 0]), length=9
+    ),
+    # Connected, full gyro, Bluetooth, high battery, and a trailing zero byte.
+    "CONTROLLER_INFO": packet(
+        SERVER, SERVER_ID, INFO, controller_header(1, 2, 2, 2, MAC, 0x04) + b"\0"
+    ),
+    "CONTROLLER_INFO_DISCONNECTED": packet(
+        SERVER, SERVER_ID, INFO, controller_header(3, 0, 0, 0, bytes(6), 0x00) + b"\0"
+    ),
+    "REQUEST_CONTROLLER_DATA_ALL": packet(
+        CLIENT, CLIENT_ID, DATA, bytes([0, 0]) + bytes(6)
+    ),
+    "REQUEST_CONTROLLER_DATA_SLOT": packet(
+        CLIENT, CLIENT_ID, DATA, bytes([1, 1]) + bytes(6)
+    ),
+    "REQUEST_CONTROLLER_DATA_MAC": packet(CLIENT, CLIENT_ID, DATA, bytes([2, 0]) + MAC),
+    "CONTROLLER_DATA": packet(
+        SERVER,
+        SERVER_ID,
+        DATA,
+        controller_header(1, 2, 2, 2, MAC, 0xEE)
+        + b"\x01"  # connected
+        + struct.pack("<I", 1234)  # packet number
+        # Select and Left, then A and R2.
+        + bytes([0b1000_0001, 0b0010_0010])
+        + bytes([1, 0])  # PS, touch button
+        + bytes([128, 64, 200, 30])  # sticks
+        + bytes([255, 0, 0, 0])  # analog d-pad left, down, right, up
+        + bytes([0, 0, 200, 0])  # analog Y, B, A, X
+        + bytes([0, 0, 100, 0])  # analog R1, L1, R2, L2
+        + touch(1, 3, 960, 471)
+        + touch(0, 0, 0, 0)
+        + struct.pack("<Q", 123456789)
+        + struct.pack("<fff", 0.5, -1.0, 0.25)
+        + struct.pack("<fff", 10.0, -20.5, 3.125),
+    ),
+}
+
+
+def main():
+    for name, data in VECTORS.items():
+        print(f"const {name}: [u8; {len(data)}] = [")
+        for i in range(0, len(data), 12):
+            print("    " + " ".join(f"0x{b:02X}," for b in data[i : i + 12]))
+        print("];")
+        print()
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,384 @@
+//! Byte-exact packets for every message type, assembled from the protocol description.
+//!
+//! `spec.py` builds the packets field by field and computes CRCs with zlib rather than the
+//! crate's `Crc32`, so these check the crate against an independent reading of the
+//! description, not against another implementation. That's what the captures checked by
+//! `captures.rs` are for. `ControllerInfo` ends with a zero byte, as DS4Windows sends it.
+
+use core::hash::Hasher;
+
+use dsu_protocol::{crc::Crc32, types::*, *};
+
+const REQUEST_PROTOCOL_VERSION_INFO: [u8; 20] = [
+    0x44, 0x53, 0x55, 0x43, 0xE9, 0x03, 0x04, 0x00, 0xC9, 0xBC, 0x7B, 0xB7, 0x78, 0x56, 0x34, 0x12,
+    0x00, 0x00, 0x10, 0x00,
+];
+
+const PROTOCOL_VERSION_INFO: [u8; 22] = [
+    0x44, 0x53, 0x55, 0x53, 0xE9, 0x03, 0x06, 0x00, 0x0A, 0xE0, 0x19, 0x08, 0xF0, 0xDE, 0xBC, 0x9A,
+    0x00, 0x00, 0x10, 0x00, 0xE9, 0x03,
+];
+
+const REQUEST_CONTROLLER_INFO: [u8; 28] = [
+    0x44, 0x53, 0x55, 0x43, 0xE9, 0x03, 0x0C, 0x00, 0x3D, 0xB2, 0x4D, 0x28, 0x78, 0x56, 0x34, 0x12,
+    0x01, 0x00, 0x10, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03,
+];
+
+const REQUEST_CONTROLLER_INFO_ONE_SLOT: [u8; 28] = [
+    0x44, 0x53, 0x55, 0x43, 0xE9, 0x03, 0x09, 0x00, 0x1D, 0xDA, 0xD0, 0x2B, 0x78, 0x56, 0x34, 0x12,
+    0x01, 0x00, 0x10, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+];
+
+const CONTROLLER_INFO: [u8; 32] = [
+    0x44, 0x53, 0x55, 0x53, 0xE9, 0x03, 0x10, 0x00, 0x2F, 0xC5, 0x1D, 0x67, 0xF0, 0xDE, 0xBC, 0x9A,
+    0x01, 0x00, 0x10, 0x00, 0x01, 0x02, 0x02, 0x02, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0x04, 0x00,
+];
+
+const CONTROLLER_INFO_DISCONNECTED: [u8; 32] = [
+    0x44, 0x53, 0x55, 0x53, 0xE9, 0x03, 0x10, 0x00, 0xE3, 0x87, 0x21, 0x9E, 0xF0, 0xDE, 0xBC, 0x9A,
+    0x01, 0x00, 0x10, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const REQUEST_CONTROLLER_DATA_ALL: [u8; 28] = [
+    0x44, 0x53, 0x55, 0x43, 0xE9, 0x03, 0x0C, 0x00, 0x38, 0x37, 0x64, 0x71, 0x78, 0x56, 0x34, 0x12,
+    0x02, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const REQUEST_CONTROLLER_DATA_SLOT: [u8; 28] = [
+    0x44, 0x53, 0x55, 0x43, 0xE9, 0x03, 0x0C, 0x00, 0x12, 0x3C, 0xB9, 0x1B, 0x78, 0x56, 0x34, 0x12,
+    0x02, 0x00, 0x10, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+const REQUEST_CONTROLLER_DATA_MAC: [u8; 28] = [
+    0x44, 0x53, 0x55, 0x43, 0xE9, 0x03, 0x0C, 0x00, 0xFC, 0x9A, 0x6B, 0x34, 0x78, 0x56, 0x34, 0x12,
+    0x02, 0x00, 0x10, 0x00, 0x02, 0x00, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB,
+];
+
+const CONTROLLER_DATA: [u8; 100] = [
+    0x44, 0x53, 0x55, 0x53, 0xE9, 0x03, 0x54, 0x00, 0xEF, 0x8B, 0xC2, 0x14, 0xF0, 0xDE, 0xBC, 0x9A,
+    0x02, 0x00, 0x10, 0x00, 0x01, 0x02, 0x02, 0x02, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xEE, 0x01,
+    0xD2, 0x04, 0x00, 0x00, 0x81, 0x22, 0x01, 0x00, 0x80, 0x40, 0xC8, 0x1E, 0xFF, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0xC8, 0x00, 0x00, 0x00, 0x64, 0x00, 0x01, 0x03, 0xC0, 0x03, 0xD7, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x15, 0xCD, 0x5B, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3F,
+    0x00, 0x00, 0x80, 0xBF, 0x00, 0x00, 0x80, 0x3E, 0x00, 0x00, 0x20, 0x41, 0x00, 0x00, 0xA4, 0xC1,
+    0x00, 0x00, 0x48, 0x40,
+];
+
+const CLIENT_ID: u32 = 0x12345678;
+const SERVER_ID: u32 = 0x9ABCDEF0;
+const MAC: [u8; 6] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB];
+
+#[test]
+fn crc_check_value() {
+    let mut crc = Crc32::new();
+    crc.write(b"123456789");
+    assert_eq!(crc.finish(), 0xCBF43926);
+}
+
+#[test]
+fn request_protocol_version_info() {
+    let built = RequestProtocolVersionInfo::new(CLIENT_ID, Crc32::new());
+    assert_eq!(built.bytes, REQUEST_PROTOCOL_VERSION_INFO);
+
+    match MessageRef::parse(&REQUEST_PROTOCOL_VERSION_INFO, Crc32::new()).unwrap() {
+        MessageRef::RequestProtocolVersionInfo(request) => {
+            check_header(request.header(), Magic::Client, 4, CLIENT_ID);
+            assert_eq!(
+                request.header().message_type().ok(),
+                Some(MessageType::ProtocolVersionInfo)
+            );
+        }
+        _ => panic!("wrong message type"),
+    }
+}
+
+#[test]
+fn protocol_version_info() {
+    let built = ProtocolVersionInfo::new(SERVER_ID, Protocol::Version1001, Crc32::new());
+    assert_eq!(built.bytes, PROTOCOL_VERSION_INFO);
+
+    match MessageRef::parse(&PROTOCOL_VERSION_INFO, Crc32::new()).unwrap() {
+        MessageRef::ProtocolVersionInfo(info) => {
+            check_header(info.header(), Magic::Server, 6, SERVER_ID);
+            assert_eq!(info.protocol().ok(), Some(Protocol::Version1001));
+        }
+        _ => panic!("wrong message type"),
+    }
+}
+
+#[test]
+fn request_controller_info() {
+    let built = RequestControllerInfo::new(CLIENT_ID, &[0, 1, 2, 3], Crc32::new()).unwrap();
+    assert_eq!(built.bytes, REQUEST_CONTROLLER_INFO);
+    let built = RequestControllerInfo::new(CLIENT_ID, &[2], Crc32::new()).unwrap();
+    assert_eq!(built.bytes, REQUEST_CONTROLLER_INFO_ONE_SLOT);
+
+    match MessageRef::parse(&REQUEST_CONTROLLER_INFO, Crc32::new()).unwrap() {
+        MessageRef::RequestControllerInfo(request) => {
+            check_header(request.header(), Magic::Client, 12, CLIENT_ID);
+            assert_eq!(request.slots().unwrap(), &[0, 1, 2, 3]);
+        }
+        _ => panic!("wrong message type"),
+    }
+    match MessageRef::parse(&REQUEST_CONTROLLER_INFO_ONE_SLOT, Crc32::new()).unwrap() {
+        MessageRef::RequestControllerInfo(request) => {
+            check_header(request.header(), Magic::Client, 9, CLIENT_ID);
+            assert_eq!(request.slots().unwrap(), &[2]);
+        }
+        _ => panic!("wrong message type"),
+    }
+}
+
+/// `initialize` takes the length from the slots it's given, not whatever the buffer held, and
+/// marks the request as a controller info request.
+#[test]
+fn request_controller_info_initialize() {
+    let mut request = RequestControllerInfo::new(CLIENT_ID, &[0, 1, 2, 3], Crc32::new()).unwrap();
+    request.initialize(CLIENT_ID, &[2], Crc32::new()).unwrap();
+    assert_eq!(request.header().packet_length(), 9);
+    assert_eq!(
+        request.header().message_type().ok(),
+        Some(MessageType::ControllerInfo)
+    );
+    assert_eq!(request.slots().unwrap(), &[2]);
+    assert!(MessageRef::parse(&request.bytes, Crc32::new()).is_ok());
+
+    let mut request = RequestControllerInfo { bytes: [0xFF; 28] };
+    assert!(request.initialize(CLIENT_ID, &[], Crc32::new()).is_err());
+    assert!(request.initialize(CLIENT_ID, &[0; 5], Crc32::new()).is_err());
+    request.initialize(CLIENT_ID, &[0, 1], Crc32::new()).unwrap();
+    assert_eq!(request.header().packet_length(), 10);
+    assert_eq!(request.slots().unwrap(), &[0, 1]);
+}
+
+#[test]
+fn bad_slot_counts_keep_the_header() {
+    let mut request = RequestControllerInfo::new(CLIENT_ID, &[0], Crc32::new()).unwrap();
+    request.bytes[20] = 9;
+    request.update_crc(Crc32::new());
+    let err = MessageRef::parse(&request.bytes, Crc32::new()).err().unwrap();
+    assert_eq!(err.code(), "invalid_slots_length");
+    assert_eq!(err.header().map(Header::sender_id), Some(CLIENT_ID));
+    assert_eq!(
+        err.message(),
+        Some((Magic::Client, MessageType::ControllerInfo))
+    );
+    assert!(MessageMut::parse_mut(&mut request.bytes, Crc32::new()).is_err());
+}
+
+#[test]
+fn controller_info() {
+    let built = ControllerInfo::new(
+        SERVER_ID,
+        1,
+        State::Connected,
+        Model::FullGyro,
+        ConnectionType::Bluetooth,
+        MAC,
+        BatteryStatus::High,
+        Crc32::new(),
+    );
+    assert_eq!(built.bytes, CONTROLLER_INFO);
+    let built = ControllerInfo::new(
+        SERVER_ID,
+        3,
+        State::Disconnected,
+        Model::NotApplicable,
+        ConnectionType::NotApplicable,
+        [0; 6],
+        BatteryStatus::NotApplicable,
+        Crc32::new(),
+    );
+    assert_eq!(built.bytes, CONTROLLER_INFO_DISCONNECTED);
+
+    match MessageRef::parse(&CONTROLLER_INFO, Crc32::new()).unwrap() {
+        MessageRef::ControllerInfo(info) => {
+            check_header(info.header(), Magic::Server, 16, SERVER_ID);
+            let controller = info.controller_header();
+            assert_eq!(controller.slot(), 1);
+            assert_eq!(controller.state().ok(), Some(State::Connected));
+            assert_eq!(controller.model().ok(), Some(Model::FullGyro));
+            assert_eq!(
+                controller.connection_type().ok(),
+                Some(ConnectionType::Bluetooth)
+            );
+            assert_eq!(controller.mac(), &MAC);
+            assert_eq!(controller.battery_status().ok(), Some(BatteryStatus::High));
+        }
+        _ => panic!("wrong message type"),
+    }
+}
+
+#[test]
+fn request_controller_data() {
+    for (registration, slot, mac, expected) in [
+        (
+            Registration::AllControllers,
+            0,
+            [0; 6],
+            &REQUEST_CONTROLLER_DATA_ALL,
+        ),
+        (
+            Registration::SlotBased,
+            1,
+            [0; 6],
+            &REQUEST_CONTROLLER_DATA_SLOT,
+        ),
+        (Registration::MacBased, 0, MAC, &REQUEST_CONTROLLER_DATA_MAC),
+    ] {
+        let built = RequestControllerData::new(CLIENT_ID, registration, slot, mac, Crc32::new());
+        assert_eq!(&built.bytes, expected);
+
+        match MessageRef::parse(expected, Crc32::new()).unwrap() {
+            MessageRef::RequestControllerData(request) => {
+                check_header(request.header(), Magic::Client, 12, CLIENT_ID);
+                assert_eq!(request.registration().ok(), Some(registration));
+                assert_eq!(request.slot(), slot);
+                assert_eq!(request.mac(), &mac);
+            }
+            _ => panic!("wrong message type"),
+        }
+    }
+}
+
+#[test]
+fn controller_data() {
+    let mut built = ControllerData::new(
+        SERVER_ID,
+        1,
+        State::Connected,
+        Model::FullGyro,
+        ConnectionType::Bluetooth,
+        MAC,
+        BatteryStatus::Charging,
+        true,
+        Crc32::new(),
+    );
+    built.set_packet_number(1234);
+    built.set_buttons(Buttons::new() | Button::Select | Button::Left | Button::A | Button::R2);
+    built.set_ps_button(1);
+    built.set_left_stick_x(128);
+    built.set_left_stick_y(64);
+    built.set_right_stick_x(200);
+    built.set_right_stick_y(30);
+    built.set_analog_dpad_left(255);
+    built.set_analog_a(200);
+    built.set_analog_r2(100);
+    built.touch1_mut().set_active(true);
+    built.touch1_mut().set_touch_id(3);
+    built.touch1_mut().set_touch_x(960);
+    built.touch1_mut().set_touch_y(471);
+    built.set_motion_timestamp(123456789);
+    built.set_accel_x(0.5);
+    built.set_accel_y(-1.0);
+    built.set_accel_z(0.25);
+    built.set_gyro_pitch(10.0);
+    built.set_gyro_yaw(-20.5);
+    built.set_gyro_roll(3.125);
+    built.update_crc(Crc32::new());
+    assert_eq!(built.bytes, CONTROLLER_DATA);
+
+    let data = match MessageRef::parse(&CONTROLLER_DATA, Crc32::new()).unwrap() {
+        MessageRef::ControllerData(data) => data,
+        _ => panic!("wrong message type"),
+    };
+    check_header(data.header(), Magic::Server, 84, SERVER_ID);
+    assert_eq!(data.controller_header().slot(), 1);
+    assert_eq!(
+        data.controller_header().battery_status().ok(),
+        Some(BatteryStatus::Charging)
+    );
+    assert!(data.is_connected());
+    assert_eq!(data.packet_number(), 1234);
+    assert_eq!(
+        data.buttons().iter().collect::<Vec<_>>(),
+        [Button::Left, Button::Select, Button::A, Button::R2]
+    );
+    assert_eq!(data.ps_button(), 1);
+    assert_eq!(data.touch_button(), 0);
+    assert_eq!(
+        [
+            data.left_stick_x(),
+            data.left_stick_y(),
+            data.right_stick_x(),
+            data.right_stick_y()
+        ],
+        [128, 64, 200, 30]
+    );
+    assert_eq!(data.analog_dpad_left(), 255);
+    assert_eq!(data.analog_a(), 200);
+    assert_eq!(data.analog_r2(), 100);
+    assert!(data.touch1().is_active());
+    assert_eq!(data.touch1().touch_id(), 3);
+    assert_eq!(
+        (data.touch1().touch_x(), data.touch1().touch_y()),
+        (960, 471)
+    );
+    assert!(!data.touch2().is_active());
+    assert_eq!(data.motion_timestamp(), 123456789);
+    assert_eq!(
+        [data.accel_x(), data.accel_y(), data.accel_z()],
+        [0.5, -1.0, 0.25]
+    );
+    assert_eq!(
+        [data.gyro_pitch(), data.gyro_yaw(), data.gyro_roll()],
+        [10.0, -20.5, 3.125]
+    );
+}
+
+const VECTORS: [&[u8]; 10] = [
+    &REQUEST_PROTOCOL_VERSION_INFO,
+    &PROTOCOL_VERSION_INFO,
+    &REQUEST_CONTROLLER_INFO,
+    &REQUEST_CONTROLLER_INFO_ONE_SLOT,
+    &CONTROLLER_INFO,
+    &CONTROLLER_INFO_DISCONNECTED,
+    &REQUEST_CONTROLLER_DATA_ALL,
+    &REQUEST_CONTROLLER_DATA_SLOT,
+    &REQUEST_CONTROLLER_DATA_MAC,
+    &CONTROLLER_DATA,
+];
+
+#[test]
+fn any_flipped_bit_is_rejected() {
+    for vector in VECTORS {
+        let mut bytes = vector.to_vec();
+        for bit in 0..bytes.len() * 8 {
+            bytes[bit / 8] ^= 1 << (bit % 8);
+            assert!(MessageRef::parse(&bytes, Crc32::new()).is_err());
+            bytes[bit / 8] ^= 1 << (bit % 8);
+        }
+        assert!(MessageRef::parse(&bytes, Crc32::new()).is_ok());
+    }
+}
+
+#[test]
+fn wrong_lengths_are_rejected() {
+    for vector in VECTORS {
+        for len in 0..vector.len() {
+            assert!(matches!(
+                MessageRef::parse(&vector[..len], Crc32::new()),
+                Err(error::MessageParseError::SliceTooSmall { .. })
+            ));
+            let mut copy = vector[..len].to_vec();
+            assert!(MessageMut::parse_mut(&mut copy, Crc32::new()).is_err());
+        }
+        let mut long = vector.to_vec();
+        long.push(0);
+        let err = MessageRef::parse(&long, Crc32::new()).err().unwrap();
+        assert!(matches!(err, error::MessageParseError::SliceTooLarge { .. }));
+        assert_eq!(err.offset(), vector.len());
+        assert!(err.header().is_some());
+        assert!(matches!(
+            MessageMut::parse_mut(&mut long, Crc32::new()),
+            Err(error::MessageParseError::SliceTooLarge { .. })
+        ));
+    }
+}
+
+fn check_header(header: &Header, magic: Magic, packet_length: u16, sender_id: u32) {
+    assert_eq!(header.magic().ok(), Some(magic));
+    assert_eq!(header.protocol().ok(), Some(Protocol::Version1001));
+    assert_eq!(header.packet_length(), packet_length);
+    assert_eq!(header.sender_id(), sender_id);
+}